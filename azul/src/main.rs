@@ -1,13 +1,31 @@
-use game::{GameState, Player, NUM_PLAYERS};
+use game::match_::ConsoleObserver;
+use game::{GameState, Player, Ruleset, TimeControl, MAX_PLAYERS, MIN_PLAYERS, PERFT_REFERENCES};
 use player::{
     command_line_player::HumanCommandLinePlayer,
     greedy_player::GreedyPlayer,
     mcts::{HeuristicMoveGenerationPlayer, MonteCarloTreeSearch},
     random_player::RandomPlayer,
 };
-use rand::{rngs::SmallRng, SeedableRng as _};
+use rand::{rngs::SmallRng, Rng as _, SeedableRng as _};
 
-async fn configure_players() -> Vec<Box<dyn Player>> {
+fn select_number_of_players() -> usize {
+    loop {
+        println!(
+            "Enter the number of players ({}-{}):",
+            MIN_PLAYERS, MAX_PLAYERS
+        );
+        let mut string = String::new();
+        std::io::stdin().read_line(&mut string).unwrap();
+        match string.trim().parse::<usize>() {
+            Ok(num_players) if (MIN_PLAYERS..=MAX_PLAYERS).contains(&num_players) => {
+                return num_players
+            }
+            _ => println!("Invalid number of players"),
+        }
+    }
+}
+
+async fn configure_players(num_players: usize) -> Vec<Box<dyn Player>> {
     let mut players = Vec::new();
 
     loop {
@@ -57,7 +75,7 @@ async fn configure_players() -> Vec<Box<dyn Player>> {
         }
 
         players.push(player);
-        if players.len() == NUM_PLAYERS {
+        if players.len() == num_players {
             break;
         }
     }
@@ -136,13 +154,88 @@ fn display_rules() {
               line of 5 tiles on their wall. Players tally their final scores, including any \
               end-of-game bonuses. The player with the highest score wins."
     );
-    let example_game_state = GameState::new(&mut SmallRng::from_entropy());
+    let example_game_state = GameState::new(MIN_PLAYERS, &mut SmallRng::from_entropy());
     println!("\n\x1b[1mExample Game State\x1b[0m");
     println!("{}", example_game_state);
 }
 
+fn run_perft_references() -> bool {
+    let mut all_passed = true;
+    for reference in PERFT_REFERENCES.iter() {
+        let start_time = std::time::Instant::now();
+        let nodes = reference
+            .game_state()
+            .perft(reference.depth, reference.seed);
+        let passed = nodes == reference.nodes;
+        all_passed &= passed;
+        println!(
+            "{} {}, depth {}: {} nodes, expected {} ({}ms)",
+            if passed { "ok    " } else { "FAILED" },
+            reference.name,
+            reference.depth,
+            nodes,
+            reference.nodes,
+            start_time.elapsed().as_millis()
+        );
+    }
+    all_passed
+}
+
+// azul perft <depth> [seed] [position]: Counts the leaf nodes for every move of the position (a new two player game by default)
+fn run_perft_divide(args: &[String]) -> Result<(), String> {
+    let depth = match args.first().map(|depth| depth.parse::<usize>()) {
+        Some(Ok(depth)) if depth > 0 => depth,
+        _ => return Err("Expected a depth of at least 1".to_string()),
+    };
+    let seed = match args.get(1) {
+        Some(seed) => seed
+            .parse::<u64>()
+            .map_err(|_| format!("Invalid seed '{}'", seed))?,
+        None => 0,
+    };
+    let mut game_state = match args.get(2) {
+        Some(position) => GameState::parse_position(position).map_err(|err| err.to_string())?,
+        None => GameState::new_without_factories(MIN_PLAYERS, Ruleset::default()),
+    };
+
+    let start_time = std::time::Instant::now();
+    let divide = game_state.perft_divide(depth, seed);
+    for (mov, nodes) in divide.iter() {
+        println!("{}: {}", mov, nodes);
+    }
+    let nodes: u64 = divide.iter().map(|(_, nodes)| nodes).sum();
+    let elapsed = start_time.elapsed().as_secs_f64();
+    println!("\nMoves: {}", divide.len());
+    println!("Nodes: {}", nodes);
+    println!(
+        "Time: {:.3}s ({:.0} nodes/s)",
+        elapsed,
+        nodes as f64 / elapsed.max(1e-9)
+    );
+    Ok(())
+}
+
+fn run_perft(args: &[String]) {
+    if args.first().map(String::as_str) == Some("--check") {
+        if !run_perft_references() {
+            std::process::exit(1);
+        }
+    } else if let Err(err) = run_perft_divide(args) {
+        println!("{}", err);
+        println!("Usage: azul perft <depth> [seed] [position]");
+        println!("       azul perft --check");
+        std::process::exit(1);
+    }
+}
+
 #[tokio::main]
 async fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("perft") {
+        run_perft(&args[2..]);
+        return;
+    }
+
     loop {
         println!("1: Start new game");
         println!("2: Display rules");
@@ -156,9 +249,19 @@ async fn main() {
         let mut rng = SmallRng::from_entropy();
         match read_line().trim() {
             "1" => {
-                let mut players = configure_players().await;
-                let game_state = GameState::new(&mut rng);
-                if let Err(err) = game::match_::run_match(game_state, &mut players, true).await {
+                let num_players = select_number_of_players();
+                let mut players = configure_players(num_players).await;
+                let game_state = GameState::new_without_factories(num_players, Ruleset::default());
+                let seed = rng.gen();
+                if let Err(err) = game::match_::run_match(
+                    game_state,
+                    &mut players,
+                    seed,
+                    TimeControl::Unlimited,
+                    &mut [&mut ConsoleObserver::default()],
+                )
+                .await
+                {
                     println!("Error: {:?}", err);
                 }
             }
@@ -1,8 +1,6 @@
 use crate::websocket_api::{EventType, WebSocketConnection, WebSocketMessage};
-use game::{
-    display_gamestate, GameState, MoveGenerationResult, MoveList, Player, SharedState, TileColor,
-    CENTER_FACTORY_INDEX, FLOOR_LINE_PENALTY, NUM_PLAYERS, NUM_TILE_COLORS,
-};
+use game::match_::{ConsoleObserver, MatchDriver, MatchObserver, MatchResult, MatchStatistcs};
+use game::{GameError, GameState, Move, Player, ScoreReport, SharedState, TimeControl};
 use rand::{rngs::SmallRng, SeedableRng};
 use std::collections::HashMap;
 use uuid::Uuid;
@@ -22,18 +20,20 @@ pub struct Match {
     game_state: GameState,
     players: Vec<Box<dyn Player>>,
     state: MatchState,
+    result: Option<MatchResult>,
 }
 
 impl Match {
     pub async fn new_with_players(players: Vec<Box<dyn Player>>) -> SharedState<Match> {
         let mut rng = SmallRng::from_entropy();
-        let game_state = GameState::new(&mut rng);
+        let game_state = GameState::new(players.len(), &mut rng);
         let id = Uuid::new_v4().to_string();
         let game_manager = Self {
             id: id.clone(),
             game_state,
             players,
             state: MatchState::NotStarted,
+            result: None,
         };
         let shared_state = SharedState::new(game_manager);
         let mut all_games = MATCHES.lock().await;
@@ -58,73 +58,107 @@ impl Match {
         &self.id
     }
 
+    // Scores and placements after the official tie-break, None until the game is over
+    pub fn get_result(&self) -> Option<MatchResult> {
+        match self.state {
+            MatchState::GameOver => self.result.clone(),
+            MatchState::NotStarted => None,
+        }
+    }
+
     pub async fn start_match(&mut self, websocket: WebSocketConnection) {
-        let player_names: Vec<String> = self.get_player_names().await;
-        let game_state = &mut self.game_state;
-        let mut move_list = MoveList::default();
-        let mut rng = SmallRng::from_entropy();
-        let mut round = 0;
-        loop {
-            game_state.check_integrity().unwrap(); // Check the integrity of the game state. If it is not valid, panic and crash the tokio task
-            send_game_state_update(game_state, &websocket); // Send the game state to the players
-            let mut turn = 0;
-            println!("{}", display_gamestate(game_state, Some(&player_names)));
-            let mut is_game_over;
-            loop {
-                is_game_over = matches!(
-                    game_state.get_possible_moves(&mut move_list, &mut rng),
-                    MoveGenerationResult::GameOver
-                );
-                if is_game_over {
-                    // If there are no legal moves we end the game
-                    break;
-                }
-
-                // Get the move from the current player
-                let current_player = game_state.get_current_player();
-                let current_player = usize::from(current_player);
-                log::info!(
-                    "Round {}, turn {}, player {}",
-                    round,
-                    turn,
-                    self.players[current_player].get_name()
-                );
-                send_game_state_update(game_state, &websocket);
-                let move_ = self.players[current_player].get_move(game_state).await;
-
-                // Validate the move
-                if !move_list.contains(move_) {
-                    // If the move is not legal, panic and crash the tokio task
-                    panic!(
-                        "Player {} made an illegal move: {:?}",
-                        current_player, move_
-                    );
-                }
-
-                // Apply the move to the game state
-                game_state.do_move(move_);
-                println!("{}", display_gamestate(game_state, Some(&player_names)));
-
-                send_game_state_update(game_state, &websocket);
-
-                // Check integrity of the game state after the move
-                game_state.check_integrity().unwrap();
-
-                turn += 1;
-            }
-            // At the end of the round, evaluate it by counting the points and moving the first player marker
-            send_game_state_update(game_state, &websocket);
-            println!("{}", display_gamestate(game_state, Some(&player_names)));
-            if is_game_over {
+        let mut websocket_observer = WebSocketObserver {
+            websocket: websocket.clone(),
+        };
+        let mut console_observer = ConsoleObserver::default();
+        // The match stops if the client disconnects, e.g. while a human player is asked for a move
+        let driver =
+            MatchDriver::new(TimeControl::Unlimited).with_cancel_token(websocket.closed_token());
+        let result = driver
+            .run(
+                self.game_state.clone(),
+                &mut self.players,
+                rand::random(),
+                &mut [&mut websocket_observer, &mut console_observer],
+            )
+            .await;
+        match result {
+            Ok(stats) => {
+                self.result = Some(stats.result);
                 self.state = MatchState::GameOver;
-                break;
             }
-            round += 1;
+            Err(error) => log::error!("Match {} stopped: {}", self.id, error),
         }
-        send_game_state_update(game_state, &websocket);
     }
 }
 
+// Streams the match to the client
+struct WebSocketObserver {
+    websocket: WebSocketConnection,
+}
+
+impl MatchObserver for WebSocketObserver {
+    fn on_match_start(&mut self, game_state: &GameState, _player_names: &[String]) {
+        send_game_state_update(game_state, &self.websocket);
+    }
+
+    fn on_round_end(&mut self, _game_state: &GameState, reports: &[ScoreReport]) {
+        send_score_reports(reports, &self.websocket);
+    }
+
+    fn on_refill(&mut self, game_state: &GameState) {
+        send_game_state_update(game_state, &self.websocket);
+    }
+
+    fn on_move(
+        &mut self,
+        _previous_game_state: &GameState,
+        game_state: &GameState,
+        player: usize,
+        move_: Move,
+        response_time: u64,
+    ) {
+        log::info!(
+            "Player {} played {} in {}ms",
+            player + 1,
+            move_,
+            response_time
+        );
+        send_game_state_update(game_state, &self.websocket);
+    }
+
+    fn on_integrity_failure(&mut self, game_state: &GameState, error: &GameError) {
+        send_integrity_violations(game_state, error, &self.websocket);
+    }
+
+    fn on_game_over(&mut self, game_state: &GameState, _stats: &MatchStatistcs) {
+        send_game_state_update(game_state, &self.websocket);
+    }
+}
+
+// Logs every violated invariant of the game state and sends them to the client
+fn send_integrity_violations(
+    game_state: &GameState,
+    error: &GameError,
+    websocket: &WebSocketConnection,
+) {
+    let violations = match error {
+        GameError::InvalidGameState(violations) => violations.clone(),
+        _ => game_state.integrity_violations(),
+    };
+    for violation in &violations {
+        log::error!("Invalid game state: {}", violation);
+    }
+    log::error!("{}", game_state.to_position_string());
+    websocket.send_message(WebSocketMessage {
+        event_type: EventType::Error,
+        data: serde_json::json!({
+            "error": "Invalid game state",
+            "violations": violations.iter().map(|violation| violation.to_string()).collect::<Vec<_>>(),
+        }),
+    });
+}
+
 pub fn send_game_state_update(game_state: &GameState, websocket: &WebSocketConnection) {
     log::info!("Sending game state update to {}", websocket.get_address());
     let json = game_state_to_json(game_state);
@@ -135,94 +169,52 @@ pub fn send_game_state_update(game_state: &GameState, websocket: &WebSocketConne
     websocket.send_message(message);
 }
 
-pub fn game_state_to_json(game_state: &GameState) -> serde_json::Value {
-    let mut players = Vec::new();
-
-    let floor_lines = game_state.get_floor_line_progress();
-    let scores = game_state.get_scores();
-    let walls = game_state.get_walls();
-    let pattern_line_occupancy = game_state.get_pattern_lines_occupancy();
-    let pattern_line_colors = game_state.get_pattern_lines_colors();
-    for player in 0..NUM_PLAYERS {
-        let player_json = serde_json::json!({
-            "floor_line_progress": floor_lines[player],
-            "floor_line_penalty": FLOOR_LINE_PENALTY[floor_lines[player].min(FLOOR_LINE_PENALTY.len() as u8 - 1) as usize],
-            "score": scores[player],
-            "wall": wall_to_json(&walls[player]),
-            "pattern": pattern_lines_to_json(&pattern_line_occupancy[player], &pattern_line_colors[player]),
-        });
-        players.push(player_json);
-    }
+pub fn send_score_reports(reports: &[ScoreReport], websocket: &WebSocketConnection) {
+    log::info!("Sending score reports to {}", websocket.get_address());
+    let reports = reports.iter().map(score_report_to_json).collect::<Vec<_>>();
+    let message = WebSocketMessage {
+        event_type: EventType::ScoreReport,
+        data: serde_json::json!({ "players": reports }),
+    };
+    websocket.send_message(message);
+}
 
-    let factories = game_state.get_factories();
-    let mut factories_json = Vec::new();
-    for (factory_index, factory) in factories.iter().enumerate() {
-        let mut factory_json = Vec::new();
-        for (color_index, number) in factory.iter().enumerate() {
-            let color = TileColor::from(color_index);
-            let color: char = color.into();
-            factory_json.push(serde_json::json!({
+pub fn score_report_to_json(report: &ScoreReport) -> serde_json::Value {
+    let placements = report
+        .placements
+        .iter()
+        .map(|placement| {
+            let color: char = placement.color.into();
+            serde_json::json!({
+                "row": placement.row,
+                "col": placement.column,
                 "color": color,
-                "number_of_tiles": number,
-            }));
-        }
-        let is_center = factory_index == CENTER_FACTORY_INDEX;
-        factories_json.push(serde_json::json!({
-            "is_center": is_center,
-            "tiles": factory_json,
-        }));
-    }
-
-    // Create new json object
-    let ret = serde_json::json!({
-        "bag": game_state.get_bag(),
-        "factories": factories_json,
-        "players": players,
-        "current_player": usize::from(game_state.get_current_player()),
-        "next_round_starting_player": usize::from(game_state.get_next_round_starting_player()),
+                "row_points": placement.row_points,
+                "column_points": placement.column_points,
+                "points": placement.points(),
+            })
+        })
+        .collect::<Vec<_>>();
+    let end_of_game = report.end_of_game.map(|bonus| {
+        serde_json::json!({
+            "complete_rows": bonus.complete_rows,
+            "row_bonus": bonus.row_bonus(),
+            "complete_columns": bonus.complete_columns,
+            "column_bonus": bonus.column_bonus(),
+            "complete_colors": bonus.complete_colors,
+            "color_bonus": bonus.color_bonus(),
+        })
     });
-    ret
+    serde_json::json!({
+        "placements": placements,
+        "floor_tiles": report.floor_tiles,
+        "floor_penalty": report.floor_penalty,
+        "end_of_game": end_of_game,
+        "total": report.total(),
+    })
 }
 
-pub fn wall_to_json(wall: &[u32; NUM_TILE_COLORS]) -> Vec<serde_json::Value> {
-    let mut wall_tiles = Vec::new();
-    for row in 0..5 {
-        for col in 0..5 {
-            let mut color = ' ';
-            for (c, wall_bitboard) in wall.iter().enumerate() {
-                let field = game::field_at(row, col);
-                if wall_bitboard & field > 0 {
-                    color = TileColor::from(c).into();
-                    break;
-                }
-            }
-            if color != ' ' {
-                wall_tiles.push(serde_json::json!({
-                    "row": row,
-                    "col": col,
-                    "color": color,
-                }));
-            }
-        }
-    }
-    wall_tiles
-}
-
-pub fn pattern_lines_to_json(
-    pattern_line_occupancy: &[u8; 5],
-    pattern_line_colors: &[Option<TileColor>; 5],
-) -> Vec<serde_json::Value> {
-    let mut pattern_lines = Vec::new();
-    for i in 0..5 {
-        let color = pattern_line_colors[i];
-        if let Some(color) = color {
-            let color: char = color.into();
-            pattern_lines.push(serde_json::json!({
-                "patern_line_index": i,
-                "color": color,
-                "number_of_tiles": pattern_line_occupancy[i],
-            }));
-        }
-    }
-    pattern_lines
+// The game state in the schema of the game crate's serde feature, see game/src/serde_support.rs
+pub fn game_state_to_json(game_state: &GameState) -> serde_json::Value {
+    serde_json::to_value(game_state).expect("A game state can always be serialized")
 }
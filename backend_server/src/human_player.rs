@@ -78,24 +78,11 @@ fn format_move_request_json(
     move_list: &MoveList,
     request_id: &str,
 ) -> serde_json::Value {
-    let game_state_json = game_state_to_json(game_state);
-    let mut moves = Vec::new();
-    for move_ in move_list {
-        let mut move_json = serde_json::Map::new();
-        move_json.insert(
-            "take_from_factory_index".to_string(),
-            move_.take_from_factory_index.into(),
-        );
-        let color = char::from(move_.color).to_string();
-        move_json.insert("color".to_string(), color.into());
-        let pattern_json = serde_json::Value::from(move_.pattern.to_vec());
-        move_json.insert("pattern".to_string(), pattern_json);
-        moves.push(serde_json::Value::from(move_json));
-    }
-    let move_list_json = serde_json::Value::from(moves);
-    let mut json = serde_json::Map::new();
-    json.insert("game_state".to_string(), game_state_json);
-    json.insert("move_list".to_string(), move_list_json);
-    json.insert("request_id".to_string(), request_id.into());
-    serde_json::Value::from(json)
+    // The moves in the short notation, e.g. "3R>22F", the client answers with the index of its choice
+    let moves = move_list.into_iter().collect::<Vec<_>>();
+    serde_json::json!({
+        "game_state": game_state_to_json(game_state),
+        "move_list": moves,
+        "request_id": request_id,
+    })
 }
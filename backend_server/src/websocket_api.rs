@@ -1,6 +1,7 @@
 use crate::{game_manager::Match, human_player::HumanPlayer};
 use futures::{SinkExt, StreamExt};
-use game::{Player, SharedState};
+use game::match_::CancelToken;
+use game::{Player, SharedState, MAX_PLAYERS, MIN_PLAYERS};
 use player::{greedy_player::GreedyPlayer, random_player::RandomPlayer};
 use std::net::SocketAddr;
 use tokio::net::TcpListener;
@@ -16,6 +17,7 @@ pub enum EventType {
     Error,
     StartGame,
     GameStateUpdate,
+    ScoreReport,
     GameOver,
     MoveRequest,
     MoveResponse,
@@ -29,6 +31,7 @@ impl EventType {
             "error" => Some(Self::Error),
             "start_game" => Some(Self::StartGame),
             "game_state_update" => Some(Self::GameStateUpdate),
+            "score_report" => Some(Self::ScoreReport),
             "game_over" => Some(Self::GameOver),
             "move_request" => Some(Self::MoveRequest),
             "move_response" => Some(Self::MoveResponse),
@@ -37,19 +40,20 @@ impl EventType {
     }
 }
 
-impl ToString for EventType {
-    fn to_string(&self) -> String {
-        match self {
+impl std::fmt::Display for EventType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let string = match self {
             Self::Ping => "ping",
             Self::NewGame => "new_game",
             Self::Error => "error",
             Self::StartGame => "start_game",
             Self::GameStateUpdate => "game_state_update",
+            Self::ScoreReport => "score_report",
             Self::GameOver => "game_over",
             Self::MoveRequest => "move_request",
             Self::MoveResponse => "move_response",
-        }
-        .to_string()
+        };
+        write!(f, "{}", string)
     }
 }
 
@@ -64,6 +68,7 @@ pub struct WebSocketConnection {
     write: SharedState<WsSink>,
     addr: SocketAddr,
     read_broadcast: tokio::sync::broadcast::Sender<WebSocketMessage>,
+    closed: CancelToken, // Cancelled when the client disconnects
 }
 
 impl WebSocketConnection {
@@ -89,6 +94,7 @@ impl WebSocketConnection {
             write,
             addr,
             read_broadcast: broadcast.0,
+            closed: CancelToken::default(),
         };
 
         connection.spawn_receiver_task(read).await;
@@ -100,11 +106,17 @@ impl WebSocketConnection {
         self.addr
     }
 
+    // Cancelled once the client disconnected, matches of the client stop then
+    pub fn closed_token(&self) -> CancelToken {
+        self.closed.clone()
+    }
+
     async fn spawn_receiver_task(&self, mut read: futures::stream::SplitStream<WsTcpStream>) {
         // Spawn a thread that will send all received messages to all broadcast receivers
 
         let broadcast_sender: tokio::sync::broadcast::Sender<WebSocketMessage> =
             self.read_broadcast.clone();
+        let closed = self.closed.clone();
 
         tokio::task::spawn(async move {
             while let Some(result) = read.next().await {
@@ -139,6 +151,7 @@ impl WebSocketConnection {
                     Err(err) => log::error!("Error reading message: {:?}", err),
                 }
             }
+            closed.cancel();
         });
     }
 
@@ -182,7 +195,10 @@ impl WebSocketConnection {
                     log::error!("Client sent error event");
                     continue;
                 }
-                EventType::GameStateUpdate | EventType::GameOver | EventType::MoveRequest => {
+                EventType::GameStateUpdate
+                | EventType::ScoreReport
+                | EventType::GameOver
+                | EventType::MoveRequest => {
                     log::error!(
                         "Client sent {} event, this event is only sent by the server",
                         message.event_type.to_string()
@@ -231,6 +247,14 @@ impl WebSocketConnection {
             player.set_name(name);
             players.push(player);
         }
+        if !(MIN_PLAYERS..=MAX_PLAYERS).contains(&players.len()) {
+            return Err(format!(
+                "Invalid number of players: {}, expected between {} and {}",
+                players.len(),
+                MIN_PLAYERS,
+                MAX_PLAYERS
+            ));
+        }
         let game_manager_shared = Match::new_with_players(players).await;
         let game_manager = game_manager_shared.lock().await;
         let player_names = game_manager.get_player_names().await;
@@ -265,9 +289,17 @@ impl WebSocketConnection {
         let mut game_manager = game_manager_shared.lock().await;
         log::info!("Starting game {}", id);
         game_manager.start_match(self.clone()).await;
+        let mut data = serde_json::json!({ "id": id });
+        if let Some(result) = game_manager.get_result() {
+            // scores, complete_rows and placements
+            if let Ok(serde_json::Value::Object(fields)) = serde_json::to_value(&result) {
+                data.as_object_mut().unwrap().extend(fields);
+            }
+            data["winners"] = serde_json::json!(result.winners());
+        }
         let response = WebSocketMessage {
             event_type: EventType::GameOver,
-            data: serde_json::json!({ "id": id }),
+            data,
         };
         Ok(response)
     }
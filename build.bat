@@ -26,25 +26,17 @@ for %%d in (%FOLDERS%) do (
 rem -- Increment the max number by 1
 set /a "new_num=max_num + 1"
 
-rem -- Compile and move the executables for each feature setup
-for %%d in (%FOLDERS%) do (
-    echo Building for clients/%%d with new number !new_num!.exe
-
-    rem -- Adjust features based on folder number
-    if %%d EQU 2 (
-        cargo build --bin test_client --release
-    ) else if %%d EQU 3 (
-        cargo build --bin test_client --release --features three_players
-    ) else if %%d EQU 4 (
-        cargo build --bin test_client --release --features four_players
-    )
+rem -- Compile the client once, the same executable supports every player count
+cargo build --bin test_client --release
 
-    rem -- Check if executable exists and move it
-    if exist ".\target\release\test_client.exe" (
-        move ".\target\release\test_client.exe" "!CLIENTS_DIR!/%%d/!new_num!.exe"
-    ) else (
-        echo Failed to find the built executable. Please check the build logs.
-    )
+rem -- Check if executable exists and copy it into each client directory
+if not exist ".\target\release\test_client.exe" (
+    echo Failed to find the built executable. Please check the build logs.
+    exit /b 1
+)
+for %%d in (%FOLDERS%) do (
+    echo Copying to clients/%%d with new number !new_num!.exe
+    copy ".\target\release\test_client.exe" "!CLIENTS_DIR!/%%d/!new_num!.exe"
 )
 
 echo All clients updated successfully.
@@ -0,0 +1,560 @@
+use crate::factories::{Factories, CENTER_FACTORY_INDEX};
+use crate::game_state::{GameState, IntegrityViolation};
+use crate::move_::{Move, WALL_TILING_INDEX};
+use crate::player::PlayerMarker;
+use crate::ruleset::Ruleset;
+use crate::tile_color::{TileColor, NUM_TILE_COLORS};
+use crate::wall;
+use crate::{MAX_PLAYERS, MIN_PLAYERS};
+use std::io::{Read, Write};
+
+/*
+    Fixed-size binary encoding of game states and moves, meant for datasets with millions of positions.
+    All fields are packed as little endian bit fields, every encoding starts with (or contains) the format version.
+
+    Game state (GAME_STATE_SIZE bytes):
+    - Byte 0: Version
+    - Number of players (3 bits), ruleset (2 bits, see Ruleset::to_bits), current player (2), next round starting player (2),
+      tile taken from center (1)
+    - Bag and lid: 5 bits per color each
+    - Factory 1-9: 3 bits per color, unused factories are empty. Center: 5 bits per color
+    - For each of the MAX_PLAYERS players (unused players are zero):
+      Score (16 bits, two's complement), floor line (7), wall (3 bits per field, row by row, 0 = empty, else color + 1),
+      pattern lines (3 bits color + 1, 3 bits tiles per line)
+
+    Move (MOVE_SIZE bytes, one u32): Factory index (4 bits, 15 for a wall tiling), color (3), pattern lines (3 bits each),
+    floor line (5), version (5).
+
+    Dataset: The header b"AZDS" + version byte, followed by the entries without any separator. Each entry is
+    the game state, the move chosen in it and the value target for the player to move as f32 (DATASET_ENTRY_SIZE bytes).
+
+    A decoded game state has to pass check_integrity, and encoding it again has to give the same bytes.
+*/
+
+pub const BINARY_FORMAT_VERSION: u8 = 1;
+
+const PLAYER_BITS: usize = 16 + 7 + 25 * 3 + 5 * 6;
+const GAME_STATE_BITS: usize = 10 + 2 * 5 * 5 + 9 * 5 * 3 + 5 * 5 + MAX_PLAYERS * PLAYER_BITS;
+pub const GAME_STATE_SIZE: usize = 1 + GAME_STATE_BITS.div_ceil(8);
+pub const MOVE_SIZE: usize = 4;
+pub const DATASET_ENTRY_SIZE: usize = GAME_STATE_SIZE + MOVE_SIZE + 4;
+const DATASET_MAGIC: &[u8; 4] = b"AZDS";
+
+const WALL_TILING_FACTORY_CODE: u32 = 15;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BinaryDecodeError {
+    UnsupportedVersion(u8),
+    InvalidField(&'static str),
+    InvalidGameState(Vec<IntegrityViolation>),
+    // Bits that are unused for this game state (e.g. the boards of missing players) aren't zero
+    NonCanonical,
+}
+
+impl std::fmt::Display for BinaryDecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnsupportedVersion(version) => write!(
+                f,
+                "Unsupported binary format version {}, expected {}",
+                version, BINARY_FORMAT_VERSION
+            ),
+            Self::InvalidField(field) => write!(f, "Invalid {}", field),
+            Self::InvalidGameState(violations) => {
+                write!(f, "Invalid game state:")?;
+                for violation in violations {
+                    write!(f, "\n  {}", violation)?;
+                }
+                Ok(())
+            }
+            Self::NonCanonical => write!(f, "Unused bits of the encoding are set"),
+        }
+    }
+}
+
+impl std::error::Error for BinaryDecodeError {}
+
+struct BitWriter<'a> {
+    bytes: &'a mut [u8],
+    position: usize,
+}
+
+impl BitWriter<'_> {
+    fn write(&mut self, value: u32, bits: usize) {
+        debug_assert!(
+            bits == 32 || value >> bits == 0,
+            "{} doesn't fit in {} bits",
+            value,
+            bits
+        );
+        for bit in 0..bits {
+            if value >> bit & 1 == 1 {
+                let position = self.position + bit;
+                self.bytes[position / 8] |= 1 << (position % 8);
+            }
+        }
+        self.position += bits;
+    }
+}
+
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl BitReader<'_> {
+    fn read(&mut self, bits: usize) -> u32 {
+        let mut value = 0;
+        for bit in 0..bits {
+            let position = self.position + bit;
+            value |= u32::from(self.bytes[position / 8] >> (position % 8) & 1) << bit;
+        }
+        self.position += bits;
+        value
+    }
+}
+
+fn color_code(color: Option<TileColor>) -> u32 {
+    color.map_or(0, |color| u8::from(color) as u32 + 1)
+}
+
+fn color_from_code(code: u32, field: &'static str) -> Result<Option<TileColor>, BinaryDecodeError> {
+    match code {
+        0 => Ok(None),
+        1..=5 => Ok(Some(TileColor::from(code as u8 - 1))),
+        _ => Err(BinaryDecodeError::InvalidField(field)),
+    }
+}
+
+impl GameState {
+    pub fn to_bytes(&self) -> [u8; GAME_STATE_SIZE] {
+        let mut bytes = [0; GAME_STATE_SIZE];
+        bytes[0] = BINARY_FORMAT_VERSION;
+        let mut writer = BitWriter {
+            bytes: &mut bytes[1..],
+            position: 0,
+        };
+        writer.write(self.num_players as u32, 3);
+        writer.write(self.ruleset.to_bits() as u32, 2);
+        writer.write(u8::from(self.current_player) as u32, 2);
+        writer.write(u8::from(self.next_round_starting_player) as u32, 2);
+        writer.write(self.tile_taken_from_center as u32, 1);
+        for count in self.bag.iter().chain(self.out_of_bag.iter()) {
+            writer.write(*count as u32, 5);
+        }
+        for factory in 0..CENTER_FACTORY_INDEX {
+            for count in self.factories[factory] {
+                writer.write(count as u32, 3);
+            }
+        }
+        for count in self.factories[CENTER_FACTORY_INDEX] {
+            writer.write(count as u32, 5);
+        }
+        for player in 0..self.get_num_players() {
+            writer.write(self.scores[player] as u16 as u32, 16);
+            writer.write(self.floor_line_progress[player] as u32, 7);
+            for row in 0..5 {
+                for column in 0..5 {
+                    let field = wall::field_at(row, column);
+                    let color = (0..NUM_TILE_COLORS)
+                        .find(|&color| self.walls[player][color] & field > 0)
+                        .map(TileColor::from);
+                    writer.write(color_code(color), 3);
+                }
+            }
+            for line in 0..5 {
+                writer.write(color_code(self.pattern_lines_colors[player][line]), 3);
+                writer.write(self.pattern_lines_occupancy[player][line] as u32, 3);
+            }
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8; GAME_STATE_SIZE]) -> Result<Self, BinaryDecodeError> {
+        if bytes[0] != BINARY_FORMAT_VERSION {
+            return Err(BinaryDecodeError::UnsupportedVersion(bytes[0]));
+        }
+        let mut reader = BitReader {
+            bytes: &bytes[1..],
+            position: 0,
+        };
+        let num_players = reader.read(3) as usize;
+        if !(MIN_PLAYERS..=MAX_PLAYERS).contains(&num_players) {
+            return Err(BinaryDecodeError::InvalidField("number of players"));
+        }
+        let ruleset = Ruleset::from_bits(reader.read(2) as u8)
+            .ok_or(BinaryDecodeError::InvalidField("ruleset"))?;
+        let mut read_player = |field| match reader.read(2) as usize {
+            player if player < num_players => Ok(PlayerMarker::new(player as u8)),
+            _ => Err(BinaryDecodeError::InvalidField(field)),
+        };
+        let current_player = read_player("current player")?;
+        let next_round_starting_player = read_player("next round starting player")?;
+
+        let mut game_state = GameState::new_without_factories(num_players, ruleset);
+        game_state.current_player = current_player;
+        game_state.next_round_starting_player = next_round_starting_player;
+        game_state.tile_taken_from_center = reader.read(1) == 1;
+        for count in game_state
+            .bag
+            .iter_mut()
+            .chain(game_state.out_of_bag.iter_mut())
+        {
+            *count = reader.read(5) as u8;
+        }
+        let mut factories = Factories::empty(num_players);
+        for factory in 0..CENTER_FACTORY_INDEX {
+            for count in factories[factory].iter_mut() {
+                *count = reader.read(3) as u8;
+            }
+        }
+        for count in factories[CENTER_FACTORY_INDEX].iter_mut() {
+            *count = reader.read(5) as u8;
+        }
+        game_state.factories = factories;
+        for player in 0..num_players {
+            game_state.scores[player] = reader.read(16) as u16 as i16;
+            game_state.floor_line_progress[player] = reader.read(7) as u8;
+            for row in 0..5 {
+                for column in 0..5 {
+                    if let Some(color) = color_from_code(reader.read(3), "wall")? {
+                        game_state.walls[player][color as usize] |= wall::field_at(row, column);
+                        game_state.wall_occupancy[player] |= wall::field_at(row, column);
+                    }
+                }
+            }
+            for line in 0..5 {
+                game_state.pattern_lines_colors[player][line] =
+                    color_from_code(reader.read(3), "pattern line color")?;
+                game_state.pattern_lines_occupancy[player][line] = reader.read(3) as u8;
+            }
+        }
+        game_state.hash = game_state.compute_hash();
+
+        let violations = game_state.integrity_violations();
+        if !violations.is_empty() {
+            return Err(BinaryDecodeError::InvalidGameState(violations));
+        }
+        // Catches tiles in factories that aren't used with this number of players and data of missing players
+        if game_state.to_bytes() != *bytes {
+            return Err(BinaryDecodeError::NonCanonical);
+        }
+        Ok(game_state)
+    }
+}
+
+impl Move {
+    pub fn to_bytes(&self) -> [u8; MOVE_SIZE] {
+        debug_assert!(*self != Move::DUMMY, "The dummy move can't be encoded");
+        let mut bytes = [0; MOVE_SIZE];
+        let mut writer = BitWriter {
+            bytes: &mut bytes,
+            position: 0,
+        };
+        let factory = if self.is_wall_tiling() {
+            WALL_TILING_FACTORY_CODE
+        } else {
+            self.take_from_factory_index as u32
+        };
+        writer.write(factory, 4);
+        writer.write(u8::from(self.color) as u32, 3);
+        for line in 0..5 {
+            writer.write(self.pattern[line] as u32, 3);
+        }
+        writer.write(self.pattern[5] as u32, 5);
+        writer.write(BINARY_FORMAT_VERSION as u32, 5);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8; MOVE_SIZE]) -> Result<Self, BinaryDecodeError> {
+        let mut reader = BitReader { bytes, position: 0 };
+        let factory = reader.read(4);
+        let color = reader.read(3);
+        let mut pattern = [0; 6];
+        for line in pattern.iter_mut().take(5) {
+            *line = reader.read(3) as u8;
+        }
+        pattern[5] = reader.read(5) as u8;
+        let version = reader.read(5) as u8;
+        if version != BINARY_FORMAT_VERSION {
+            return Err(BinaryDecodeError::UnsupportedVersion(version));
+        }
+
+        let take_from_factory_index = match factory {
+            WALL_TILING_FACTORY_CODE => WALL_TILING_INDEX,
+            factory if (factory as usize) <= CENTER_FACTORY_INDEX => factory as u8,
+            _ => return Err(BinaryDecodeError::InvalidField("factory index")),
+        };
+        if color as usize >= NUM_TILE_COLORS {
+            return Err(BinaryDecodeError::InvalidField("color"));
+        }
+        if pattern.iter().take(5).any(|&tiles| tiles > 5) {
+            return Err(BinaryDecodeError::InvalidField("pattern"));
+        }
+        Ok(Self {
+            take_from_factory_index,
+            color: TileColor::from(color as u8),
+            pattern,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DatasetEntry {
+    pub game_state: GameState,
+    pub best_move: Move,
+    pub value: f32, // Target value for the player to move, e.g. the game result or the search value
+}
+
+impl DatasetEntry {
+    pub fn to_bytes(&self) -> [u8; DATASET_ENTRY_SIZE] {
+        let mut bytes = [0; DATASET_ENTRY_SIZE];
+        bytes[..GAME_STATE_SIZE].copy_from_slice(&self.game_state.to_bytes());
+        bytes[GAME_STATE_SIZE..GAME_STATE_SIZE + MOVE_SIZE]
+            .copy_from_slice(&self.best_move.to_bytes());
+        bytes[GAME_STATE_SIZE + MOVE_SIZE..].copy_from_slice(&self.value.to_le_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8; DATASET_ENTRY_SIZE]) -> Result<Self, BinaryDecodeError> {
+        let (game_state, rest) = bytes.split_at(GAME_STATE_SIZE);
+        let (best_move, value) = rest.split_at(MOVE_SIZE);
+        Ok(Self {
+            game_state: GameState::from_bytes(game_state.try_into().unwrap())?,
+            best_move: Move::from_bytes(best_move.try_into().unwrap())?,
+            value: f32::from_le_bytes(value.try_into().unwrap()),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum DatasetError {
+    Io(std::io::Error),
+    InvalidHeader,
+    UnsupportedVersion(u8),
+    InvalidEntry {
+        index: u64,
+        error: BinaryDecodeError,
+    },
+}
+
+impl std::fmt::Display for DatasetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "{}", error),
+            Self::InvalidHeader => write!(f, "Not a position dataset"),
+            Self::UnsupportedVersion(version) => write!(
+                f,
+                "Unsupported dataset version {}, expected {}",
+                version, BINARY_FORMAT_VERSION
+            ),
+            Self::InvalidEntry { index, error } => write!(f, "Entry {}: {}", index, error),
+        }
+    }
+}
+
+impl std::error::Error for DatasetError {}
+
+impl From<std::io::Error> for DatasetError {
+    fn from(error: std::io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+// Writes the header on creation and one entry per call, wrap the writer in a BufWriter for files
+pub struct DatasetWriter<W: Write> {
+    writer: W,
+    entries: u64,
+}
+
+impl<W: Write> DatasetWriter<W> {
+    pub fn new(mut writer: W) -> Result<Self, DatasetError> {
+        writer.write_all(DATASET_MAGIC)?;
+        writer.write_all(&[BINARY_FORMAT_VERSION])?;
+        Ok(Self { writer, entries: 0 })
+    }
+
+    pub fn write(&mut self, entry: &DatasetEntry) -> Result<(), DatasetError> {
+        self.writer.write_all(&entry.to_bytes())?;
+        self.entries += 1;
+        Ok(())
+    }
+
+    pub fn entries(&self) -> u64 {
+        self.entries
+    }
+
+    // Flushes the writer and returns it
+    pub fn finish(mut self) -> Result<W, DatasetError> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+// Reads the entries one by one, a truncated last entry is an error
+pub struct DatasetReader<R: Read> {
+    reader: R,
+    entries: u64,
+}
+
+impl<R: Read> DatasetReader<R> {
+    pub fn new(mut reader: R) -> Result<Self, DatasetError> {
+        let mut header = [0; 5];
+        reader
+            .read_exact(&mut header)
+            .map_err(|error| match error.kind() {
+                std::io::ErrorKind::UnexpectedEof => DatasetError::InvalidHeader,
+                _ => DatasetError::Io(error),
+            })?;
+        if header[..4] != *DATASET_MAGIC {
+            return Err(DatasetError::InvalidHeader);
+        }
+        if header[4] != BINARY_FORMAT_VERSION {
+            return Err(DatasetError::UnsupportedVersion(header[4]));
+        }
+        Ok(Self { reader, entries: 0 })
+    }
+
+    // Ok(None) at the end of the dataset
+    pub fn read(&mut self) -> Result<Option<DatasetEntry>, DatasetError> {
+        let mut bytes = [0; DATASET_ENTRY_SIZE];
+        let mut filled = 0;
+        while filled < DATASET_ENTRY_SIZE {
+            match self.reader.read(&mut bytes[filled..]) {
+                Ok(0) if filled == 0 => return Ok(None),
+                Ok(0) => return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into()),
+                Ok(read) => filled += read,
+                Err(error) if error.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(error) => return Err(error.into()),
+            }
+        }
+        let index = self.entries;
+        self.entries += 1;
+        DatasetEntry::from_bytes(&bytes)
+            .map(Some)
+            .map_err(|error| DatasetError::InvalidEntry { index, error })
+    }
+}
+
+impl<R: Read> Iterator for DatasetReader<R> {
+    type Item = Result<DatasetEntry, DatasetError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::random_positions;
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    #[test]
+    fn test_binary_round_trip() {
+        assert_eq!(GAME_STATE_SIZE, 93);
+        for position in random_positions(12) {
+            let game_state = position.game_state;
+            let bytes = game_state.to_bytes();
+            assert_eq!(GameState::from_bytes(&bytes), Ok(game_state.clone()));
+            assert_eq!(
+                Move::from_bytes(&position.move_.to_bytes()),
+                Ok(position.move_)
+            );
+            assert!(bytes.len() < game_state.serialize_string().len());
+        }
+    }
+
+    #[test]
+    fn test_binary_decode_errors() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        let bytes = GameState::new(2, &mut rng).to_bytes();
+
+        let mut invalid = bytes;
+        invalid[0] = BINARY_FORMAT_VERSION + 1;
+        assert_eq!(
+            GameState::from_bytes(&invalid),
+            Err(BinaryDecodeError::UnsupportedVersion(
+                BINARY_FORMAT_VERSION + 1
+            ))
+        );
+        // 7 players
+        let mut invalid = bytes;
+        invalid[1] |= 0b111;
+        assert_eq!(
+            GameState::from_bytes(&invalid),
+            Err(BinaryDecodeError::InvalidField("number of players"))
+        );
+        // A tile in the bag too many
+        let mut invalid = bytes;
+        invalid[2] ^= 1 << 2;
+        assert!(matches!(
+            GameState::from_bytes(&invalid),
+            Err(BinaryDecodeError::InvalidGameState(_))
+        ));
+        // The score of the third player in a two player game
+        let third_player = 10 + 2 * 5 * 5 + 9 * 5 * 3 + 5 * 5 + 2 * PLAYER_BITS;
+        let mut invalid = bytes;
+        invalid[1 + third_player / 8 + 1] = 1;
+        assert_eq!(
+            GameState::from_bytes(&invalid),
+            Err(BinaryDecodeError::NonCanonical)
+        );
+
+        let move_bytes = Move::new_wall_tiling(2, 3, TileColor::Green).to_bytes();
+        let mut invalid = move_bytes;
+        invalid[0] = (invalid[0] & !0b1111) | 12;
+        assert_eq!(
+            Move::from_bytes(&invalid),
+            Err(BinaryDecodeError::InvalidField("factory index"))
+        );
+        let mut invalid = move_bytes;
+        invalid[3] = 0;
+        assert_eq!(
+            Move::from_bytes(&invalid),
+            Err(BinaryDecodeError::UnsupportedVersion(0))
+        );
+    }
+
+    #[test]
+    fn test_dataset_round_trip() {
+        let entries = random_positions(12)
+            .take(200)
+            .enumerate()
+            .map(|(i, position)| DatasetEntry {
+                game_state: position.game_state,
+                best_move: position.move_,
+                value: i as f32 / 200. - 0.5,
+            })
+            .collect::<Vec<_>>();
+        let mut writer = DatasetWriter::new(Vec::new()).unwrap();
+        for entry in &entries {
+            writer.write(entry).unwrap();
+        }
+        assert_eq!(writer.entries(), 200);
+        let bytes = writer.finish().unwrap();
+        assert_eq!(bytes.len(), 5 + 200 * DATASET_ENTRY_SIZE);
+
+        let read = DatasetReader::new(bytes.as_slice())
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(read, entries);
+
+        // A truncated entry and a damaged entry are reported, a file of a different format is rejected
+        let mut reader = DatasetReader::new(&bytes[..bytes.len() - 1]).unwrap();
+        assert!(matches!(reader.nth(199), Some(Err(DatasetError::Io(_)))));
+        let mut damaged = bytes.clone();
+        damaged[5 + DATASET_ENTRY_SIZE] = 0;
+        let mut reader = DatasetReader::new(damaged.as_slice()).unwrap();
+        assert!(reader.next().unwrap().is_ok());
+        assert!(matches!(
+            reader.next(),
+            Some(Err(DatasetError::InvalidEntry { index: 1, .. }))
+        ));
+        assert!(matches!(
+            DatasetReader::new(&b"AGN"[..]),
+            Err(DatasetError::InvalidHeader)
+        ));
+    }
+}
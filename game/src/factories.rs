@@ -1,22 +1,85 @@
 use crate::Bag;
-use crate::{tile_color::NUM_TILE_COLORS, NUM_PLAYERS};
+use crate::{tile_color::NUM_TILE_COLORS, MAX_PLAYERS, MIN_PLAYERS};
 use rand::rngs::SmallRng;
 use rand::Rng;
 
-// Map the number of players to the number of factories (+1 for the center factory at the last index)
+// Map the number of players to the number of factories (+1 for the center factory)
 const PLAYERS_TO_FACTORIES: [usize; 3] = [6, 8, 10];
-pub const NUM_FACTORIES: usize = PLAYERS_TO_FACTORIES[NUM_PLAYERS - 2];
-pub const CENTER_FACTORY_INDEX: usize = NUM_FACTORIES - 1;
+pub const MAX_FACTORIES: usize = PLAYERS_TO_FACTORIES[MAX_PLAYERS - MIN_PLAYERS];
+// The center is always stored at the last index, independent of the number of players.
+// Factories that are not used with fewer players stay empty.
+pub const CENTER_FACTORY_INDEX: usize = MAX_FACTORIES - 1;
 
 pub type Factory = [u8; NUM_TILE_COLORS];
 
+#[inline]
+pub fn num_factories_for_players(num_players: usize) -> usize {
+    PLAYERS_TO_FACTORIES[num_players - MIN_PLAYERS]
+}
+
+// Factories after a refill, the bag and lid after the tiles were drawn and the probability of drawing these factories
+#[derive(Debug, Clone, PartialEq)]
+pub struct RefillOutcome {
+    pub factories: Factories,
+    pub bag: Bag,
+    pub out_of_bag: Bag,
+    pub probability: f64,
+}
+
+// When the bag is empty, the tiles in the lid are put back into it
+#[inline]
+fn refill_bag_if_empty(bag: &mut Bag, out_of_bag: &mut Bag) {
+    if bag.iter().all(|&tile_count| tile_count == 0) {
+        *bag = *out_of_bag;
+        out_of_bag.fill(0);
+    }
+}
+
+// Probability that the remaining tiles are drawn into a factory that already holds drawn_tiles tiles
+fn factory_probability(bag: &Bag, out_of_bag: &Bag, remaining: &Factory, drawn_tiles: u8) -> f64 {
+    let mut bag = *bag;
+    let mut out_of_bag = *out_of_bag;
+    refill_bag_if_empty(&mut bag, &mut out_of_bag);
+    let tiles_left_in_bag = bag.iter().sum::<u8>();
+
+    if remaining.iter().all(|&tile_count| tile_count == 0) {
+        // A factory is only filled with less than 4 tiles if the bag and the lid are empty
+        return if drawn_tiles == 4 || tiles_left_in_bag == 0 {
+            1.
+        } else {
+            0.
+        };
+    }
+    if drawn_tiles == 4 || tiles_left_in_bag == 0 {
+        return 0.;
+    }
+
+    // Each draw picks one of the colors left in the bag with the same probability, independent of the number of tiles
+    let colors_in_bag = bag.iter().filter(|&&tile_count| tile_count > 0).count() as f64;
+    let mut probability = 0.;
+    for color in 0..NUM_TILE_COLORS {
+        if remaining[color] == 0 || bag[color] == 0 {
+            continue;
+        }
+        let mut next_bag = bag;
+        let mut next_remaining = *remaining;
+        next_bag[color] -= 1;
+        next_remaining[color] -= 1;
+        probability +=
+            factory_probability(&next_bag, &out_of_bag, &next_remaining, drawn_tiles + 1)
+                / colors_in_bag;
+    }
+    probability
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Factories {
-    factories: [Factory; NUM_FACTORIES],
+    factories: [Factory; MAX_FACTORIES],
+    num_factories: u8, // Number of factories in use (including the center)
 }
 
 impl std::ops::Deref for Factories {
-    type Target = [Factory; NUM_FACTORIES];
+    type Target = [Factory; MAX_FACTORIES];
 
     #[inline]
     fn deref(&self) -> &Self::Target {
@@ -49,18 +112,105 @@ impl std::ops::IndexMut<usize> for Factories {
 
 impl Factories {
     #[inline]
-    pub fn empty() -> Self {
+    pub fn empty(num_players: usize) -> Self {
         Factories {
-            factories: [[0; NUM_TILE_COLORS]; NUM_FACTORIES],
+            factories: [[0; NUM_TILE_COLORS]; MAX_FACTORIES],
+            num_factories: num_factories_for_players(num_players) as u8,
         }
     }
 
+    #[inline]
+    pub fn num_factories(&self) -> usize {
+        self.num_factories as usize
+    }
+
+    // Indices of the factories in use, the center is always the last one
+    #[inline]
+    pub fn active_indices(&self) -> impl Iterator<Item = usize> {
+        (0..self.num_factories() - 1).chain(std::iter::once(CENTER_FACTORY_INDEX))
+    }
+
     #[inline]
     pub fn is_empty(&self) -> bool {
         self.iter()
             .all(|factory| factory.iter().all(|&tile_count| tile_count == 0))
     }
 
+    // Lowest index of the factories with the same tiles as this one, taking from any of them leads to equivalent positions.
+    // The center is never equivalent to another factory.
+    pub fn equivalent_factory(&self, factory_index: usize) -> usize {
+        if factory_index == CENTER_FACTORY_INDEX {
+            return factory_index;
+        }
+        (0..factory_index)
+            .find(|&i| self.factories[i] == self.factories[factory_index])
+            .unwrap_or(factory_index)
+    }
+
+    // Number of factories with the same tiles as this one, including itself
+    pub fn multiplicity(&self, factory_index: usize) -> u8 {
+        if factory_index == CENTER_FACTORY_INDEX {
+            return 1;
+        }
+        (0..self.num_factories() - 1)
+            .filter(|&i| self.factories[i] == self.factories[factory_index])
+            .count() as u8
+    }
+
+    // Probability that refill_by_drawing_from_bag fills the empty factories with the content of these factories.
+    // This includes the case that the bag runs empty during the refill and the lid is put back into the bag.
+    pub fn refill_probability(&self, bag: &Bag, out_of_bag: &Bag) -> f64 {
+        if self[CENTER_FACTORY_INDEX]
+            .iter()
+            .any(|&tile_count| tile_count > 0)
+        {
+            return 0.;
+        }
+        let mut bag = *bag;
+        let mut out_of_bag = *out_of_bag;
+        let mut probability = 1.;
+        for factory in self.iter().take(self.num_factories() - 1) {
+            probability *= factory_probability(&bag, &out_of_bag, factory, 0);
+            if probability == 0. {
+                return 0.;
+            }
+
+            // The tiles are taken from the bag until it is empty, the rest comes from the lid.
+            // The probability is only positive if the factory can be drawn like this.
+            if factory.iter().sum::<u8>() < bag.iter().sum::<u8>() {
+                for (bag_tiles, drawn_tiles) in bag.iter_mut().zip(factory) {
+                    *bag_tiles -= drawn_tiles;
+                }
+            } else {
+                for color in 0..NUM_TILE_COLORS {
+                    bag[color] = out_of_bag[color] - (factory[color] - bag[color]);
+                }
+                out_of_bag.fill(0);
+            }
+        }
+        probability
+    }
+
+    // Draws a refill like refill_by_drawing_from_bag and returns it together with its probability
+    pub fn sample_refill(
+        num_players: usize,
+        bag: &Bag,
+        out_of_bag: &Bag,
+        rng: &mut SmallRng,
+    ) -> RefillOutcome {
+        let mut factories = Self::empty(num_players);
+        let mut next_bag = *bag;
+        let mut next_out_of_bag = *out_of_bag;
+        factories.refill_by_drawing_from_bag(&mut next_bag, &mut next_out_of_bag, rng);
+        let probability = factories.refill_probability(bag, out_of_bag);
+        RefillOutcome {
+            factories,
+            bag: next_bag,
+            out_of_bag: next_out_of_bag,
+            probability,
+        }
+    }
+
     #[inline]
     pub fn refill_by_drawing_from_bag(
         &mut self,
@@ -77,7 +227,8 @@ impl Factories {
         }
 
         let mut tiles_left_in_bag = bag.iter().sum::<u8>();
-        for factory in self.factories.iter_mut().take(CENTER_FACTORY_INDEX) {
+        let num_factories = self.num_factories();
+        for factory in self.factories.iter_mut().take(num_factories - 1) {
             #[cfg(debug_assertions)]
             {
                 for color in factory.iter_mut() {
@@ -119,7 +270,7 @@ mod tests {
 
     #[test]
     fn test_empty_factories() {
-        let factories = Factories::empty();
+        let factories = Factories::empty(2);
 
         for factory in factories.iter() {
             for &tile_count in factory.iter() {
@@ -133,7 +284,7 @@ mod tests {
         // Make sure the number of tiles placed in the factories is correct
 
         // Setup factories and bag
-        let mut factories: Factories = Factories::empty();
+        let mut factories: Factories = Factories::empty(2);
         let mut bag = [20, 20, 20, 20, 20];
         let mut out_of_bag: Bag = [0, 0, 0, 0, 0];
         let mut rng = SmallRng::seed_from_u64(42); // Seed RNG for reproducibility
@@ -143,7 +294,7 @@ mod tests {
 
         // Check that the factories are filled correctly
         let remaining_tiles_in_bag = bag.iter().sum::<u8>();
-        let expected_num_tiles_in_factories = 4 * (NUM_FACTORIES as u8 - 1); // -1 because the center factory is not filled
+        let expected_num_tiles_in_factories = 4 * (factories.num_factories() as u8 - 1); // -1 because the center factory is not filled
 
         assert_eq!(
             remaining_tiles_in_bag,
@@ -151,9 +302,82 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_refill_uses_factories_of_player_count() {
+        // Make sure only the factories in use are filled and the center stays at the last index
+        for num_players in MIN_PLAYERS..=MAX_PLAYERS {
+            let mut factories = Factories::empty(num_players);
+            let mut bag = [20, 20, 20, 20, 20];
+            let mut out_of_bag: Bag = [0, 0, 0, 0, 0];
+            let mut rng = SmallRng::seed_from_u64(42);
+
+            factories.refill_by_drawing_from_bag(&mut bag, &mut out_of_bag, &mut rng);
+
+            let num_factories = num_factories_for_players(num_players);
+            for (factory_index, factory) in factories.iter().enumerate() {
+                let tile_count = factory.iter().sum::<u8>();
+                if factory_index < num_factories - 1 {
+                    assert_eq!(tile_count, 4);
+                } else {
+                    assert_eq!(tile_count, 0);
+                }
+            }
+            assert_eq!(
+                factories.active_indices().last(),
+                Some(CENTER_FACTORY_INDEX)
+            );
+        }
+    }
+
+    #[test]
+    fn test_refill_probability() {
+        // Two tiles can only end up in the first factory, in either order
+        let mut factories = Factories::empty(2);
+        factories[0] = [1, 1, 0, 0, 0];
+        assert_eq!(factories.refill_probability(&[1, 1, 0, 0, 0], &[0; 5]), 1.);
+        assert_eq!(factories.refill_probability(&[2, 0, 0, 0, 0], &[0; 5]), 0.);
+        // A factory with less than 4 tiles while tiles are left
+        assert_eq!(factories.refill_probability(&[1, 1, 1, 0, 0], &[0; 5]), 0.);
+
+        // The colors are drawn with the same probability, no matter how many tiles are left of each
+        // (each of the four draws picks blue with 1/2 while the yellow tile is in the bag)
+        let mut factories = Factories::empty(2);
+        factories[0] = [4, 0, 0, 0, 0];
+        factories[1] = [1, 1, 0, 0, 0];
+        let probability = factories.refill_probability(&[5, 1, 0, 0, 0], &[0; 5]);
+        assert_eq!(probability, 1. / 16.);
+    }
+
+    #[test]
+    fn test_refill_probability_matches_sampling() {
+        // Few tiles in the bag, so the lid is put back into it during the refill
+        let bag: Bag = [2, 1, 0, 0, 1];
+        let out_of_bag: Bag = [0, 2, 3, 1, 0];
+        let mut rng = SmallRng::seed_from_u64(0);
+        let num_samples = 20000;
+        let mut frequencies: std::collections::HashMap<Vec<Factory>, (u32, f64)> =
+            Default::default();
+        for _ in 0..num_samples {
+            let outcome = Factories::sample_refill(2, &bag, &out_of_bag, &mut rng);
+            assert_eq!(outcome.bag, [0; 5]);
+            assert_eq!(outcome.out_of_bag, [0; 5]);
+            let entry = frequencies
+                .entry(outcome.factories.to_vec())
+                .or_insert((0, outcome.probability));
+            entry.0 += 1;
+        }
+
+        let total_probability: f64 = frequencies.values().map(|(_, p)| p).sum();
+        assert!((total_probability - 1.).abs() < 1e-9);
+        for (count, probability) in frequencies.values() {
+            let frequency = *count as f64 / num_samples as f64;
+            assert!((frequency - probability).abs() < 0.01);
+        }
+    }
+
     #[test]
     fn test_index_access() {
-        let mut factories = Factories::empty();
+        let mut factories = Factories::empty(2);
         // Modify a factory
         factories[0][1] = 5;
 
@@ -165,7 +389,7 @@ mod tests {
         // Make sure the function refills the bag from the out_of_bag when it runs out of tiles
 
         // Setup factories and bag
-        let mut factories: Factories = Factories::empty();
+        let mut factories: Factories = Factories::empty(2);
         let mut bag = [1, 1, 1, 1, 1]; // not enough tiles to fill all factories
         let mut out_of_bag: Bag = [19, 19, 19, 19, 19];
         let mut rng = SmallRng::seed_from_u64(42); // Seed RNG for reproducibility
@@ -182,7 +406,7 @@ mod tests {
         // Make sure the factory filling works even if there are not enough tiles in the bag and out_of_bag
 
         // Setup factories and bag
-        let mut factories: Factories = Factories::empty();
+        let mut factories: Factories = Factories::empty(2);
         let mut bag = [0, 0, 0, 0, 0]; // no tiles in the bag
         let mut out_of_bag: Bag = [0, 0, 0, 0, 10]; // only 10 white tiles in the out_of_bag
         let mut rng = SmallRng::seed_from_u64(42); // Seed RNG for reproducibility
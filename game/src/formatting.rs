@@ -18,12 +18,14 @@ pub fn bag_to_string(bag: &Bag) -> String {
     string
 }
 
-pub fn factories_to_string(factories: &Factories) -> String {
+pub fn factories_to_string(factories: &Factories, num_players: usize) -> String {
     let mut string = String::new();
+    let num_factories = factories.num_factories();
 
     let mut factory_strings = Vec::new();
     let mut total_length = 0;
-    for (factory_index, factory) in factories.iter().enumerate() {
+    for factory_index in factories.active_indices() {
+        let factory = &factories[factory_index];
         let mut factory_string = String::new();
         let tile_count: usize = factory.iter().sum::<u8>() as usize;
 
@@ -43,22 +45,22 @@ pub fn factories_to_string(factories: &Factories) -> String {
             );
         }
 
-        if factory_index != NUM_FACTORIES - 1 {
+        if factory_index != CENTER_FACTORY_INDEX {
             factory_string.push_str(&".".repeat(4 - tile_count));
         }
 
         factory_strings.push(factory_string);
     }
 
-    let maximum_size = (NUM_PLAYERS * 33).max(total_length);
-    let spaces_between_factories = (maximum_size - total_length) / NUM_FACTORIES;
+    let maximum_size = (num_players * 33).max(total_length);
+    let spaces_between_factories = (maximum_size - total_length) / num_factories;
 
     for factory_string in &factory_strings {
         string.push_str(factory_string);
         string.push_str(&" ".repeat(spaces_between_factories));
     }
     let remaining_space =
-        maximum_size - total_length - spaces_between_factories * (NUM_FACTORIES - 1);
+        maximum_size - total_length - spaces_between_factories * (num_factories - 1);
     let leading_spaces = remaining_space / 2;
     format!("{}{}\n", " ".repeat(leading_spaces), string,)
 }
@@ -79,7 +81,10 @@ fn player_wall_to_string(game_state: &GameState, player_index: usize) -> String
                 }
             }
 
-            if !found && game_state.get_wall_ocupancy()[player_index] & bit == 0 {
+            if !found && game_state.get_ruleset().is_free_wall() {
+                // The fields of a free wall have no color until a tile is placed
+                string.push('.');
+            } else if !found && game_state.get_wall_ocupancy()[player_index] & bit == 0 {
                 for (color, wall_color) in WALL_COLOR_MASKS.iter().enumerate() {
                     if wall_color & bit > 0 {
                         let (start, end) = TileColor::from(color).get_color_string();
@@ -135,9 +140,11 @@ fn player_pattern_board_to_string(game_state: &GameState, player_index: usize) -
 }
 
 pub fn display_gamestate(game_state: &GameState, player_names: Option<&Vec<String>>) -> String {
+    let num_players = game_state.get_num_players();
+
     // Empty line for spacing
     let mut empty_line = " ".to_string();
-    for _ in 0..NUM_PLAYERS - 1 {
+    for _ in 0..num_players - 1 {
         empty_line.push_str(&" ".repeat(28));
         empty_line.push_str("|  ");
     }
@@ -153,12 +160,15 @@ pub fn display_gamestate(game_state: &GameState, player_names: Option<&Vec<Strin
         bag_to_string(&game_state.get_out_of_bag())
     ));
     string.push('\n');
-    string.push_str(&factories_to_string(game_state.get_factories()));
+    string.push_str(&factories_to_string(
+        game_state.get_factories(),
+        num_players,
+    ));
     string.push('\n');
 
     // Player header
     string.push(' ');
-    for player_index in 0..NUM_PLAYERS {
+    for player_index in 0..num_players {
         if usize::from(game_state.get_current_player()) == player_index {
             string.push_str("\x1b[30m\x1b[47m");
         }
@@ -180,7 +190,7 @@ pub fn display_gamestate(game_state: &GameState, player_names: Option<&Vec<Strin
             player_name,
             game_state.get_scores()[player_index]
         ));
-        if player_index != NUM_PLAYERS - 1 {
+        if player_index != num_players - 1 {
             string.push_str("|  ");
         }
     }
@@ -191,7 +201,7 @@ pub fn display_gamestate(game_state: &GameState, player_names: Option<&Vec<Strin
 
     // Compute max lines
     let mut max_lines = 0;
-    for player_index in 0..NUM_PLAYERS {
+    for player_index in 0..num_players {
         let pattern_string = player_pattern_board_to_string(game_state, player_index);
         let wall_string = player_wall_to_string(game_state, player_index);
         max_lines = max_lines.max(pattern_string.lines().count());
@@ -200,7 +210,7 @@ pub fn display_gamestate(game_state: &GameState, player_names: Option<&Vec<Strin
 
     // Player pattern boards next to walls
     for line in 0..max_lines {
-        for player_index in 0..NUM_PLAYERS {
+        for player_index in 0..num_players {
             let pattern_string = player_pattern_board_to_string(game_state, player_index);
             let wall_string = player_wall_to_string(game_state, player_index);
 
@@ -210,7 +220,7 @@ pub fn display_gamestate(game_state: &GameState, player_names: Option<&Vec<Strin
             string.push_str(pattern_line);
             string.push_str("->  "); // separator between pattern and wall
             string.push_str(wall_line);
-            if player_index != NUM_PLAYERS - 1 {
+            if player_index != num_players - 1 {
                 string.push_str(" | ");
             }
         }
@@ -222,7 +232,7 @@ pub fn display_gamestate(game_state: &GameState, player_names: Option<&Vec<Strin
 
     string.push(' ');
     // Player floor lines
-    for player_index in 0..NUM_PLAYERS {
+    for player_index in 0..num_players {
         let mut floor_line = String::new();
         let progress = game_state.get_floor_line_progress()[player_index]
             .min(FLOOR_LINE_PENALTY.len() as u8 - 1) as usize;
@@ -247,7 +257,7 @@ pub fn display_gamestate(game_state: &GameState, player_names: Option<&Vec<Strin
 
         let total_penalty = FLOOR_LINE_PENALTY[progress] as i16;
         string.push_str(&format!(" {:5}", -total_penalty));
-        if player_index != NUM_PLAYERS - 1 {
+        if player_index != num_players - 1 {
             string.push_str(" |  ");
         }
     }
@@ -1,10 +1,15 @@
-use crate::factories::{Factories, CENTER_FACTORY_INDEX, NUM_FACTORIES};
+use crate::factories::{
+    num_factories_for_players, Factories, Factory, RefillOutcome, CENTER_FACTORY_INDEX,
+};
 use crate::move_::Move;
 use crate::move_list::MoveList;
 use crate::player::PlayerMarker;
+use crate::ruleset::{PatternLinePlacement, Ruleset};
+use crate::score_report::EndOfGameBonus;
 use crate::tile_color::{TileColor, NUM_TILE_COLORS};
 use crate::wall::{self, WALL_COLOR_MASKS};
-use crate::{GameError, NUM_PLAYERS};
+use crate::zobrist;
+use crate::{GameError, MAX_PLAYERS, MIN_PLAYERS};
 use rand::rngs::SmallRng;
 
 #[cfg(debug_assertions)]
@@ -49,6 +54,36 @@ fn find_tile_combinations(
     }
 }
 
+// Official placement: All tiles go into one pattern line, the tiles that don't fit are discarded
+fn find_single_line_placements(
+    tiles: u8,
+    remaining_space: &[u8; 6],
+    move_list: &mut MoveList,
+    factory_index: u8,
+    color: TileColor,
+) {
+    for (pattern_line_index, space) in remaining_space.iter().enumerate().take(5) {
+        if *space == 0 {
+            continue;
+        }
+        let mut pattern = [0; 6];
+        pattern[pattern_line_index] = tiles.min(*space);
+        pattern[5] = tiles - pattern[pattern_line_index];
+        move_list.push(Move {
+            take_from_factory_index: factory_index,
+            color,
+            pattern,
+        });
+    }
+
+    // Placing all tiles on the floor line is always possible
+    move_list.push(Move {
+        take_from_factory_index: factory_index,
+        color,
+        pattern: [0, 0, 0, 0, 0, tiles],
+    });
+}
+
 pub type Bag = [u8; NUM_TILE_COLORS];
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -58,33 +93,495 @@ pub enum MoveGenerationResult {
     Continue,
 }
 
+// Everything do_move changes that can't be recomputed from the move itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MoveUndo {
+    mov: Move,
+    factory_content: Factory, // Content of the factory the tiles were taken from before the move
+    score: i16,               // Score of the player before the move, changes on wall tiling moves
+    current_player: PlayerMarker,
+    next_round_starting_player: PlayerMarker,
+    tile_taken_from_center: bool,
+    hash: u64,
+}
+
+// Everything evaluate_round changes, the lines that were emptied are full, so only their colors are needed to restore them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RoundUndo {
+    scores: [i16; MAX_PLAYERS],
+    floor_line_progress: [u8; MAX_PLAYERS],
+    evaluated_lines: [[Option<TileColor>; 5]; MAX_PLAYERS],
+    current_player: PlayerMarker,
+    tile_taken_from_center: bool,
+    hash: u64,
+}
+
+// A refill only happens when all factories are empty, so only the bag and the lid need to be stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RefillUndo {
+    bag: Bag,
+    out_of_bag: Bag,
+    hash: u64,
+}
+
+// Describes which field of a serialized game state could not be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GameStateParseError {
+    MissingField(&'static str),
+    InvalidField {
+        field: &'static str,
+        value: String,
+    },
+    PlayerCountOutOfRange(usize),
+    FactoryCountMismatch {
+        num_players: usize,
+        expected: usize,
+        found: usize,
+    },
+}
+
+impl GameStateParseError {
+    fn invalid_field(field: &'static str, value: &str) -> Self {
+        Self::InvalidField {
+            field,
+            value: value.to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for GameStateParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MissingField(field) => write!(f, "Missing {}", field),
+            Self::InvalidField { field, value } => write!(f, "Invalid {} '{}'", field, value),
+            Self::PlayerCountOutOfRange(num_players) => write!(
+                f,
+                "Number of players in string ({}) is not between {} and {}",
+                num_players, MIN_PLAYERS, MAX_PLAYERS
+            ),
+            Self::FactoryCountMismatch {
+                num_players,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Expected {} factories for {} players, got {}",
+                expected, num_players, found
+            ),
+        }
+    }
+}
+
+impl std::error::Error for GameStateParseError {}
+
+fn field<'a>(
+    entries: &[&'a str],
+    index: usize,
+    name: &'static str,
+) -> Result<&'a str, GameStateParseError> {
+    entries
+        .get(index)
+        .copied()
+        .ok_or(GameStateParseError::MissingField(name))
+}
+
+fn parse_field<T: std::str::FromStr>(
+    value: &str,
+    name: &'static str,
+) -> Result<T, GameStateParseError> {
+    value
+        .parse()
+        .map_err(|_| GameStateParseError::invalid_field(name, value))
+}
+
+// Why a move can't be played in a position, see GameState::validate_move
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IllegalMoveReason {
+    // The factories are empty, the round has to be evaluated before the next move
+    RoundOver,
+    WallTilingExpected {
+        player: PlayerMarker,
+        pattern_line_index: usize,
+    },
+    NoWallTilingPending,
+    MalformedWallTiling,
+    WrongWallTilingLine {
+        expected: usize,
+        found: usize,
+    },
+    WrongWallTilingColor {
+        expected: TileColor,
+        found: TileColor,
+    },
+    WallFieldUnavailable {
+        row: usize,
+        column: usize,
+    },
+    InvalidFactory(u8),
+    ColorNotInFactory {
+        factory_index: u8,
+        color: TileColor,
+    },
+    WrongTileCount {
+        available: u8,
+        placed: u32,
+    },
+    PatternLineColorMismatch {
+        pattern_line_index: usize,
+        existing: TileColor,
+    },
+    ColorAlreadyOnWall {
+        pattern_line_index: usize,
+    },
+    PatternLineOverflow {
+        pattern_line_index: usize,
+        space: u8,
+        placed: u8,
+    },
+    // Official placement: The tiles of a move go into a single pattern line
+    MultiplePatternLines,
+    // Official placement: Tiles may only be discarded once the chosen pattern line is full
+    PatternLineNotFilled {
+        pattern_line_index: usize,
+    },
+}
+
+impl std::fmt::Display for IllegalMoveReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::RoundOver => write!(f, "The factories are empty, the round is over"),
+            Self::WallTilingExpected {
+                player,
+                pattern_line_index,
+            } => write!(
+                f,
+                "Player {} has to tile pattern line {} to the wall first",
+                usize::from(*player) + 1,
+                pattern_line_index + 1
+            ),
+            Self::NoWallTilingPending => write!(f, "No pattern line is waiting to be tiled"),
+            Self::MalformedWallTiling => {
+                write!(f, "A wall tiling has to name exactly one row and column")
+            }
+            Self::WrongWallTilingLine { expected, found } => write!(
+                f,
+                "Pattern line {} has to be tiled, not pattern line {}",
+                expected + 1,
+                found + 1
+            ),
+            Self::WrongWallTilingColor { expected, found } => write!(
+                f,
+                "The pattern line holds {} tiles, not {}",
+                expected, found
+            ),
+            Self::WallFieldUnavailable { row, column } => write!(
+                f,
+                "Wall field {} in row {} is taken or its color is already in the column",
+                column + 1,
+                row + 1
+            ),
+            Self::InvalidFactory(factory_index) => {
+                write!(f, "Factory {} is not in play", factory_index)
+            }
+            Self::ColorNotInFactory {
+                factory_index,
+                color,
+            } => write!(f, "Factory {} holds no {} tiles", factory_index, color),
+            Self::WrongTileCount { available, placed } => write!(
+                f,
+                "All {} tiles of the color have to be taken, the move places {}",
+                available, placed
+            ),
+            Self::PatternLineColorMismatch {
+                pattern_line_index,
+                existing,
+            } => write!(
+                f,
+                "Pattern line {} already holds {} tiles",
+                pattern_line_index + 1,
+                existing
+            ),
+            Self::ColorAlreadyOnWall { pattern_line_index } => write!(
+                f,
+                "The color is already on the wall in row {}",
+                pattern_line_index + 1
+            ),
+            Self::PatternLineOverflow {
+                pattern_line_index,
+                space,
+                placed,
+            } => write!(
+                f,
+                "Pattern line {} has space for {} tiles, the move places {}",
+                pattern_line_index + 1,
+                space,
+                placed
+            ),
+            Self::MultiplePatternLines => {
+                write!(f, "All tiles of a move have to go into one pattern line")
+            }
+            Self::PatternLineNotFilled { pattern_line_index } => write!(
+                f,
+                "Pattern line {} has to be filled before tiles are discarded",
+                pattern_line_index + 1
+            ),
+        }
+    }
+}
+
+// Where the tiles of a color are, the tiles are conserved if they add up to 20
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TileCounts {
+    pub bag: u32,
+    pub out_of_bag: u32,
+    pub factories: u32,
+    pub pattern_lines: u32,
+    pub walls: u32,
+}
+
+impl TileCounts {
+    pub fn total(&self) -> u32 {
+        self.bag + self.out_of_bag + self.factories + self.pattern_lines + self.walls
+    }
+}
+
+// A broken invariant of a game state, see GameState::check_integrity
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntegrityViolation {
+    BagOverflow {
+        color: TileColor,
+        count: u8,
+    },
+    FactoryOverflow {
+        factory_index: usize,
+        count: u32,
+    },
+    TileConservation {
+        color: TileColor,
+        counts: TileCounts,
+    },
+    OverlappingWallTiles {
+        player: usize,
+        color: TileColor,
+    },
+    TileOutsideWall {
+        player: usize,
+        color: TileColor,
+    },
+    // The tiles of the color are on fields the wall variant doesn't allow for the color
+    WallPatternViolation {
+        player: usize,
+        color: TileColor,
+    },
+    WallOccupancyMismatch {
+        player: usize,
+        expected: u32,
+        found: u32,
+    },
+    PatternLineWithoutColor {
+        player: usize,
+        pattern_line_index: usize,
+        count: u8,
+    },
+    EmptyPatternLineWithColor {
+        player: usize,
+        pattern_line_index: usize,
+        color: TileColor,
+    },
+    PatternColorOnWall {
+        player: usize,
+        pattern_line_index: usize,
+        color: TileColor,
+    },
+    PatternLineOverflow {
+        player: usize,
+        pattern_line_index: usize,
+        count: u8,
+    },
+    HashMismatch {
+        stored: u64,
+        computed: u64,
+    },
+}
+
+impl std::fmt::Display for IntegrityViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::BagOverflow { color, count } => {
+                write!(f, "The bag holds {} {} tiles, at most 20 exist", count, color)
+            }
+            Self::FactoryOverflow {
+                factory_index,
+                count,
+            } => write!(
+                f,
+                "Factory {} holds {} tiles, at most 4 fit",
+                factory_index + 1,
+                count
+            ),
+            Self::TileConservation { color, counts } => write!(
+                f,
+                "There are {} {} tiles instead of 20: {} in the bag, {} in the lid, {} in the factories, {} in pattern lines, {} on walls",
+                counts.total(),
+                color,
+                counts.bag,
+                counts.out_of_bag,
+                counts.factories,
+                counts.pattern_lines,
+                counts.walls
+            ),
+            Self::OverlappingWallTiles { player, color } => write!(
+                f,
+                "Player {} has {} tiles on wall fields that hold another color",
+                player + 1,
+                color
+            ),
+            Self::TileOutsideWall { player, color } => write!(
+                f,
+                "Player {} has {} tiles outside of the wall",
+                player + 1,
+                color
+            ),
+            Self::WallPatternViolation { player, color } => write!(
+                f,
+                "Player {} has {} tiles on fields that are not allowed for the color",
+                player + 1,
+                color
+            ),
+            Self::WallOccupancyMismatch {
+                player,
+                expected,
+                found,
+            } => write!(
+                f,
+                "The wall occupancy of player {} is {:b}, the tiles on the wall are {:b}",
+                player + 1,
+                found,
+                expected
+            ),
+            Self::PatternLineWithoutColor {
+                player,
+                pattern_line_index,
+                count,
+            } => write!(
+                f,
+                "Pattern line {} of player {} holds {} tiles without a color",
+                pattern_line_index + 1,
+                player + 1,
+                count
+            ),
+            Self::EmptyPatternLineWithColor {
+                player,
+                pattern_line_index,
+                color,
+            } => write!(
+                f,
+                "Pattern line {} of player {} is empty but has the color {}",
+                pattern_line_index + 1,
+                player + 1,
+                color
+            ),
+            Self::PatternColorOnWall {
+                player,
+                pattern_line_index,
+                color,
+            } => write!(
+                f,
+                "Pattern line {} of player {} holds {} tiles, which are already on the wall in that row",
+                pattern_line_index + 1,
+                player + 1,
+                color
+            ),
+            Self::PatternLineOverflow {
+                player,
+                pattern_line_index,
+                count,
+            } => write!(
+                f,
+                "Pattern line {} of player {} holds {} tiles",
+                pattern_line_index + 1,
+                player + 1,
+                count
+            ),
+            Self::HashMismatch { stored, computed } => write!(
+                f,
+                "The hash {:x} does not match the position, expected {:x}",
+                stored, computed
+            ),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct GameState {
-    bag: Bag, // For each color, how many tiles are left in the bag
-    out_of_bag: Bag,
-    factories: Factories, // For each factory, how many tiles of each color are in it (including the center)
+    pub(crate) ruleset: Ruleset,
 
-    scores: [i16; NUM_PLAYERS], // For each player, how many points they have
-    floor_line_progress: [u8; NUM_PLAYERS], // For each player, how many tiles they have in their penalty
+    pub(crate) bag: Bag, // For each color, how many tiles are left in the bag
+    pub(crate) out_of_bag: Bag,
+    pub(crate) factories: Factories, // For each factory, how many tiles of each color are in it (including the center)
 
-    walls: [[u32; NUM_TILE_COLORS]; NUM_PLAYERS], // For each player, and each color, the locations of the tiles on their wall
-    wall_occupancy: [u32; NUM_PLAYERS],           // For each player, the occupancy of their wall
+    // The per player arrays are sized for the maximum number of players, only the first num_players entries are used
+    pub(crate) num_players: u8,
 
-    pattern_lines_occupancy: [[u8; 5]; NUM_PLAYERS], // For each player, the occupancy of their pattern lines
-    pattern_lines_colors: [[Option<TileColor>; 5]; NUM_PLAYERS], // For each player, the color of their pattern lines. If the pattern line is empty, the color is 255
+    pub(crate) scores: [i16; MAX_PLAYERS], // For each player, how many points they have
+    pub(crate) floor_line_progress: [u8; MAX_PLAYERS], // For each player, how many tiles they have in their penalty
 
-    current_player: PlayerMarker,
-    next_round_starting_player: PlayerMarker,
-    tile_taken_from_center: bool,
+    pub(crate) walls: [[u32; NUM_TILE_COLORS]; MAX_PLAYERS], // For each player, and each color, the locations of the tiles on their wall
+    pub(crate) wall_occupancy: [u32; MAX_PLAYERS], // For each player, the occupancy of their wall
+
+    pub(crate) pattern_lines_occupancy: [[u8; 5]; MAX_PLAYERS], // For each player, the occupancy of their pattern lines
+    pub(crate) pattern_lines_colors: [[Option<TileColor>; 5]; MAX_PLAYERS], // For each player, the color of their pattern lines. If the pattern line is empty, the color is 255
+
+    pub(crate) current_player: PlayerMarker,
+    pub(crate) next_round_starting_player: PlayerMarker,
+    pub(crate) tile_taken_from_center: bool,
+
+    pub(crate) hash: u64, // Zobrist hash of all the fields above, updated incrementally
 }
 
 impl std::fmt::Debug for GameState {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.serialize_string())
+        write!(f, "{}", self.to_position_string())
+    }
+}
+
+impl PartialEq for GameState {
+    fn eq(&self, other: &Self) -> bool {
+        // The hash differs for almost all different positions, the fields only need to be compared if it matches
+        self.hash == other.hash
+            && self.ruleset == other.ruleset
+            && self.num_players == other.num_players
+            && self.bag == other.bag
+            && self.out_of_bag == other.out_of_bag
+            && self.factories == other.factories
+            && self.scores == other.scores
+            && self.floor_line_progress == other.floor_line_progress
+            && self.walls == other.walls
+            && self.pattern_lines_occupancy == other.pattern_lines_occupancy
+            && self.pattern_lines_colors == other.pattern_lines_colors
+            && self.current_player == other.current_player
+            && self.next_round_starting_player == other.next_round_starting_player
+            && self.tile_taken_from_center == other.tile_taken_from_center
+    }
+}
+
+impl Eq for GameState {}
+
+impl std::hash::Hash for GameState {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        state.write_u64(self.hash);
     }
 }
 
 impl GameState {
+    pub fn get_num_players(&self) -> usize {
+        self.num_players as usize
+    }
+
+    pub fn get_ruleset(&self) -> Ruleset {
+        self.ruleset
+    }
+
     pub fn get_current_player(&self) -> PlayerMarker {
         self.current_player
     }
@@ -93,8 +590,8 @@ impl GameState {
         self.next_round_starting_player
     }
 
-    pub fn get_scores(&self) -> [i16; NUM_PLAYERS] {
-        self.scores
+    pub fn get_scores(&self) -> &[i16] {
+        &self.scores[..self.get_num_players()]
     }
 
     pub fn get_bag(&self) -> Bag {
@@ -102,7 +599,9 @@ impl GameState {
     }
 
     pub fn set_bag(&mut self, bag: Bag) {
+        self.hash ^= self.hash_bags();
         self.bag = bag;
+        self.hash ^= self.hash_bags();
     }
 
     pub fn get_out_of_bag(&self) -> Bag {
@@ -110,7 +609,9 @@ impl GameState {
     }
 
     pub fn set_out_of_bag(&mut self, out_of_bag: Bag) {
+        self.hash ^= self.hash_bags();
         self.out_of_bag = out_of_bag;
+        self.hash ^= self.hash_bags();
     }
 
     pub fn get_factories(&self) -> &Factories {
@@ -118,35 +619,177 @@ impl GameState {
     }
 
     pub fn set_factories(&mut self, factories: Factories) {
+        self.hash ^= self.hash_factories();
         self.factories = factories;
+        self.hash ^= self.hash_factories();
     }
 
-    pub fn get_floor_line_progress(&self) -> [u8; NUM_PLAYERS] {
-        self.floor_line_progress
+    pub fn get_floor_line_progress(&self) -> &[u8] {
+        &self.floor_line_progress[..self.get_num_players()]
     }
 
-    pub fn get_walls(&self) -> &[[u32; NUM_TILE_COLORS]; NUM_PLAYERS] {
-        &self.walls
+    pub fn get_walls(&self) -> &[[u32; NUM_TILE_COLORS]] {
+        &self.walls[..self.get_num_players()]
     }
 
-    pub fn get_wall_ocupancy(&self) -> [u32; NUM_PLAYERS] {
-        self.wall_occupancy
+    pub fn get_wall_ocupancy(&self) -> &[u32] {
+        &self.wall_occupancy[..self.get_num_players()]
     }
 
-    pub fn get_pattern_lines_occupancy(&self) -> &[[u8; 5]; NUM_PLAYERS] {
-        &self.pattern_lines_occupancy
+    pub fn get_pattern_lines_occupancy(&self) -> &[[u8; 5]] {
+        &self.pattern_lines_occupancy[..self.get_num_players()]
     }
 
-    pub fn get_pattern_lines_colors(&self) -> &[[Option<TileColor>; 5]; NUM_PLAYERS] {
-        &self.pattern_lines_colors
+    pub fn get_pattern_lines_colors(&self) -> &[[Option<TileColor>; 5]] {
+        &self.pattern_lines_colors[..self.get_num_players()]
     }
 
     pub fn get_tile_taken_from_center(&self) -> bool {
         self.tile_taken_from_center
     }
 
+    // 64 bit Zobrist hash of the position, equal positions have equal hashes
+    pub fn get_hash(&self) -> u64 {
+        self.hash
+    }
+
+    fn hash_turn(&self) -> u64 {
+        let mut hash = zobrist::current_player(self.current_player.into())
+            ^ zobrist::next_round_starting_player(self.next_round_starting_player.into());
+        if self.tile_taken_from_center {
+            hash ^= zobrist::TILE_TAKEN_FROM_CENTER_KEY;
+        }
+        hash
+    }
+
+    fn hash_bags(&self) -> u64 {
+        let mut hash = 0;
+        for color in 0..NUM_TILE_COLORS {
+            hash ^= zobrist::bag(color, self.bag[color])
+                ^ zobrist::out_of_bag(color, self.out_of_bag[color]);
+        }
+        hash
+    }
+
+    fn hash_factory(&self, factory_index: usize) -> u64 {
+        let mut hash = 0;
+        for (color, number_of_tiles) in self.factories[factory_index].iter().enumerate() {
+            hash ^= zobrist::factory(factory_index, color, *number_of_tiles);
+        }
+        hash
+    }
+
+    fn hash_factories(&self) -> u64 {
+        (0..self.factories.len()).fold(0, |hash, factory_index| {
+            hash ^ self.hash_factory(factory_index)
+        })
+    }
+
+    fn hash_score_and_floor_line(&self, player: usize) -> u64 {
+        zobrist::score(player, self.scores[player])
+            ^ zobrist::floor_line(player, self.floor_line_progress[player])
+    }
+
+    fn hash_pattern_lines(&self, player: usize) -> u64 {
+        let mut hash = 0;
+        for line in 0..5 {
+            hash ^= zobrist::pattern_line(
+                player,
+                line,
+                self.pattern_lines_occupancy[player][line],
+                self.pattern_lines_colors[player][line],
+            );
+        }
+        hash
+    }
+
+    fn hash_wall(&self, player: usize) -> u64 {
+        let mut hash = 0;
+        for (color, color_board) in self.walls[player].iter().enumerate() {
+            let mut board = *color_board;
+            while board > 0 {
+                hash ^= zobrist::wall(player, color, board.trailing_zeros() as usize);
+                board &= board - 1;
+            }
+        }
+        hash
+    }
+
+    fn hash_player(&self, player: usize) -> u64 {
+        self.hash_score_and_floor_line(player)
+            ^ self.hash_pattern_lines(player)
+            ^ self.hash_wall(player)
+    }
+
+    fn hash_players(&self) -> u64 {
+        (0..self.get_num_players()).fold(0, |hash, player| hash ^ self.hash_player(player))
+    }
+
+    // The hash of everything a move can change, it is xored out before and in after the move
+    fn hash_move_features(&self, mov: Move, player: usize) -> u64 {
+        let mut hash = self.hash_turn()
+            ^ self.hash_bags()
+            ^ self.hash_score_and_floor_line(player)
+            ^ self.hash_pattern_lines(player);
+        if mov.is_wall_tiling() {
+            hash ^= self.hash_wall(player);
+        } else {
+            let take_from_factory_index = mov.take_from_factory_index as usize;
+            hash ^= self.hash_factory(take_from_factory_index);
+            if take_from_factory_index != CENTER_FACTORY_INDEX {
+                hash ^= self.hash_factory(CENTER_FACTORY_INDEX);
+            }
+        }
+        hash
+    }
+
+    pub(crate) fn compute_hash(&self) -> u64 {
+        zobrist::setup(self.get_num_players(), self.ruleset.to_bits())
+            ^ self.hash_turn()
+            ^ self.hash_bags()
+            ^ self.hash_factories()
+            ^ self.hash_players()
+    }
+
+    // Free wall: The fields a full pattern line of a player could be tiled to
+    pub fn get_wall_tiling_candidates(
+        &self,
+        player_index: usize,
+        pattern_line_index: usize,
+    ) -> u32 {
+        match self.pattern_lines_colors[player_index][pattern_line_index] {
+            Some(color) => {
+                wall::get_free_wall_candidates(&self.walls[player_index], pattern_line_index, color)
+            }
+            None => 0,
+        }
+    }
+
+    // Free wall: After the factories are empty, the full pattern lines are tiled one at a time.
+    // The players tile in turn order, each from the top line to the bottom line.
+    // Lines that can't be tiled anywhere are skipped, they are moved to the floor line when the round is evaluated.
+    pub fn get_pending_wall_tiling(&self) -> Option<(PlayerMarker, usize)> {
+        if !self.ruleset.is_free_wall() || !self.factories.is_empty() {
+            return None;
+        }
+        for player_index in 0..self.get_num_players() {
+            for (pattern_line_index, number_of_tiles) in self.pattern_lines_occupancy[player_index]
+                .iter()
+                .enumerate()
+            {
+                if *number_of_tiles as usize == pattern_line_index + 1
+                    && self.get_wall_tiling_candidates(player_index, pattern_line_index) > 0
+                {
+                    return Some((PlayerMarker::new(player_index as u8), pattern_line_index));
+                }
+            }
+        }
+        None
+    }
+
     pub fn serialize_string(&self) -> String {
-        let number_of_players = NUM_PLAYERS as u8;
+        let number_of_players = self.num_players;
+        let num_players = self.get_num_players();
         let bag = (self.bag[0] as usize)
             | (self.bag[1] as usize) << 8
             | (self.bag[2] as usize) << 16
@@ -163,20 +806,21 @@ impl GameState {
         // 3 bits * 5 colors * max 10 factories = 150 bit
         // The center might be larger
 
-        let mut factories = [0b0_u128; NUM_FACTORIES];
-        for (factory_index, factory) in self.factories.iter().enumerate().take(CENTER_FACTORY_INDEX)
-        {
+        // The center is always written last
+        let mut factories = Vec::with_capacity(self.factories.num_factories());
+        for factory_index in self.factories.active_indices() {
+            // The center might hold more than 15 tiles of a color, so it gets 8 bits per color
+            let bits_per_color = if factory_index == CENTER_FACTORY_INDEX {
+                8
+            } else {
+                4
+            };
             let mut factory_binary = 0b0_u128;
-            for (color_index, number_of_tiles) in factory.iter().enumerate() {
+            for (color_index, number_of_tiles) in self.factories[factory_index].iter().enumerate() {
                 let number_of_tiles = *number_of_tiles as u128;
-                factory_binary |= number_of_tiles << (color_index * 4);
+                factory_binary |= number_of_tiles << (color_index * bits_per_color);
             }
-            factories[factory_index] = factory_binary;
-        }
-        factories[CENTER_FACTORY_INDEX] = 0b0_u128;
-        for color_index in 0..NUM_TILE_COLORS {
-            let number_of_tiles = self.factories[CENTER_FACTORY_INDEX][color_index] as u128;
-            factories[CENTER_FACTORY_INDEX] |= number_of_tiles << (color_index * 8);
+            factories.push(factory_binary);
         }
 
         let factories_string = factories
@@ -186,32 +830,40 @@ impl GameState {
             .join("-");
 
         let mut scores = 0b0_u64;
-        for (player_index, score) in self.scores.iter().enumerate() {
+        for (player_index, score) in self.get_scores().iter().enumerate() {
             let score = (*score + 1000) as u64;
             scores |= score << (player_index * 16);
         }
 
         let mut floor_line_progress = 0b0_u64;
-        for (player_index, progress) in self.floor_line_progress.iter().enumerate() {
+        for (player_index, progress) in self.get_floor_line_progress().iter().enumerate() {
             let progress = *progress as u64;
             floor_line_progress |= progress << (player_index * 8);
         }
 
-        let mut walls = [0b0_u32; NUM_PLAYERS];
-        for (wall_index, wall) in self.walls.iter().enumerate() {
-            for bitboard in wall.iter() {
-                walls[wall_index] |= bitboard;
-            }
-        }
-        let walls_string = walls
-            .iter()
-            .map(|v| v.to_string())
-            .collect::<Vec<_>>()
-            .join("-");
+        // The colors of a colored wall follow from the occupancy, a free wall needs one bitboard per color
+        let walls_string = if self.ruleset.is_free_wall() {
+            self.get_walls()
+                .iter()
+                .map(|wall| {
+                    wall.iter()
+                        .map(|v| v.to_string())
+                        .collect::<Vec<_>>()
+                        .join(":")
+                })
+                .collect::<Vec<_>>()
+                .join("-")
+        } else {
+            self.get_wall_ocupancy()
+                .iter()
+                .map(|v| v.to_string())
+                .collect::<Vec<_>>()
+                .join("-")
+        };
 
         // 4 * 5 * 8
-        let mut pattern_line_occupancy = [0b0_u64; NUM_PLAYERS];
-        for (player_index, pattern_lines) in self.pattern_lines_occupancy.iter().enumerate() {
+        let mut pattern_line_occupancy = vec![0b0_u64; num_players];
+        for (player_index, pattern_lines) in self.get_pattern_lines_occupancy().iter().enumerate() {
             pattern_line_occupancy[player_index] |= pattern_lines[0] as u64;
             pattern_line_occupancy[player_index] |= (pattern_lines[1] as u64) << 8;
             pattern_line_occupancy[player_index] |= (pattern_lines[2] as u64) << 16;
@@ -224,8 +876,8 @@ impl GameState {
             .collect::<Vec<_>>()
             .join("-");
 
-        let mut pattern_line_colors = [0b0_u64; NUM_PLAYERS];
-        for (player_index, pattern_lines) in self.pattern_lines_colors.iter().enumerate() {
+        let mut pattern_line_colors = vec![0b0_u64; num_players];
+        for (player_index, pattern_lines) in self.get_pattern_lines_colors().iter().enumerate() {
             for (line_index, color) in pattern_lines.iter().enumerate() {
                 if let Some(color) = color {
                     pattern_line_colors[player_index] |= (*color as u64) << (line_index * 8);
@@ -243,7 +895,7 @@ impl GameState {
         let next_round_starting_player = usize::from(self.next_round_starting_player);
 
         format!(
-            "{}_{}_{}_{}_{}_{}_{}_{}_{}_{}_{}_{}",
+            "{}_{}_{}_{}_{}_{}_{}_{}_{}_{}_{}_{}_{}",
             number_of_players,
             usize::from(self.current_player),
             next_round_starting_player,
@@ -255,38 +907,36 @@ impl GameState {
             walls_string,
             pattern_line_string,
             pattern_line_colors_string,
-            self.tile_taken_from_center as u8
+            self.tile_taken_from_center as u8,
+            self.ruleset.to_bits()
         )
     }
 
-    pub fn deserialize_string(string: &str) -> Result<Self, String> {
+    pub fn deserialize_string(string: &str) -> Result<Self, GameStateParseError> {
         let entries: Vec<&str> = string.split('_').collect();
 
-        let number_of_players = entries.first().ok_or("No number of players")?;
-        let number_of_players = number_of_players
-            .parse::<u8>()
-            .map_err(|_| "Invalid number of players")?;
-        if number_of_players != NUM_PLAYERS as u8 {
-            return Err(format!(
-                "Number of players in string ({}) does not match number of players in game ({})",
-                number_of_players, NUM_PLAYERS
+        let number_of_players: u8 = parse_field(
+            field(&entries, 0, "number of players")?,
+            "number of players",
+        )?;
+        if !(MIN_PLAYERS..=MAX_PLAYERS).contains(&(number_of_players as usize)) {
+            return Err(GameStateParseError::PlayerCountOutOfRange(
+                number_of_players as usize,
             ));
         }
+        let num_players = number_of_players as usize;
 
-        let current_player = entries.get(1).ok_or("No current player")?;
-        let current_player = current_player
-            .parse::<u8>()
-            .map_err(|_| "Invalid current player")?;
-        let current_player = PlayerMarker::new(current_player);
-
-        let next_round_starting_player = entries.get(2).ok_or("No next round starting player")?;
-        let next_round_starting_player = next_round_starting_player
-            .parse::<u8>()
-            .map_err(|_| "Invalid next round starting player")?;
-        let next_round_starting_player = PlayerMarker::new(next_round_starting_player);
+        let parse_player = |index: usize, name: &'static str| {
+            let value = field(&entries, index, name)?;
+            match parse_field::<u8>(value, name)? {
+                player if (player as usize) < num_players => Ok(PlayerMarker::new(player)),
+                _ => Err(GameStateParseError::invalid_field(name, value)),
+            }
+        };
+        let current_player = parse_player(1, "current player")?;
+        let next_round_starting_player = parse_player(2, "next round starting player")?;
 
-        let bag_binary = entries.get(3).ok_or("No bag")?;
-        let bag_binary = bag_binary.parse::<usize>().map_err(|_| "Invalid bag")?;
+        let bag_binary: usize = parse_field(field(&entries, 3, "bag")?, "bag")?;
         let mut bag = [0; NUM_TILE_COLORS];
         bag[0] = (bag_binary & 0xFF) as u8;
         bag[1] = ((bag_binary >> 8) & 0xFF) as u8;
@@ -294,10 +944,8 @@ impl GameState {
         bag[3] = ((bag_binary >> 24) & 0xFF) as u8;
         bag[4] = ((bag_binary >> 32) & 0xFF) as u8;
 
-        let out_of_bag_binary = entries.get(4).ok_or("No out of bag")?;
-        let out_of_bag_binary = out_of_bag_binary
-            .parse::<usize>()
-            .map_err(|_| "Invalid out of bag")?;
+        let out_of_bag_binary: usize =
+            parse_field(field(&entries, 4, "out of bag")?, "out of bag")?;
         let mut out_of_bag = [0; NUM_TILE_COLORS];
         out_of_bag[0] = (out_of_bag_binary & 0xFF) as u8;
         out_of_bag[1] = ((out_of_bag_binary >> 8) & 0xFF) as u8;
@@ -305,104 +953,138 @@ impl GameState {
         out_of_bag[3] = ((out_of_bag_binary >> 24) & 0xFF) as u8;
         out_of_bag[4] = ((out_of_bag_binary >> 32) & 0xFF) as u8;
 
-        let factories_strings = entries.get(5).ok_or("No factories")?;
-        let mut factories = Factories::empty();
-        for (factory_index, factory_string) in factories_strings
-            .split('-')
-            .enumerate()
-            .take(CENTER_FACTORY_INDEX)
-        {
-            let factory_binary = factory_string
-                .parse::<u128>()
-                .map_err(|_| "Invalid factory")?;
-            for color_index in 0..NUM_TILE_COLORS {
-                factories[factory_index][color_index] =
-                    ((factory_binary >> (color_index * 4)) & 0b1111) as u8;
-            }
-        }
-        for color_index in 0..NUM_TILE_COLORS {
-            let factory_string = factories_strings
-                .split('-')
-                .nth(CENTER_FACTORY_INDEX)
-                .ok_or("No center factory")?;
-            let factory_binary = factory_string
-                .parse::<u64>()
-                .map_err(|_| "Invalid factory")?;
-
-            factories[CENTER_FACTORY_INDEX][color_index] =
-                ((factory_binary >> (color_index * 8)) & 0xFF) as u8;
-        }
-
-        let scores_binary = entries.get(6).ok_or("No scores")?;
-        let scores_binary = scores_binary
-            .parse::<usize>()
-            .map_err(|_| "Invalid scores")?;
-        let mut scores = [0; NUM_PLAYERS];
-
-        for (player_index, player_score) in scores.iter_mut().enumerate() {
+        let factories_strings = field(&entries, 5, "factories")?;
+        let factories_strings: Vec<&str> = factories_strings.split('-').collect();
+        let mut factories = Factories::empty(num_players);
+        if factories_strings.len() != num_factories_for_players(num_players) {
+            return Err(GameStateParseError::FactoryCountMismatch {
+                num_players,
+                expected: num_factories_for_players(num_players),
+                found: factories_strings.len(),
+            });
+        }
+        let factory_indices: Vec<usize> = factories.active_indices().collect();
+        for (factory_index, factory_string) in factory_indices.into_iter().zip(factories_strings) {
+            let factory_binary: u128 = parse_field(factory_string, "factory")?;
+            let (bits_per_color, color_mask) = if factory_index == CENTER_FACTORY_INDEX {
+                (8, 0xFF)
+            } else {
+                (4, 0b1111)
+            };
+            for (color_index, number_of_tiles) in factories[factory_index].iter_mut().enumerate() {
+                *number_of_tiles =
+                    ((factory_binary >> (color_index * bits_per_color)) & color_mask) as u8;
+            }
+        }
+
+        let scores_binary: usize = parse_field(field(&entries, 6, "scores")?, "scores")?;
+        let mut scores = [0; MAX_PLAYERS];
+
+        for (player_index, player_score) in scores.iter_mut().enumerate().take(num_players) {
             *player_score = ((scores_binary >> (player_index * 16)) & 0xFFFF) as i16 - 1000;
         }
 
-        let floor_line_progress_binary = entries.get(7).ok_or("No floor line progress")?;
-        let floor_line_progress_binary = floor_line_progress_binary
-            .parse::<usize>()
-            .map_err(|_| "Invalid floor line progress")?;
+        let floor_line_progress_binary: usize = parse_field(
+            field(&entries, 7, "floor line progress")?,
+            "floor line progress",
+        )?;
 
-        let mut floor_line_progress = [0; NUM_PLAYERS];
-        for (player_index, progress) in floor_line_progress.iter_mut().enumerate() {
+        let mut floor_line_progress = [0; MAX_PLAYERS];
+        for (player_index, progress) in floor_line_progress.iter_mut().enumerate().take(num_players)
+        {
             *progress = ((floor_line_progress_binary >> (player_index * 8)) & 0xFF) as u8;
         }
 
-        let walls_strings = entries.get(8).ok_or("No walls")?;
-        let mut walls = [[0; NUM_TILE_COLORS]; NUM_PLAYERS];
+        // Strings without a ruleset were written before rulesets existed and use the default placement
+        let ruleset = match entries.get(12) {
+            Some(ruleset) => Ruleset::from_bits(parse_field(ruleset, "ruleset")?)
+                .ok_or_else(|| GameStateParseError::invalid_field("ruleset", ruleset))?,
+            None => Ruleset::default(),
+        };
+
+        let walls_strings = field(&entries, 8, "walls")?;
+        let mut walls = [[0; NUM_TILE_COLORS]; MAX_PLAYERS];
 
-        for (wall_index, wall_string) in walls_strings.split('-').enumerate() {
-            let wall_binary = wall_string.parse::<u32>().map_err(|_| "Invalid wall")?;
-            for (color_index, color_mask) in WALL_COLOR_MASKS.iter().enumerate() {
-                walls[wall_index][color_index] = wall_binary & color_mask;
+        for (wall_index, wall_string) in walls_strings.split('-').enumerate().take(num_players) {
+            if ruleset.is_free_wall() {
+                let color_strings: Vec<&str> = wall_string.split(':').collect();
+                if color_strings.len() != NUM_TILE_COLORS {
+                    return Err(GameStateParseError::invalid_field("wall", wall_string));
+                }
+                for (color_index, color_string) in color_strings.into_iter().enumerate() {
+                    walls[wall_index][color_index] = parse_field(color_string, "wall")?;
+                }
+            } else {
+                let wall_binary: u32 = parse_field(wall_string, "wall")?;
+                for (color_index, color_mask) in WALL_COLOR_MASKS.iter().enumerate() {
+                    walls[wall_index][color_index] = wall_binary & color_mask;
+                }
+            }
+            // Tiles outside of the 5x5 wall have no zobrist key
+            if walls[wall_index]
+                .iter()
+                .any(|bitboard| bitboard & !wall::VALID_WALL_TILES > 0)
+            {
+                return Err(GameStateParseError::invalid_field("wall", wall_string));
             }
         }
 
-        let pattern_lines_strings = entries.get(9).ok_or("No pattern lines")?;
-        let mut pattern_lines_occupancy = [[0; 5]; NUM_PLAYERS];
-        for (player_index, pattern_string) in pattern_lines_strings.split('-').enumerate() {
-            let pattern_binary = pattern_string
-                .parse::<u64>()
-                .map_err(|_| "Invalid pattern")?;
-            for line_index in 0..5 {
-                pattern_lines_occupancy[player_index][line_index] =
-                    ((pattern_binary >> (line_index * 8)) & 0xFF) as u8;
+        let pattern_lines_strings = field(&entries, 9, "pattern lines")?;
+        let mut pattern_lines_occupancy = [[0; 5]; MAX_PLAYERS];
+        for (player_index, pattern_string) in pattern_lines_strings
+            .split('-')
+            .enumerate()
+            .take(num_players)
+        {
+            let pattern_binary: u64 = parse_field(pattern_string, "pattern lines")?;
+            for (line_index, occupancy) in
+                pattern_lines_occupancy[player_index].iter_mut().enumerate()
+            {
+                *occupancy = ((pattern_binary >> (line_index * 8)) & 0xFF) as u8;
             }
         }
 
-        let pattern_lines_colors_strings = entries.get(10).ok_or("No pattern lines colors")?;
-        let mut pattern_lines_colors = [[Option::None; 5]; NUM_PLAYERS];
-        for (player_index, player_string) in pattern_lines_colors_strings.split('-').enumerate() {
-            let player_binary = player_string.parse::<u64>().map_err(|_| "Invalid player")?;
-            for line_index in 0..5 {
+        let pattern_lines_colors_strings = field(&entries, 10, "pattern line colors")?;
+        let mut pattern_lines_colors = [[Option::None; 5]; MAX_PLAYERS];
+        for (player_index, player_string) in pattern_lines_colors_strings
+            .split('-')
+            .enumerate()
+            .take(num_players)
+        {
+            let player_binary: u64 = parse_field(player_string, "pattern line colors")?;
+            for (line_index, line_color) in
+                pattern_lines_colors[player_index].iter_mut().enumerate()
+            {
                 let color = ((player_binary >> (line_index * 8)) & 0xFF) as u8;
-                if color == 255 {
-                    pattern_lines_colors[player_index][line_index] = None;
-                } else {
-                    pattern_lines_colors[player_index][line_index] = Some(TileColor::from(color));
-                }
+                *line_color = match color {
+                    255 => None,
+                    color if (color as usize) < NUM_TILE_COLORS => Some(TileColor::from(color)),
+                    _ => {
+                        return Err(GameStateParseError::invalid_field(
+                            "pattern line colors",
+                            player_string,
+                        ))
+                    }
+                };
             }
         }
 
-        let mut wall_occupancy = [0u32; NUM_PLAYERS];
+        let mut wall_occupancy = [0u32; MAX_PLAYERS];
         for (player_index, wall) in walls.iter().enumerate() {
             for bitboard in wall.iter() {
                 wall_occupancy[player_index] |= bitboard;
             }
         }
 
-        let tile_taken_from_center = entries.get(11).ok_or("No tile taken from center")?;
-        let tile_taken_from_center = tile_taken_from_center
-            .parse::<u8>()
-            .map_err(|_| "Invalid tile taken from center")?;
+        let tile_taken_from_center: u8 = parse_field(
+            field(&entries, 11, "tile taken from center")?,
+            "tile taken from center",
+        )?;
         let tile_taken_from_center = tile_taken_from_center == 1;
 
-        Ok(Self {
+        let mut game_state = Self {
+            ruleset,
+            num_players: number_of_players,
             current_player,
             bag,
             out_of_bag,
@@ -415,12 +1097,29 @@ impl GameState {
             pattern_lines_colors,
             next_round_starting_player,
             tile_taken_from_center,
-        })
+            hash: 0,
+        };
+        game_state.hash = game_state.compute_hash();
+        Ok(game_state)
     }
 
     pub fn evaluate_round(&mut self) -> bool {
+        self.evaluate_round_with_undo().0
+    }
+
+    pub fn evaluate_round_with_undo(&mut self) -> (bool, RoundUndo) {
+        let mut undo = RoundUndo {
+            scores: self.scores,
+            floor_line_progress: self.floor_line_progress,
+            evaluated_lines: [[None; 5]; MAX_PLAYERS],
+            current_player: self.current_player,
+            tile_taken_from_center: self.tile_taken_from_center,
+            hash: self.hash,
+        };
+        // Everything except the bag and the factories may change
+        let hash_before = self.hash_turn() ^ self.hash_bags() ^ self.hash_players();
         let mut is_game_over = false;
-        for player_index in 0..NUM_PLAYERS {
+        for player_index in 0..self.get_num_players() {
             let mut score: i16 = 0;
             // Find completed pattern lines
             for (pattern_line_index, no_tiles_in_pattern_line) in self.pattern_lines_occupancy
@@ -449,6 +1148,17 @@ impl GameState {
 
                 let pattern_line_color =
                     self.pattern_lines_colors[player_index][pattern_line_index].unwrap(); // Must be Some because the pattern line is full
+                undo.evaluated_lines[player_index][pattern_line_index] = Some(pattern_line_color);
+
+                if self.ruleset.is_free_wall() {
+                    // All lines that could be tiled were tiled by wall tiling moves already, the remaining tiles go to the floor line
+                    self.floor_line_progress[player_index] += *no_tiles_in_pattern_line;
+                    self.out_of_bag[pattern_line_color as usize] += *no_tiles_in_pattern_line;
+                    *no_tiles_in_pattern_line = 0;
+                    self.pattern_lines_colors[player_index][pattern_line_index] = None;
+                    continue;
+                }
+
                 let color_mask = wall::WALL_COLOR_MASKS[pattern_line_color as usize];
                 let new_tile = row_mask & color_mask;
                 let new_tile_pos = new_tile.trailing_zeros() as u8;
@@ -495,21 +1205,62 @@ impl GameState {
             self.evaluate_end_of_game();
         }
 
-        is_game_over
+        self.hash ^= hash_before ^ self.hash_turn() ^ self.hash_bags() ^ self.hash_players();
+
+        (is_game_over, undo)
+    }
+
+    pub fn undo_evaluate_round(&mut self, undo: RoundUndo) {
+        for player_index in 0..self.get_num_players() {
+            for (pattern_line_index, color) in undo.evaluated_lines[player_index].iter().enumerate()
+            {
+                let Some(color) = *color else {
+                    continue;
+                };
+                let tiles_in_line = pattern_line_index as u8 + 1;
+                if self.ruleset.is_free_wall() {
+                    // The line was moved to the floor line
+                    self.out_of_bag[color as usize] -= tiles_in_line;
+                } else {
+                    let new_tile =
+                        wall::get_row_mask(pattern_line_index) & WALL_COLOR_MASKS[color as usize];
+                    self.walls[player_index][color as usize] &= !new_tile;
+                    self.wall_occupancy[player_index] &= !new_tile;
+                    self.out_of_bag[color as usize] -= tiles_in_line - 1;
+                }
+                self.pattern_lines_occupancy[player_index][pattern_line_index] = tiles_in_line;
+                self.pattern_lines_colors[player_index][pattern_line_index] = Some(color);
+            }
+        }
+
+        self.scores = undo.scores;
+        self.floor_line_progress = undo.floor_line_progress;
+        self.current_player = undo.current_player;
+        self.tile_taken_from_center = undo.tile_taken_from_center;
+        self.hash = undo.hash;
+
+        #[cfg(debug_assertions)]
+        self.check_integrity().unwrap();
     }
 
     fn evaluate_end_of_game(&mut self) {
-        for (player, wall_occupancy) in self.wall_occupancy.iter().enumerate() {
+        let num_players = self.get_num_players();
+        for (player, wall_occupancy) in self.wall_occupancy.iter().enumerate().take(num_players) {
             let complete_rows = wall::count_complete_rows(*wall_occupancy);
             let complete_colums = wall::count_complete_columns(*wall_occupancy);
-            let complete_colors = wall::count_full_colors(*wall_occupancy);
-            let score =
-                complete_rows as i16 * 2 + complete_colums as i16 * 7 + complete_colors as i16 * 10;
+            // Counting the tiles per color works for both wall variants
+            let complete_colors = self.walls[player]
+                .iter()
+                .filter(|color_board| color_board.count_ones() == 5)
+                .count();
+            let score = complete_rows as i16 * EndOfGameBonus::ROW_POINTS
+                + complete_colums as i16 * EndOfGameBonus::COLUMN_POINTS
+                + complete_colors as i16 * EndOfGameBonus::COLOR_POINTS;
             self.scores[player] += score;
         }
     }
 
-    pub fn do_move(&mut self, mov: Move) {
+    pub fn do_move(&mut self, mov: Move) -> MoveUndo {
         #[cfg(debug_assertions)]
         {
             let mut move_list = MoveList::default();
@@ -522,8 +1273,41 @@ impl GameState {
                 println!("{:?}", move_list);
                 panic!("The move is not valid");
             }
+            if !self.ruleset.allows_placement(&mov.pattern) {
+                println!("{}", self);
+                println!("{}", mov);
+                panic!(
+                    "The move does not follow the {} placement rules",
+                    self.ruleset
+                );
+            }
         }
         let current_player: usize = self.current_player.into();
+        let undo = MoveUndo {
+            mov,
+            factory_content: if mov.is_wall_tiling() {
+                [0; NUM_TILE_COLORS]
+            } else {
+                self.factories[mov.take_from_factory_index as usize]
+            },
+            score: self.scores[current_player],
+            current_player: self.current_player,
+            next_round_starting_player: self.next_round_starting_player,
+            tile_taken_from_center: self.tile_taken_from_center,
+            hash: self.hash,
+        };
+        let hash_before = self.hash_move_features(mov, current_player);
+
+        if mov.is_wall_tiling() {
+            self.do_wall_tiling_move(mov);
+            self.hash ^= hash_before ^ self.hash_move_features(mov, current_player);
+
+            #[cfg(debug_assertions)]
+            self.check_integrity().unwrap();
+
+            return undo;
+        }
+
         let take_from_factory_index = mov.take_from_factory_index as usize;
         let color = mov.color as usize;
         let factory_content: [u8; 5] = self.factories[take_from_factory_index];
@@ -592,20 +1376,274 @@ impl GameState {
         self.floor_line_progress[current_player] += mov.pattern[5];
         self.out_of_bag[color] += mov.pattern[5]; // Discarded patterns are added to the out_of_bag. They will be put bag into the bag at the end of the round
 
-        // Advance the player
-        self.current_player = self.current_player.next();
+        // Advance the player. If this move ended the drafting on a free wall, the first player who has to tile a line is next
+        self.current_player = match self.get_pending_wall_tiling() {
+            Some((player, _)) => player,
+            None => self.current_player.next(self.get_num_players()),
+        };
+
+        self.hash ^= hash_before ^ self.hash_move_features(mov, current_player);
 
         #[cfg(debug_assertions)]
         self.check_integrity().unwrap();
+
+        undo
+    }
+
+    pub fn undo_move(&mut self, undo: MoveUndo) {
+        let mov = undo.mov;
+        let player: usize = undo.current_player.into();
+        let color = mov.color as usize;
+
+        if mov.is_wall_tiling() {
+            let (row, column) = mov.get_wall_tiling_field();
+            let new_tile = wall::field_at(row, column);
+            self.walls[player][color] &= !new_tile;
+            self.wall_occupancy[player] &= !new_tile;
+            self.out_of_bag[color] -= row as u8;
+            self.pattern_lines_occupancy[player][row] = row as u8 + 1;
+            self.pattern_lines_colors[player][row] = Some(mov.color);
+        } else {
+            let take_from_factory_index = mov.take_from_factory_index as usize;
+            if take_from_factory_index == CENTER_FACTORY_INDEX {
+                self.factories[CENTER_FACTORY_INDEX][color] = undo.factory_content[color];
+                if !undo.tile_taken_from_center {
+                    // The first player marker goes back to the center
+                    self.floor_line_progress[player] -= 1;
+                }
+            } else {
+                for (color_index, factory_content) in undo.factory_content.iter().enumerate() {
+                    if color_index != color {
+                        self.factories[CENTER_FACTORY_INDEX][color_index] -= factory_content;
+                    }
+                }
+                self.factories[take_from_factory_index] = undo.factory_content;
+            }
+
+            for pattern_line_index in 0..5 {
+                self.pattern_lines_occupancy[player][pattern_line_index] -=
+                    mov.pattern[pattern_line_index];
+                if self.pattern_lines_occupancy[player][pattern_line_index] == 0 {
+                    self.pattern_lines_colors[player][pattern_line_index] = None;
+                }
+            }
+            self.floor_line_progress[player] -= mov.pattern[5];
+            self.out_of_bag[color] -= mov.pattern[5];
+        }
+
+        self.scores[player] = undo.score;
+        self.current_player = undo.current_player;
+        self.next_round_starting_player = undo.next_round_starting_player;
+        self.tile_taken_from_center = undo.tile_taken_from_center;
+        self.hash = undo.hash;
+
+        #[cfg(debug_assertions)]
+        self.check_integrity().unwrap();
+    }
+
+    fn do_wall_tiling_move(&mut self, mov: Move) {
+        let current_player: usize = self.current_player.into();
+        let (row, column) = mov.get_wall_tiling_field();
+        let new_tile = wall::field_at(row, column);
+        let new_tile_pos = new_tile.trailing_zeros() as u8;
+
+        // The tile scores as soon as it is placed, as it would during the evaluation of the round
+        let score_for_tile =
+            wall::get_placed_tile_score(self.wall_occupancy[current_player], new_tile_pos);
+        self.scores[current_player] += score_for_tile as i16;
+
+        self.walls[current_player][mov.color as usize] |= new_tile;
+        self.wall_occupancy[current_player] |= new_tile;
+
+        // One tile of the line is placed on the wall, the others go into the lid
+        self.out_of_bag[mov.color as usize] += row as u8;
+        self.pattern_lines_occupancy[current_player][row] = 0;
+        self.pattern_lines_colors[current_player][row] = None;
+
+        if let Some((player, _)) = self.get_pending_wall_tiling() {
+            self.current_player = player;
+        }
+    }
+
+    fn find_wall_tiling_moves(
+        &self,
+        player_index: usize,
+        pattern_line_index: usize,
+        move_list: &mut MoveList,
+    ) {
+        let color = self.pattern_lines_colors[player_index][pattern_line_index].unwrap(); // Must be Some because the pattern line is full
+        let candidates = self.get_wall_tiling_candidates(player_index, pattern_line_index);
+        for column in 0..5 {
+            if candidates & wall::field_at(pattern_line_index, column) > 0 {
+                move_list.push(Move::new_wall_tiling(pattern_line_index, column, color));
+            }
+        }
+    }
+
+    // Explains why a move can't be played in this position, agrees with the moves from get_possible_moves
+    pub fn validate_move(&self, mov: Move) -> Result<(), IllegalMoveReason> {
+        let pending_wall_tiling = self.get_pending_wall_tiling();
+        if mov.is_wall_tiling() {
+            let (player, pattern_line_index) =
+                pending_wall_tiling.ok_or(IllegalMoveReason::NoWallTilingPending)?;
+            let lines: Vec<usize> = (0..5).filter(|&line| mov.pattern[line] > 0).collect();
+            if lines.len() != 1 || mov.pattern[lines[0]] > 5 || mov.pattern[5] > 0 {
+                return Err(IllegalMoveReason::MalformedWallTiling);
+            }
+            let (row, column) = mov.get_wall_tiling_field();
+            if row != pattern_line_index {
+                return Err(IllegalMoveReason::WrongWallTilingLine {
+                    expected: pattern_line_index,
+                    found: row,
+                });
+            }
+            let color = self.pattern_lines_colors[usize::from(player)][row].unwrap(); // The pattern line is full
+            if mov.color != color {
+                return Err(IllegalMoveReason::WrongWallTilingColor {
+                    expected: color,
+                    found: mov.color,
+                });
+            }
+            let candidates = self.get_wall_tiling_candidates(player.into(), row);
+            if candidates & wall::field_at(row, column) == 0 {
+                return Err(IllegalMoveReason::WallFieldUnavailable { row, column });
+            }
+            return Ok(());
+        }
+
+        if let Some((player, pattern_line_index)) = pending_wall_tiling {
+            return Err(IllegalMoveReason::WallTilingExpected {
+                player,
+                pattern_line_index,
+            });
+        }
+        if self.factories.is_empty() {
+            return Err(IllegalMoveReason::RoundOver);
+        }
+
+        let factory_index = mov.take_from_factory_index as usize;
+        if !self
+            .factories
+            .active_indices()
+            .any(|index| index == factory_index)
+        {
+            return Err(IllegalMoveReason::InvalidFactory(
+                mov.take_from_factory_index,
+            ));
+        }
+        let available = self.factories[factory_index][mov.color as usize];
+        if available == 0 {
+            return Err(IllegalMoveReason::ColorNotInFactory {
+                factory_index: mov.take_from_factory_index,
+                color: mov.color,
+            });
+        }
+        let placed: u32 = mov.pattern.iter().map(|&x| x as u32).sum();
+        if placed != available as u32 {
+            return Err(IllegalMoveReason::WrongTileCount { available, placed });
+        }
+
+        let current_player: usize = self.current_player.into();
+        let mut remaining_space = [0; 5];
+        for (pattern_line_index, space) in remaining_space.iter_mut().enumerate() {
+            // States loaded with deserialize_string aren't checked, an overfull line has no space left
+            *space = (pattern_line_index as u8 + 1)
+                .saturating_sub(self.pattern_lines_occupancy[current_player][pattern_line_index]);
+            let tiles = mov.pattern[pattern_line_index];
+            if tiles == 0 {
+                continue;
+            }
+            match self.pattern_lines_colors[current_player][pattern_line_index] {
+                Some(existing) if existing != mov.color => {
+                    return Err(IllegalMoveReason::PatternLineColorMismatch {
+                        pattern_line_index,
+                        existing,
+                    });
+                }
+                Some(_) => {}
+                None => {
+                    let color_board = self.walls[current_player][mov.color as usize];
+                    if color_board & wall::get_row_mask(pattern_line_index) > 0 {
+                        return Err(IllegalMoveReason::ColorAlreadyOnWall { pattern_line_index });
+                    }
+                }
+            }
+            if tiles > *space {
+                return Err(IllegalMoveReason::PatternLineOverflow {
+                    pattern_line_index,
+                    space: *space,
+                    placed: tiles,
+                });
+            }
+        }
+
+        if !self.ruleset.allows_placement(&mov.pattern) {
+            return Err(IllegalMoveReason::MultiplePatternLines);
+        }
+        // With the official placement only the tiles that don't fit into the chosen line are discarded
+        if self.ruleset.pattern_line_placement == PatternLinePlacement::Single && mov.pattern[5] > 0
+        {
+            if let Some(pattern_line_index) = (0..5).find(|&line| mov.pattern[line] > 0) {
+                if mov.pattern[pattern_line_index] < remaining_space[pattern_line_index] {
+                    return Err(IllegalMoveReason::PatternLineNotFilled { pattern_line_index });
+                }
+            }
+        }
+        Ok(())
     }
 
     pub fn get_possible_moves(
         &mut self,
         move_list: &mut MoveList,
         rng: &mut SmallRng,
+    ) -> MoveGenerationResult {
+        self.generate_moves(move_list, rng, false)
+    }
+
+    // Like get_possible_moves, but factories with the same tiles only get the moves of the first of them.
+    // Each of these moves stands for move_multiplicity moves that lead to equivalent positions.
+    pub fn get_canonical_moves(
+        &mut self,
+        move_list: &mut MoveList,
+        rng: &mut SmallRng,
+    ) -> MoveGenerationResult {
+        self.generate_moves(move_list, rng, true)
+    }
+
+    // Number of legal moves that are equivalent to this move, i.e. the same move from a factory with the same tiles
+    pub fn move_multiplicity(&self, mov: Move) -> u8 {
+        if mov.is_wall_tiling() {
+            1
+        } else {
+            self.factories
+                .multiplicity(mov.take_from_factory_index as usize)
+        }
+    }
+
+    // The move get_canonical_moves generates instead of this one
+    pub fn canonical_move(&self, mut mov: Move) -> Move {
+        if !mov.is_wall_tiling() {
+            mov.take_from_factory_index =
+                self.factories
+                    .equivalent_factory(mov.take_from_factory_index as usize) as u8;
+        }
+        mov
+    }
+
+    fn generate_moves(
+        &mut self,
+        move_list: &mut MoveList,
+        rng: &mut SmallRng,
+        canonical: bool,
     ) -> MoveGenerationResult {
         move_list.clear(); // Clear any remaining moves from the previous round
 
+        // On a free wall the tiling of the full pattern lines is decided by the players before the round is evaluated
+        if let Some((player, pattern_line_index)) = self.get_pending_wall_tiling() {
+            self.find_wall_tiling_moves(player.into(), pattern_line_index, move_list);
+            return MoveGenerationResult::Continue;
+        }
+
         let is_round_over = self.factories.is_empty();
         if is_round_over {
             let is_game_over = self.evaluate_round();
@@ -626,6 +1664,9 @@ impl GameState {
 
         // Iterate over all factory and all color combinations
         for (factory_index, factory) in self.factories.iter().enumerate() {
+            if canonical && self.factories.equivalent_factory(factory_index) != factory_index {
+                continue; // The same moves were generated for an earlier factory with the same tiles
+            }
             for (color, number) in factory.iter().enumerate() {
                 if *number == 0 {
                     continue; // Skip the move gen if there are no tiles of this color in the factory
@@ -645,25 +1686,33 @@ impl GameState {
                         }
                     } else {
                         // the pattern line did not have a color yet: We need to check whether we are allowed to place this color here
-                        // It is not possible to place a tile in a pattern line if the corresponding row in the wall is already full
-                        let wall_mask = WALL_COLOR_MASKS[color];
-                        let wall_occupancy = self.wall_occupancy[current_player];
+                        // It is not possible to place a tile in a pattern line if the color is already on the wall in the corresponding row
+                        let color_board = self.walls[current_player][color];
                         let row_mask: u32 = wall::get_row_mask(pattern_line_index);
-                        if wall_occupancy & row_mask & wall_mask > 0 {
+                        if color_board & row_mask > 0 {
                             remaining_space[pattern_line_index] = 0;
                         }
                     }
                 }
 
-                find_tile_combinations(
-                    *number,
-                    &mut [0, 0, 0, 0, 0, 0],
-                    &mut remaining_space,
-                    move_list,
-                    factory_index as u8,
-                    TileColor::from(color),
-                    0,
-                );
+                match self.ruleset.pattern_line_placement {
+                    PatternLinePlacement::Spread => find_tile_combinations(
+                        *number,
+                        &mut [0, 0, 0, 0, 0, 0],
+                        &mut remaining_space,
+                        move_list,
+                        factory_index as u8,
+                        TileColor::from(color),
+                        0,
+                    ),
+                    PatternLinePlacement::Single => find_single_line_placements(
+                        *number,
+                        &remaining_space,
+                        move_list,
+                        factory_index as u8,
+                        TileColor::from(color),
+                    ),
+                }
             }
         }
 
@@ -674,167 +1723,283 @@ impl GameState {
         }
     }
 
-    pub fn fill_factories(&mut self, rng: &mut SmallRng) {
+    pub fn fill_factories(&mut self, rng: &mut SmallRng) -> RefillUndo {
+        let undo = RefillUndo {
+            bag: self.bag,
+            out_of_bag: self.out_of_bag,
+            hash: self.hash,
+        };
+        let hash_before = self.hash_bags() ^ self.hash_factories();
         self.factories
             .refill_by_drawing_from_bag(&mut self.bag, &mut self.out_of_bag, rng);
+        self.hash ^= hash_before ^ self.hash_bags() ^ self.hash_factories();
+        undo
+    }
+
+    // Draws a refill from the bag and lid of this game state without changing it
+    pub fn sample_refill(&self, rng: &mut SmallRng) -> RefillOutcome {
+        Factories::sample_refill(self.get_num_players(), &self.bag, &self.out_of_bag, rng)
+    }
+
+    // Probability that the factories are refilled with this content, the factories of this game state have to be empty
+    pub fn refill_probability(&self, factories: &Factories) -> f64 {
+        factories.refill_probability(&self.bag, &self.out_of_bag)
     }
 
+    // Refills the factories with a known draw, e.g. from a game record. The draw has to be possible with the tiles in the bag,
+    // if it uses more tiles than the bag holds, the bag was emptied first and the rest was drawn after the lid was put back into it.
+    pub fn fill_factories_with(&mut self, factories: &Factories) -> Result<RefillUndo, GameError> {
+        if !self.factories.is_empty()
+            || factories.num_factories() != self.factories.num_factories()
+            || factories[CENTER_FACTORY_INDEX].iter().any(|&x| x > 0)
+            || factories
+                .iter()
+                .any(|factory| factory.iter().map(|&x| x as u32).sum::<u32>() > 4)
+        {
+            return Err(GameError::ImpossibleRefill);
+        }
+        // With at most 4 tiles in each of the 9 factories, no count overflows
+        let mut drawn = [0u8; NUM_TILE_COLORS];
+        for factory in factories.iter() {
+            for (color, &number_of_tiles) in factory.iter().enumerate() {
+                drawn[color] += number_of_tiles;
+            }
+        }
+
+        let tiles_in_bag = self.bag.iter().map(|&x| x as u32).sum::<u32>();
+        let drawn_tiles = drawn.iter().map(|&x| x as u32).sum::<u32>();
+        let (bag, out_of_bag) = if drawn_tiles < tiles_in_bag {
+            let mut bag = self.bag;
+            for (bag_tiles, &drawn_tiles) in bag.iter_mut().zip(drawn.iter()) {
+                *bag_tiles = bag_tiles
+                    .checked_sub(drawn_tiles)
+                    .ok_or(GameError::ImpossibleRefill)?;
+            }
+            (bag, self.out_of_bag)
+        } else {
+            let mut bag = self.out_of_bag;
+            for color in 0..NUM_TILE_COLORS {
+                bag[color] = drawn[color]
+                    .checked_sub(self.bag[color])
+                    .and_then(|drawn_from_lid| bag[color].checked_sub(drawn_from_lid))
+                    .ok_or(GameError::ImpossibleRefill)?;
+            }
+            (bag, [0; NUM_TILE_COLORS])
+        };
+
+        let undo = RefillUndo {
+            bag: self.bag,
+            out_of_bag: self.out_of_bag,
+            hash: self.hash,
+        };
+        let hash_before = self.hash_bags() ^ self.hash_factories();
+        self.bag = bag;
+        self.out_of_bag = out_of_bag;
+        self.factories = factories.clone();
+        self.hash ^= hash_before ^ self.hash_bags() ^ self.hash_factories();
+        Ok(undo)
+    }
+
+    pub fn undo_fill_factories(&mut self, undo: RefillUndo) {
+        self.factories = Factories::empty(self.get_num_players());
+        self.bag = undo.bag;
+        self.out_of_bag = undo.out_of_bag;
+        self.hash = undo.hash;
+    }
+
+    // Checks all invariants of the position and returns every violation that was found
     pub fn check_integrity(&self) -> Result<(), GameError> {
-        let mut is_valid = true;
+        let violations = self.integrity_violations();
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(GameError::InvalidGameState(violations))
+        }
+    }
+
+    pub fn integrity_violations(&self) -> Vec<IntegrityViolation> {
+        let mut violations = Vec::new();
+        let num_players = self.get_num_players();
 
         // Make sure the bag has less or equal than 20 tiles of each color
-        for color in self.bag.iter() {
-            if *color > 20 {
-                is_valid = false;
-                println!("Bag has more than 20 tiles of a color");
+        for (color, &count) in self.bag.iter().enumerate() {
+            if count > 20 {
+                violations.push(IntegrityViolation::BagOverflow {
+                    color: TileColor::from(color),
+                    count,
+                });
             }
         }
 
-        // Make sure the factories have 0 or 4 tiles (Except the center)
-        for factory in self.factories.iter().take(CENTER_FACTORY_INDEX) {
-            let mut tile_count = 0;
-            for color in factory.iter() {
-                tile_count += color;
-            }
-            if tile_count > 4 {
-                // In the rare case that you run out of tiles again while there are none left in the lid, start the new round as usual even though not all Factory displays are properly filled.
-                is_valid = false;
-                println!("Factory has {} tiles", tile_count);
+        // Make sure the factories have at most 4 tiles (Except the center)
+        // In the rare case that you run out of tiles again while there are none left in the lid, start the new round as usual even though not all Factory displays are properly filled.
+        for (factory_index, factory) in self.factories.iter().take(CENTER_FACTORY_INDEX).enumerate()
+        {
+            let count: u32 = factory.iter().map(|&x| x as u32).sum();
+            if count > 4 {
+                violations.push(IntegrityViolation::FactoryOverflow {
+                    factory_index,
+                    count,
+                });
             }
         }
 
-        // Make sure there are no tiles of different colors on the same position on the wall
-        for player in 0..NUM_PLAYERS {
+        for player in 0..num_players {
             let mut occupancy: u32 = 0b0;
-            for color in 0..NUM_TILE_COLORS {
-                let bitboard = self.walls[player][color];
+            for (color, &bitboard) in self.walls[player].iter().enumerate() {
+                let color = TileColor::from(color);
+                // Make sure there are no tiles of different colors on the same position on the wall
                 if bitboard & occupancy > 0 {
-                    is_valid = false;
-                    println!(
-                        "Player {} has tiles of different colors on the same position on the wall",
-                        player
-                    );
-                }
-                if self.wall_occupancy[player] & bitboard != bitboard {
-                    is_valid = false;
-                    println!(
-                        "Player {} has tiles on the wall that are not in the occupancy",
-                        player
-                    );
+                    violations.push(IntegrityViolation::OverlappingWallTiles { player, color });
                 }
                 occupancy |= bitboard;
+
+                // Make sure the wall follows the pattern of the wall variant
+                if bitboard & !wall::VALID_WALL_TILES > 0 {
+                    violations.push(IntegrityViolation::TileOutsideWall { player, color });
+                }
+                let follows_pattern = if self.ruleset.is_free_wall() {
+                    // Every row and every column holds at most one tile of each color
+                    (0..5).all(|i| {
+                        (bitboard & wall::get_row_mask(i)).count_ones() <= 1
+                            && (bitboard & (wall::COLUMN_MASK << i)).count_ones() <= 1
+                    })
+                } else {
+                    bitboard & WALL_COLOR_MASKS[color as usize] == bitboard
+                };
+                if !follows_pattern {
+                    violations.push(IntegrityViolation::WallPatternViolation { player, color });
+                }
             }
-        }
 
-        // Make sure occupancy is correct
-        for player in 0..NUM_PLAYERS {
-            let mut calculated_occupancy: u32 = 0b0;
-            for color in 0..NUM_TILE_COLORS {
-                let bitboard = self.walls[player][color];
-                calculated_occupancy |= bitboard;
-            }
-            if calculated_occupancy != self.wall_occupancy[player] {
-                is_valid = false;
-                println!(
-                    "Player {} has wrong occupancy. Expected: {:b} Actual: {:b}",
-                    player, calculated_occupancy, self.wall_occupancy[player]
-                );
+            // Make sure occupancy is correct
+            if occupancy != self.wall_occupancy[player] {
+                violations.push(IntegrityViolation::WallOccupancyMismatch {
+                    player,
+                    expected: occupancy,
+                    found: self.wall_occupancy[player],
+                });
             }
         }
 
         // Check pattern line color assignment / occupancy match
-        for player in 0..NUM_PLAYERS {
-            for pattern_line in 0..5 {
-                let color = self.pattern_lines_colors[player][pattern_line];
-                match color {
-                    None => {
-                        if self.pattern_lines_occupancy[player][pattern_line] != 0 {
-                            println!("There are tiles in pattern line {} of player {} but they don't have a color", pattern_line, player);
-                            is_valid = false;
-                        }
+        for player in 0..num_players {
+            for pattern_line_index in 0..5 {
+                let count = self.pattern_lines_occupancy[player][pattern_line_index];
+                match self.pattern_lines_colors[player][pattern_line_index] {
+                    None if count != 0 => {
+                        violations.push(IntegrityViolation::PatternLineWithoutColor {
+                            player,
+                            pattern_line_index,
+                            count,
+                        });
                     }
-                    Some(_color) => {
-                        if self.pattern_lines_occupancy[player][pattern_line] == 0 {
-                            println!("There are no tiles in pattern line {} of player {} but the line has a color {:?}", pattern_line, player, color);
-                            is_valid = false;
+                    None => {}
+                    Some(color) => {
+                        if count == 0 {
+                            violations.push(IntegrityViolation::EmptyPatternLineWithColor {
+                                player,
+                                pattern_line_index,
+                                color,
+                            });
+                        }
+                        // A color can only be placed in a pattern line if it is not on the wall in that row yet
+                        let row_mask = wall::get_row_mask(pattern_line_index);
+                        if self.walls[player][color as usize] & row_mask > 0 {
+                            violations.push(IntegrityViolation::PatternColorOnWall {
+                                player,
+                                pattern_line_index,
+                                color,
+                            });
                         }
                     }
                 }
+                if count as usize > pattern_line_index + 1 {
+                    violations.push(IntegrityViolation::PatternLineOverflow {
+                        player,
+                        pattern_line_index,
+                        count,
+                    });
+                }
             }
         }
 
-        // Count the entire number of tiles in the game
-        let mut tile_count = self.bag;
-        for factory in self.factories.iter() {
-            for (color, number) in factory.iter().enumerate() {
-                tile_count[color] += number;
-            }
-        }
-
-        for (color, num) in self.out_of_bag.iter().enumerate() {
-            tile_count[color] += num;
-        }
-
-        for player in 0..NUM_PLAYERS {
-            for pattern_line_index in 0..5 {
-                let color = self.pattern_lines_colors[player][pattern_line_index];
-                if let Some(color) = color {
-                    let color = color as usize;
-                    tile_count[color] += self.pattern_lines_occupancy[player][pattern_line_index];
+        // Count the entire number of tiles of each color in the game, there are 20 of each
+        for (color, (&bag, &out_of_bag)) in self.bag.iter().zip(self.out_of_bag.iter()).enumerate()
+        {
+            let tile_color = TileColor::from(color);
+            let mut counts = TileCounts {
+                bag: bag as u32,
+                out_of_bag: out_of_bag as u32,
+                factories: self.factories.iter().map(|f| f[color] as u32).sum(),
+                pattern_lines: 0,
+                walls: 0,
+            };
+            for player in 0..num_players {
+                for pattern_line_index in 0..5 {
+                    if self.pattern_lines_colors[player][pattern_line_index] == Some(tile_color) {
+                        counts.pattern_lines +=
+                            self.pattern_lines_occupancy[player][pattern_line_index] as u32;
+                    }
                 }
+                counts.walls += self.walls[player][color].count_ones();
             }
-        }
-
-        for player in 0..NUM_PLAYERS {
-            for (color, wall) in self.walls[player].iter().enumerate() {
-                tile_count[color] += wall.count_ones() as u8;
+            if counts.total() != 20 {
+                violations.push(IntegrityViolation::TileConservation {
+                    color: tile_color,
+                    counts,
+                });
             }
         }
 
-        // Make sure the total number of tiles is 20 for each
-        let sum_tiles_in_game: u8 = tile_count.iter().sum();
-        if sum_tiles_in_game != 100 {
-            println!(
-                "The sum of the tiles does not add up to 100. {:?} = {}",
-                tile_count,
-                tile_count.iter().sum::<u8>(),
-            );
-            println!("{}", self);
-            is_valid = false;
-        }
-        for (color, number) in tile_count.iter().enumerate() {
-            if *number > 20 {
-                is_valid = false;
-                println!(
-                    "There are {} tiles of color {} in the game",
-                    number,
-                    TileColor::from(color as u8)
-                );
-            }
+        let hash = self.compute_hash();
+        if hash != self.hash {
+            violations.push(IntegrityViolation::HashMismatch {
+                stored: self.hash,
+                computed: hash,
+            });
         }
 
-        match is_valid {
-            true => Ok(()),
-            false => Err(GameError::InvalidGameState),
-        }
+        violations
+    }
+
+    pub fn new(num_players: usize, rng: &mut SmallRng) -> Self {
+        Self::new_with_ruleset(num_players, Ruleset::default(), rng)
+    }
+
+    pub fn new_with_ruleset(num_players: usize, ruleset: Ruleset, rng: &mut SmallRng) -> Self {
+        let mut ret = Self::new_without_factories(num_players, ruleset);
+        ret.fill_factories(rng);
+        ret
     }
 
-    pub fn new(rng: &mut SmallRng) -> Self {
+    // The start of a game before the factories are filled for the first round
+    pub fn new_without_factories(num_players: usize, ruleset: Ruleset) -> Self {
+        assert!(
+            (MIN_PLAYERS..=MAX_PLAYERS).contains(&num_players),
+            "Number of players must be between {} and {}, got {}",
+            MIN_PLAYERS,
+            MAX_PLAYERS,
+            num_players
+        );
         let mut ret = Self {
+            ruleset,
+            num_players: num_players as u8,
             bag: [20, 20, 20, 20, 20],
             out_of_bag: [0; NUM_TILE_COLORS],
-            factories: Factories::empty(),
-            scores: [0; NUM_PLAYERS],
-            floor_line_progress: [0; NUM_PLAYERS],
-            walls: [[0; NUM_TILE_COLORS]; NUM_PLAYERS],
-            wall_occupancy: [0; NUM_PLAYERS],
+            factories: Factories::empty(num_players),
+            scores: [0; MAX_PLAYERS],
+            floor_line_progress: [0; MAX_PLAYERS],
+            walls: [[0; NUM_TILE_COLORS]; MAX_PLAYERS],
+            wall_occupancy: [0; MAX_PLAYERS],
             current_player: PlayerMarker::new(0),
-            pattern_lines_occupancy: [[0; 5]; NUM_PLAYERS],
-            pattern_lines_colors: [[None; 5]; NUM_PLAYERS],
+            pattern_lines_occupancy: [[0; 5]; MAX_PLAYERS],
+            pattern_lines_colors: [[None; 5]; MAX_PLAYERS],
             next_round_starting_player: PlayerMarker::new(0),
             tile_taken_from_center: false,
+            hash: 0,
         };
-        ret.fill_factories(rng);
+        ret.hash = ret.compute_hash();
         ret
     }
 }
@@ -850,14 +2015,21 @@ impl std::fmt::Display for GameState {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_support::{random_games, random_positions};
     use rand::{Rng, SeedableRng};
 
     #[test]
     fn test_serialize_deserialize() {
         let mut move_list = MoveList::default();
-        for _ in 0..20 {
-            let mut rng: rand::rngs::SmallRng = SeedableRng::seed_from_u64(0);
-            let mut game_state = GameState::new(&mut rng);
+        for i in 0..20 {
+            let mut rng: rand::rngs::SmallRng = SeedableRng::seed_from_u64(i);
+            let num_players = MIN_PLAYERS + i as usize % (MAX_PLAYERS - MIN_PLAYERS + 1);
+            let ruleset = match i % 3 {
+                0 => Ruleset::default(),
+                1 => Ruleset::OFFICIAL,
+                _ => Ruleset::OFFICIAL_FREE_WALL,
+            };
+            let mut game_state = GameState::new_with_ruleset(num_players, ruleset, &mut rng);
             loop {
                 game_state.check_integrity().unwrap();
 
@@ -876,6 +2048,16 @@ mod tests {
                 let string = game_state.serialize_string();
                 let reconstructed_game_state =
                     GameState::deserialize_string(string.as_str()).unwrap();
+                assert_eq!(game_state, reconstructed_game_state);
+                assert_eq!(game_state.hash, reconstructed_game_state.hash, "Hash");
+                assert_eq!(
+                    game_state.num_players, reconstructed_game_state.num_players,
+                    "Number of players"
+                );
+                assert_eq!(
+                    game_state.ruleset, reconstructed_game_state.ruleset,
+                    "Ruleset"
+                );
                 assert_eq!(game_state.bag, reconstructed_game_state.bag, "Bag");
                 assert_eq!(
                     game_state.out_of_bag, reconstructed_game_state.out_of_bag,
@@ -916,4 +2098,415 @@ mod tests {
             }
         }
     }
+    #[test]
+    fn test_deserialize_rejects_invalid_player_count() {
+        let mut rng: rand::rngs::SmallRng = SeedableRng::seed_from_u64(0);
+        let string = GameState::new(2, &mut rng).serialize_string();
+        let string = format!("5{}", &string[1..]);
+        assert_eq!(
+            GameState::deserialize_string(&string),
+            Err(GameStateParseError::PlayerCountOutOfRange(5))
+        );
+    }
+
+    #[test]
+    fn test_deserialize_rejects_factory_count_mismatch() {
+        let mut rng: rand::rngs::SmallRng = SeedableRng::seed_from_u64(0);
+        let string = GameState::new(2, &mut rng).serialize_string();
+        // A 2 player string claiming to be a 3 player game has too few factories
+        let string = format!("3{}", &string[1..]);
+        assert_eq!(
+            GameState::deserialize_string(&string),
+            Err(GameStateParseError::FactoryCountMismatch {
+                num_players: 3,
+                expected: 8,
+                found: 6
+            })
+        );
+    }
+
+    #[test]
+    fn test_deserialize_names_invalid_field() {
+        let mut rng: rand::rngs::SmallRng = SeedableRng::seed_from_u64(0);
+        let string = GameState::new(2, &mut rng).serialize_string();
+        let entries: Vec<&str> = string.split('_').collect();
+        let replace_entry = |index: usize, value: &str| {
+            let mut entries = entries.clone();
+            entries[index] = value;
+            entries.join("_")
+        };
+
+        assert_eq!(
+            GameState::deserialize_string(&entries[..4].join("_")),
+            Err(GameStateParseError::MissingField("out of bag"))
+        );
+        assert_eq!(
+            GameState::deserialize_string(&replace_entry(1, "2")),
+            Err(GameStateParseError::InvalidField {
+                field: "current player",
+                value: "2".to_string()
+            })
+        );
+        assert_eq!(
+            GameState::deserialize_string(&replace_entry(6, "x")),
+            Err(GameStateParseError::InvalidField {
+                field: "scores",
+                value: "x".to_string()
+            })
+        );
+        // A tile outside of the 5x5 wall, only free walls store the bitboards unmasked
+        let mut free_wall_entries = entries.clone();
+        free_wall_entries[8] = "32:0:0:0:0-0:0:0:0:0";
+        free_wall_entries[12] = "2";
+        assert_eq!(
+            GameState::deserialize_string(&free_wall_entries.join("_")),
+            Err(GameStateParseError::InvalidField {
+                field: "wall",
+                value: "32:0:0:0:0".to_string()
+            })
+        );
+        assert_eq!(
+            GameState::deserialize_string(&replace_entry(12, "4")),
+            Err(GameStateParseError::InvalidField {
+                field: "ruleset",
+                value: "4".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_validate_move() {
+        for position in random_positions(8) {
+            let game_state = &position.game_state;
+            // Moves close to the legal ones have to be accepted exactly if they are generated
+            let mut candidates = Vec::new();
+            for move_ in &position.moves {
+                if move_.is_wall_tiling() {
+                    for row in 0..5 {
+                        for column in 0..5 {
+                            for color in 0..NUM_TILE_COLORS {
+                                candidates.push(Move::new_wall_tiling(
+                                    row,
+                                    column,
+                                    TileColor::from(color),
+                                ));
+                            }
+                        }
+                    }
+                    continue;
+                }
+                for from in 0..6 {
+                    for to in 0..6 {
+                        if move_.pattern[from] > 0 && from != to {
+                            let mut candidate = *move_;
+                            candidate.pattern[from] -= 1;
+                            candidate.pattern[to] += 1;
+                            candidates.push(candidate);
+                        }
+                    }
+                }
+                for factory_index in 0..=CENTER_FACTORY_INDEX as u8 {
+                    for color in 0..NUM_TILE_COLORS {
+                        candidates.push(Move {
+                            take_from_factory_index: factory_index,
+                            color: TileColor::from(color),
+                            pattern: move_.pattern,
+                        });
+                    }
+                }
+            }
+            for candidate in candidates {
+                assert_eq!(
+                    game_state.validate_move(candidate).is_ok(),
+                    position.moves.contains(&candidate),
+                    "{} {:?}",
+                    candidate,
+                    game_state.validate_move(candidate)
+                );
+            }
+            assert!(game_state.validate_move(Move::DUMMY).is_err());
+        }
+    }
+
+    #[test]
+    fn test_validate_move_in_overfull_pattern_line() {
+        let mut rng: rand::rngs::SmallRng = SeedableRng::seed_from_u64(0);
+        let mut game_state = GameState::new(2, &mut rng);
+        game_state.pattern_lines_occupancy[0][0] = 3;
+        game_state.pattern_lines_colors[0][0] = Some(TileColor::Blue);
+        let game_state = GameState::deserialize_string(&game_state.serialize_string()).unwrap();
+        assert!(game_state.check_integrity().is_err());
+
+        let (factory_index, factory) = game_state
+            .factories
+            .iter()
+            .enumerate()
+            .find(|(_, factory)| factory[TileColor::Blue as usize] > 0)
+            .unwrap();
+        let mut pattern = [0; 6];
+        pattern[0] = 1;
+        pattern[5] = factory[TileColor::Blue as usize] - 1;
+        let mov = Move {
+            take_from_factory_index: factory_index as u8,
+            color: TileColor::Blue,
+            pattern,
+        };
+        assert_eq!(
+            game_state.validate_move(mov),
+            Err(IllegalMoveReason::PatternLineOverflow {
+                pattern_line_index: 0,
+                space: 0,
+                placed: 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_deserialize_legacy_string_without_ruleset() {
+        let mut rng: rand::rngs::SmallRng = SeedableRng::seed_from_u64(0);
+        let game_state = GameState::new_with_ruleset(2, Ruleset::OFFICIAL, &mut rng);
+        let string = game_state.serialize_string();
+        let legacy_string = &string[..string.rfind('_').unwrap()];
+        let reconstructed_game_state = GameState::deserialize_string(legacy_string).unwrap();
+        assert_eq!(reconstructed_game_state.ruleset, Ruleset::default());
+    }
+
+    #[test]
+    fn test_official_move_generation() {
+        for position in random_positions(20) {
+            let game_state = &position.game_state;
+            if game_state.ruleset.pattern_line_placement != PatternLinePlacement::Single {
+                continue;
+            }
+            for move_ in &position.moves {
+                let used_pattern_lines = move_.pattern.iter().take(5).filter(|&&x| x > 0);
+                assert!(used_pattern_lines.count() <= 1, "{}", move_);
+                if move_.is_wall_tiling() {
+                    continue;
+                }
+                let taken_tiles = game_state.factories[move_.take_from_factory_index as usize]
+                    [move_.color as usize];
+                assert_eq!(move_.pattern.iter().sum::<u8>(), taken_tiles, "{}", move_);
+            }
+            // Discarding everything is always possible, unless a full pattern line is tiled on the free wall
+            if game_state.get_pending_wall_tiling().is_none() {
+                assert!(position.moves.iter().any(|m| m.is_discard_only()));
+            }
+
+            let mut game_state = game_state.clone();
+            game_state.do_move(position.move_);
+            game_state.check_integrity().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_free_wall_tiling() {
+        let free_wall_games =
+            random_games(20).filter(|game| game.final_state.ruleset.is_free_wall());
+        for game in free_wall_games {
+            let mut num_wall_tiling_moves = 0;
+            for position in &game.positions {
+                let game_state = &position.game_state;
+                if let Some((player, pattern_line_index)) = game_state.get_pending_wall_tiling() {
+                    assert_eq!(game_state.current_player, player);
+                    assert!(!position.moves.is_empty());
+                    for move_ in &position.moves {
+                        assert!(move_.is_wall_tiling(), "{}", move_);
+                        assert_eq!(move_.get_wall_tiling_field().0, pattern_line_index);
+                        assert_eq!(
+                            Move::deserialize_string(&move_.serialize_string()).unwrap(),
+                            *move_
+                        );
+                    }
+                    num_wall_tiling_moves += 1;
+                } else {
+                    assert!(position.moves.iter().all(|m| !m.is_wall_tiling()));
+                }
+
+                let mut game_state = game_state.clone();
+                game_state.do_move(position.move_);
+                game_state.check_integrity().unwrap();
+            }
+            assert!(num_wall_tiling_moves > 0);
+
+            // No color may repeat in a row or column
+            for wall in game.final_state.get_walls() {
+                for color_board in wall {
+                    for i in 0..5 {
+                        assert!((color_board & wall::get_row_mask(i)).count_ones() <= 1);
+                        assert!((color_board & (wall::COLUMN_MASK << i)).count_ones() <= 1);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_undo() {
+        let mut rng: rand::rngs::SmallRng = SeedableRng::seed_from_u64(0);
+        for game in random_games(12) {
+            let round_ends = game
+                .positions
+                .iter()
+                .map(|position| &position.before_generation)
+                .chain([&game.end])
+                .filter(|game_state| {
+                    game_state.factories.is_empty()
+                        && game_state.get_pending_wall_tiling().is_none()
+                });
+            for game_state in round_ends {
+                let mut game_state = game_state.clone();
+                let before = game_state.serialize_string();
+                let (_, round_undo) = game_state.evaluate_round_with_undo();
+                let refill_undo = game_state.fill_factories(&mut rng);
+                game_state.undo_fill_factories(refill_undo);
+                game_state.undo_evaluate_round(round_undo);
+                assert_eq!(game_state.serialize_string(), before);
+            }
+
+            for position in &game.positions {
+                // Every move has to be undone exactly, not only the one that is played
+                let mut game_state = position.game_state.clone();
+                let before = game_state.serialize_string();
+                for move_ in &position.moves {
+                    let undo = game_state.do_move(*move_);
+                    game_state.undo_move(undo);
+                    assert_eq!(game_state.serialize_string(), before, "{}", move_);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_hash() {
+        let mut positions: std::collections::HashMap<u64, String> = Default::default();
+        for game in random_games(12) {
+            let mut seen_positions = std::collections::HashSet::new();
+            for position in &game.positions {
+                let mut game_state = position.game_state.clone();
+                assert_eq!(game_state.hash, game_state.compute_hash());
+
+                // Different positions must not share a hash
+                let string = game_state.serialize_string();
+                let known_string = positions.entry(game_state.hash).or_insert(string.clone());
+                assert_eq!(*known_string, string);
+                seen_positions.insert(game_state.clone());
+
+                let before = game_state.clone();
+                let undo = game_state.do_move(position.move_);
+                assert_eq!(game_state.hash, game_state.compute_hash());
+                assert_ne!(game_state, before);
+                game_state.undo_move(undo);
+                assert_eq!(game_state, before);
+            }
+
+            // The positions can be found by an equal position that was built independently
+            for position in seen_positions.iter() {
+                let copy = GameState::deserialize_string(&position.serialize_string()).unwrap();
+                assert!(seen_positions.contains(&copy));
+            }
+        }
+    }
+
+    #[test]
+    fn test_canonical_moves() {
+        let mut rng: rand::rngs::SmallRng = SeedableRng::seed_from_u64(0);
+        let mut canonical_move_list = MoveList::default();
+        for position in random_positions(12) {
+            let mut game_state = position.game_state.clone();
+            // The factories are filled already, no refill is drawn
+            game_state.get_canonical_moves(&mut canonical_move_list, &mut rng);
+            let total: usize = canonical_move_list
+                .into_iter()
+                .map(|move_| game_state.move_multiplicity(*move_) as usize)
+                .sum();
+            assert_eq!(total, position.moves.len());
+
+            for move_ in &canonical_move_list {
+                assert!(position.moves.contains(move_));
+                assert_eq!(game_state.canonical_move(*move_), *move_);
+            }
+            for move_ in &position.moves {
+                let canonical_move = game_state.canonical_move(*move_);
+                assert!(canonical_move_list.contains(canonical_move));
+
+                // Both moves lead to the same position up to the order of the factories
+                let mut after_move = game_state.clone();
+                after_move.do_move(*move_);
+                let mut after_canonical_move = game_state.clone();
+                after_canonical_move.do_move(canonical_move);
+                let mut factories = after_move.factories.clone();
+                let mut canonical_factories = after_canonical_move.factories.clone();
+                factories.sort();
+                canonical_factories.sort();
+                assert_eq!(factories, canonical_factories);
+                after_canonical_move.set_factories(after_move.factories.clone());
+                assert_eq!(after_move, after_canonical_move);
+            }
+        }
+    }
+
+    #[test]
+    fn test_integrity_violations() {
+        let mut rng: rand::rngs::SmallRng = SeedableRng::seed_from_u64(0);
+        let game_state = GameState::new(2, &mut rng);
+        assert_eq!(game_state.check_integrity(), Ok(()));
+
+        // A tile disappears from the bag
+        let mut broken = game_state.clone();
+        broken.bag[TileColor::Red as usize] -= 1;
+        broken.hash = broken.compute_hash();
+        let violations = broken.integrity_violations();
+        assert_eq!(violations.len(), 1);
+        let IntegrityViolation::TileConservation { color, counts } = violations[0] else {
+            panic!("Unexpected violation {:?}", violations[0]);
+        };
+        assert_eq!(color, TileColor::Red);
+        assert_eq!(counts.total(), 19);
+        assert_eq!(
+            counts.bag,
+            game_state.bag[TileColor::Red as usize] as u32 - 1
+        );
+        assert_eq!(counts.factories, {
+            let factories = game_state.get_factories();
+            factories
+                .iter()
+                .map(|f| f[TileColor::Red as usize] as u32)
+                .sum::<u32>()
+        });
+
+        // Three blue tiles move from the bag to the second player, two into the first pattern line and one onto the wall in the same row.
+        // The tiles are conserved, but the pattern line overflows, its color is on the wall already and the hash is outdated.
+        let mut broken = game_state.clone();
+        broken.bag[TileColor::Blue as usize] -= 3;
+        broken.pattern_lines_occupancy[1][0] = 2;
+        broken.pattern_lines_colors[1][0] = Some(TileColor::Blue);
+        broken.walls[1][TileColor::Blue as usize] = wall::field_at(0, 0);
+        broken.wall_occupancy[1] = wall::field_at(0, 0);
+        let violations = broken.integrity_violations();
+        for expected in [
+            IntegrityViolation::PatternColorOnWall {
+                player: 1,
+                pattern_line_index: 0,
+                color: TileColor::Blue,
+            },
+            IntegrityViolation::PatternLineOverflow {
+                player: 1,
+                pattern_line_index: 0,
+                count: 2,
+            },
+        ] {
+            assert!(violations.contains(&expected), "{:?}", violations);
+        }
+        assert!(violations
+            .iter()
+            .any(|violation| matches!(violation, IntegrityViolation::HashMismatch { .. })));
+        assert!(!violations
+            .iter()
+            .any(|violation| matches!(violation, IntegrityViolation::TileConservation { .. })));
+        assert_eq!(
+            broken.check_integrity(),
+            Err(GameError::InvalidGameState(violations))
+        );
+    }
 }
@@ -0,0 +1,500 @@
+use crate::factories::CENTER_FACTORY_INDEX;
+use crate::game_state::{Bag, GameState, IntegrityViolation};
+use crate::notation::color_from_notation;
+use crate::player::PlayerMarker;
+use crate::ruleset::Ruleset;
+use crate::tile_color::{TileColor, NUM_TILE_COLORS};
+use crate::wall;
+
+/*
+    Sets up an arbitrary position by name, e.g. for tests and puzzles:
+
+    GameStateBuilder::new(2)
+        .factory(0, "BBYR")
+        .center("WK")
+        .wall_row(0, 2, "..BY.")
+        .pattern_line(0, 3, TileColor::Blue, 2)
+        .floor(1, 2)
+        .score(1, 12)
+        .build()
+
+    Tiles are written as colors in the move notation (B, Y, R, G or K, W) and walls row by row with '.' for an empty field.
+    Unless they are set, the bag and the lid are completed with the tiles that are not placed anywhere else, the bag first.
+    build checks the result with GameState::check_integrity.
+*/
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GameStateBuilderError {
+    InvalidTile(char),
+    PlayerOutOfRange(usize),
+    FactoryOutOfRange(usize),
+    PatternLineOutOfRange(usize),
+    RowOutOfRange(usize),
+    InvalidWallRow(String), // A wall row has 5 fields
+    // The floor line holds at most 7 tiles, including the first player marker
+    TooManyFloorTiles { player: usize, count: u32 },
+    // More tiles of the color are placed (including the bag and the lid if they are set) than the 20 that exist
+    TooManyTiles { color: TileColor, count: u32 },
+    Invalid(Vec<IntegrityViolation>),
+}
+
+impl std::fmt::Display for GameStateBuilderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidTile(c) => {
+                write!(
+                    f,
+                    "Invalid tile '{}', expected one of B, Y, R, G (or K), W",
+                    c
+                )
+            }
+            Self::PlayerOutOfRange(player) => write!(f, "There is no player {}", player),
+            Self::FactoryOutOfRange(factory_index) => {
+                write!(f, "There is no factory {}", factory_index)
+            }
+            Self::PatternLineOutOfRange(pattern_line_index) => {
+                write!(f, "There is no pattern line {}", pattern_line_index)
+            }
+            Self::RowOutOfRange(row) => write!(f, "There is no wall row {}", row),
+            Self::InvalidWallRow(value) => write!(
+                f,
+                "Invalid wall row '{}', expected 5 fields with a color or '.'",
+                value
+            ),
+            Self::TooManyFloorTiles { player, count } => write!(
+                f,
+                "Player {} has {} tiles on the floor line, it holds at most 7",
+                player, count
+            ),
+            Self::TooManyTiles { color, count } => write!(
+                f,
+                "The position uses {} {} tiles, only 20 exist",
+                count, color
+            ),
+            Self::Invalid(violations) => {
+                write!(f, "Invalid game state:")?;
+                for violation in violations {
+                    write!(f, "\n  {}", violation)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for GameStateBuilderError {}
+
+fn parse_tiles(tiles: &str) -> Result<Bag, GameStateBuilderError> {
+    let mut counts = [0u8; NUM_TILE_COLORS];
+    for c in tiles.chars().filter(|c| !c.is_whitespace()) {
+        let color = color_from_notation(c.to_ascii_uppercase())
+            .map_err(|_| GameStateBuilderError::InvalidTile(c))?;
+        counts[color as usize] = counts[color as usize].saturating_add(1);
+    }
+    Ok(counts)
+}
+
+#[derive(Debug, Clone)]
+pub struct GameStateBuilder {
+    game_state: GameState,
+    bag: Option<Bag>,
+    out_of_bag: Option<Bag>,
+    error: Option<GameStateBuilderError>, // The first error of a setter, returned by build
+}
+
+impl GameStateBuilder {
+    // An empty position: Empty factories, walls and pattern lines, it is the first player's turn
+    pub fn new(num_players: usize) -> Self {
+        Self::new_with_ruleset(num_players, Ruleset::default())
+    }
+
+    pub fn new_with_ruleset(num_players: usize, ruleset: Ruleset) -> Self {
+        let mut game_state = GameState::new_without_factories(num_players, ruleset);
+        game_state.bag = [0; NUM_TILE_COLORS];
+        Self {
+            game_state,
+            bag: None,
+            out_of_bag: None,
+            error: None,
+        }
+    }
+
+    fn fail(mut self, error: GameStateBuilderError) -> Self {
+        self.error.get_or_insert(error);
+        self
+    }
+
+    fn check_player(&self, player: usize) -> Result<(), GameStateBuilderError> {
+        if player < self.game_state.get_num_players() {
+            Ok(())
+        } else {
+            Err(GameStateBuilderError::PlayerOutOfRange(player))
+        }
+    }
+
+    // The tiles of a factory, counted from 0
+    pub fn factory(mut self, factory_index: usize, tiles: &str) -> Self {
+        if factory_index + 1 >= self.game_state.factories.num_factories() {
+            return self.fail(GameStateBuilderError::FactoryOutOfRange(factory_index));
+        }
+        match parse_tiles(tiles) {
+            Ok(tiles) => {
+                self.game_state.factories[factory_index] = tiles;
+                self
+            }
+            Err(error) => self.fail(error),
+        }
+    }
+
+    pub fn center(mut self, tiles: &str) -> Self {
+        match parse_tiles(tiles) {
+            Ok(tiles) => {
+                self.game_state.factories[CENTER_FACTORY_INDEX] = tiles;
+                self
+            }
+            Err(error) => self.fail(error),
+        }
+    }
+
+    // The fields of a wall row from left to right, e.g. "B.R.." (the wall variant decides which colors may go where)
+    pub fn wall_row(mut self, player: usize, row: usize, fields: &str) -> Self {
+        if let Err(error) = self.check_player(player) {
+            return self.fail(error);
+        }
+        if row >= 5 {
+            return self.fail(GameStateBuilderError::RowOutOfRange(row));
+        }
+        let fields: Vec<char> = fields.chars().collect();
+        if fields.len() != 5 {
+            let value = fields.iter().collect();
+            return self.fail(GameStateBuilderError::InvalidWallRow(value));
+        }
+        let row_mask = wall::get_row_mask(row);
+        for color_board in self.game_state.walls[player].iter_mut() {
+            *color_board &= !row_mask;
+        }
+        for (column, &c) in fields.iter().enumerate() {
+            if c == '.' {
+                continue;
+            }
+            match color_from_notation(c.to_ascii_uppercase()) {
+                Ok(color) => {
+                    self.game_state.walls[player][color as usize] |= wall::field_at(row, column)
+                }
+                Err(_) => return self.fail(GameStateBuilderError::InvalidTile(c)),
+            }
+        }
+        self.game_state.wall_occupancy[player] = self.game_state.walls[player]
+            .iter()
+            .fold(0, |occupancy, color_board| occupancy | color_board);
+        self
+    }
+
+    pub fn pattern_line(
+        mut self,
+        player: usize,
+        pattern_line_index: usize,
+        color: TileColor,
+        count: u8,
+    ) -> Self {
+        if let Err(error) = self.check_player(player) {
+            return self.fail(error);
+        }
+        if pattern_line_index >= 5 {
+            return self.fail(GameStateBuilderError::PatternLineOutOfRange(
+                pattern_line_index,
+            ));
+        }
+        self.game_state.pattern_lines_occupancy[player][pattern_line_index] = count;
+        self.game_state.pattern_lines_colors[player][pattern_line_index] =
+            if count > 0 { Some(color) } else { None };
+        self
+    }
+
+    // The number of tiles on the floor line, the first player marker is added by first_player_marker.
+    // Only the number is stored, the discarded tiles are counted as part of the lid.
+    pub fn floor(mut self, player: usize, count: u8) -> Self {
+        if let Err(error) = self.check_player(player) {
+            return self.fail(error);
+        }
+        let marker = (self.game_state.tile_taken_from_center
+            && usize::from(self.game_state.next_round_starting_player) == player)
+            as u32;
+        let count = count as u32 + marker;
+        if count > 7 {
+            return self.fail(GameStateBuilderError::TooManyFloorTiles { player, count });
+        }
+        self.game_state.floor_line_progress[player] = count as u8;
+        self
+    }
+
+    pub fn score(mut self, player: usize, score: i16) -> Self {
+        if let Err(error) = self.check_player(player) {
+            return self.fail(error);
+        }
+        self.game_state.scores[player] = score;
+        self
+    }
+
+    pub fn current_player(mut self, player: usize) -> Self {
+        if let Err(error) = self.check_player(player) {
+            return self.fail(error);
+        }
+        self.game_state.current_player = PlayerMarker::new(player as u8);
+        self
+    }
+
+    // The player took the first player marker from the center this round, it is on their floor line
+    pub fn first_player_marker(mut self, player: usize) -> Self {
+        if let Err(error) = self.check_player(player) {
+            return self.fail(error);
+        }
+        let previous_marker = self.game_state.tile_taken_from_center
+            && usize::from(self.game_state.next_round_starting_player) == player;
+        let count = self.game_state.floor_line_progress[player] as u32 + !previous_marker as u32;
+        if count > 7 {
+            return self.fail(GameStateBuilderError::TooManyFloorTiles { player, count });
+        }
+        if self.game_state.tile_taken_from_center {
+            let previous = usize::from(self.game_state.next_round_starting_player);
+            self.game_state.floor_line_progress[previous] -= 1;
+        }
+        self.game_state.tile_taken_from_center = true;
+        self.game_state.next_round_starting_player = PlayerMarker::new(player as u8);
+        self.game_state.floor_line_progress[player] += 1;
+        self
+    }
+
+    pub fn bag(mut self, tiles: &str) -> Self {
+        match parse_tiles(tiles) {
+            Ok(tiles) => {
+                self.bag = Some(tiles);
+                self
+            }
+            Err(error) => self.fail(error),
+        }
+    }
+
+    // The box lid with the discarded tiles, it is put back into the bag when the bag runs empty
+    pub fn lid(mut self, tiles: &str) -> Self {
+        match parse_tiles(tiles) {
+            Ok(tiles) => {
+                self.out_of_bag = Some(tiles);
+                self
+            }
+            Err(error) => self.fail(error),
+        }
+    }
+
+    pub fn build(self) -> Result<GameState, GameStateBuilderError> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        let mut game_state = self.game_state;
+        let num_players = game_state.get_num_players();
+        for color in 0..NUM_TILE_COLORS {
+            let mut placed: u32 = game_state.factories.iter().map(|f| f[color] as u32).sum();
+            for player in 0..num_players {
+                for pattern_line_index in 0..5 {
+                    if game_state.pattern_lines_colors[player][pattern_line_index]
+                        == Some(TileColor::from(color))
+                    {
+                        placed +=
+                            game_state.pattern_lines_occupancy[player][pattern_line_index] as u32;
+                    }
+                }
+                placed += game_state.walls[player][color].count_ones();
+            }
+
+            let bag = self.bag.map(|bag| bag[color] as u32);
+            let out_of_bag = self.out_of_bag.map(|out_of_bag| out_of_bag[color] as u32);
+            let given = placed + bag.unwrap_or(0) + out_of_bag.unwrap_or(0);
+            let missing = match 20u32.checked_sub(given) {
+                Some(missing) => missing,
+                None => {
+                    return Err(GameStateBuilderError::TooManyTiles {
+                        color: TileColor::from(color),
+                        count: given,
+                    })
+                }
+            };
+            // The missing tiles go into whichever of the bag and the lid is not set, the bag first
+            let (bag, out_of_bag) = match (bag, out_of_bag) {
+                (None, out_of_bag) => (missing, out_of_bag.unwrap_or(0)),
+                (Some(bag), None) => (bag, missing),
+                (Some(bag), Some(out_of_bag)) => (bag, out_of_bag),
+            };
+            game_state.bag[color] = bag as u8;
+            game_state.out_of_bag[color] = out_of_bag as u8;
+        }
+
+        game_state.hash = game_state.compute_hash();
+        game_state.check_integrity().map_err(|error| match error {
+            crate::GameError::InvalidGameState(violations) => {
+                GameStateBuilderError::Invalid(violations)
+            }
+            _ => unreachable!("check_integrity only reports violations"),
+        })?;
+        Ok(game_state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Move, MoveList};
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    #[test]
+    fn test_build_position() {
+        let game_state = GameStateBuilder::new(2)
+            .factory(0, "BBYR")
+            .factory(3, "wwwk")
+            .center("R")
+            .wall_row(0, 0, "B....")
+            .wall_row(1, 2, "..BY.")
+            .pattern_line(0, 3, TileColor::Yellow, 2)
+            .first_player_marker(1)
+            .floor(1, 2)
+            .score(0, 7)
+            .score(1, 12)
+            .current_player(1)
+            .lid("YYY")
+            .build()
+            .unwrap();
+
+        assert_eq!(game_state.get_factories()[0], [2, 1, 1, 0, 0]);
+        assert_eq!(game_state.get_factories()[3], [0, 0, 0, 1, 3]);
+        assert_eq!(
+            game_state.get_factories()[CENTER_FACTORY_INDEX],
+            [0, 0, 1, 0, 0]
+        );
+        assert_eq!(game_state.get_scores(), &[7, 12]);
+        assert_eq!(game_state.get_floor_line_progress(), &[0, 3]);
+        assert_eq!(usize::from(game_state.get_current_player()), 1);
+        assert_eq!(usize::from(game_state.get_next_round_starting_player()), 1);
+        assert!(game_state.get_tile_taken_from_center());
+        assert_eq!(game_state.get_out_of_bag(), [0, 3, 0, 0, 0]);
+        // Blue: 2 in a factory, 2 on walls. Yellow: 1 in a factory, 1 on a wall, 2 in a pattern line, 3 in the lid
+        assert_eq!(game_state.get_bag(), [16, 13, 18, 19, 17]);
+
+        // The position is equal to the same position loaded from its string
+        let copy = GameState::deserialize_string(&game_state.serialize_string()).unwrap();
+        assert_eq!(copy, game_state);
+
+        // Moves can be played in it
+        let mut move_list = MoveList::default();
+        game_state
+            .clone()
+            .get_possible_moves(&mut move_list, &mut SmallRng::seed_from_u64(0));
+        assert!(move_list.contains(Move::from_notation("4W>555").unwrap()));
+    }
+
+    #[test]
+    fn test_module_example() {
+        GameStateBuilder::new(2)
+            .factory(0, "BBYR")
+            .center("WK")
+            .wall_row(0, 2, "..BY.")
+            .pattern_line(0, 3, TileColor::Blue, 2)
+            .floor(1, 2)
+            .score(1, 12)
+            .build()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_build_errors() {
+        assert_eq!(
+            GameStateBuilder::new(2).factory(5, "B").build().err(),
+            Some(GameStateBuilderError::FactoryOutOfRange(5))
+        );
+        assert_eq!(
+            GameStateBuilder::new(2).center("BX").build().err(),
+            Some(GameStateBuilderError::InvalidTile('X'))
+        );
+        assert_eq!(
+            GameStateBuilder::new(2).score(2, 1).build().err(),
+            Some(GameStateBuilderError::PlayerOutOfRange(2))
+        );
+        assert_eq!(
+            GameStateBuilder::new(2).wall_row(0, 0, "B..").build().err(),
+            Some(GameStateBuilderError::InvalidWallRow("B..".to_string()))
+        );
+        assert_eq!(
+            GameStateBuilder::new(2).floor(0, 255).build().err(),
+            Some(GameStateBuilderError::TooManyFloorTiles {
+                player: 0,
+                count: 255
+            })
+        );
+        assert_eq!(
+            GameStateBuilder::new(2)
+                .first_player_marker(1)
+                .floor(1, 7)
+                .build()
+                .err(),
+            Some(GameStateBuilderError::TooManyFloorTiles {
+                player: 1,
+                count: 8
+            })
+        );
+        assert_eq!(
+            GameStateBuilder::new(2)
+                .floor(1, 7)
+                .first_player_marker(1)
+                .build()
+                .err(),
+            Some(GameStateBuilderError::TooManyFloorTiles {
+                player: 1,
+                count: 8
+            })
+        );
+        // Moving the marker to the same player doesn't add it twice
+        assert!(GameStateBuilder::new(2)
+            .floor(1, 6)
+            .first_player_marker(1)
+            .first_player_marker(1)
+            .build()
+            .is_ok());
+        assert_eq!(
+            GameStateBuilder::new(2)
+                .bag(&"R".repeat(18))
+                .center("RRR")
+                .build()
+                .err(),
+            Some(GameStateBuilderError::TooManyTiles {
+                color: TileColor::Red,
+                count: 21
+            })
+        );
+
+        // Blue is not allowed in the second column of the first row of the colored wall
+        let error = GameStateBuilder::new(2)
+            .wall_row(0, 0, ".B...")
+            .build()
+            .err();
+        assert_eq!(
+            error,
+            Some(GameStateBuilderError::Invalid(vec![
+                IntegrityViolation::WallPatternViolation {
+                    player: 0,
+                    color: TileColor::Blue
+                }
+            ]))
+        );
+        // On the free wall it is
+        assert!(
+            GameStateBuilder::new_with_ruleset(2, Ruleset::OFFICIAL_FREE_WALL)
+                .wall_row(0, 0, ".B...")
+                .build()
+                .is_ok()
+        );
+
+        // A full factory holds 4 tiles
+        assert!(matches!(
+            GameStateBuilder::new(2).factory(0, "BBBBB").build(),
+            Err(GameStateBuilderError::Invalid(_))
+        ));
+    }
+}
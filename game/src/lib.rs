@@ -1,43 +1,92 @@
+mod binary;
 mod factories;
 mod formatting;
 mod game_state;
+mod game_state_builder;
 pub mod match_;
 mod move_;
 mod move_list;
+mod notation;
+pub mod observation;
+mod perft;
 mod player;
+mod position;
+mod record;
+mod ruleset;
+mod score_report;
+#[cfg(feature = "serde")]
+mod serde_support;
 mod shared_state;
+#[cfg(test)]
+mod test_support;
 mod tile_color;
+mod time_control;
 pub mod wall;
+mod zobrist;
 
-pub use factories::{Factories, Factory};
-pub use factories::{CENTER_FACTORY_INDEX, NUM_FACTORIES};
+pub use binary::{BinaryDecodeError, DatasetEntry, DatasetError, DatasetReader, DatasetWriter};
+pub use binary::{BINARY_FORMAT_VERSION, DATASET_ENTRY_SIZE, GAME_STATE_SIZE, MOVE_SIZE};
+pub use factories::{num_factories_for_players, CENTER_FACTORY_INDEX, MAX_FACTORIES};
+pub use factories::{Factories, Factory, RefillOutcome};
 pub use formatting::display_gamestate;
-pub use game_state::Bag;
-pub use game_state::GameState;
+pub use game_state::{Bag, MoveUndo, RefillUndo, RoundUndo};
+pub use game_state::{GameState, GameStateParseError, IllegalMoveReason};
+pub use game_state::{IntegrityViolation, TileCounts};
 pub use game_state::{MoveGenerationResult, FLOOR_LINE_PENALTY};
-pub use move_::Move;
+pub use game_state_builder::{GameStateBuilder, GameStateBuilderError};
+pub use move_::{Move, MoveParseError, WALL_TILING_INDEX};
 pub use move_list::MoveList;
+pub use notation::NotationError;
+pub use perft::{PerftReference, PERFT_REFERENCES};
 pub use player::{Player, PlayerMarker};
+pub use position::{PositionParseError, POSITION_FORMAT_VERSION};
+pub use record::{current_date, GameRecord, RecordEvent, RecordParseError, ReplayError};
+pub use ruleset::{PatternLinePlacement, Ruleset, WallVariant};
+pub use score_report::{EndOfGameBonus, ScoreReport, WallPlacement};
 pub use shared_state::SharedState;
-pub use tile_color::TileColor;
 pub use tile_color::NUM_TILE_COLORS;
+pub use tile_color::{InvalidTileColor, TileColor};
+pub use time_control::{Clocks, TimeControl};
 pub use wall::field_at;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum GameError {
     IllegalMove,
     PlayerCountMismatch,
-    InvalidGameState,
+    InvalidGameState(Vec<IntegrityViolation>), // Every invariant the game state breaks, see GameState::check_integrity
+    ImpossibleRefill,                          // The factory content can't be drawn from the bag
+    Cancelled, // The match was cancelled before its end, see match_::CancelToken
 }
 
-#[cfg(feature = "three_players")]
-pub const NUM_PLAYERS: usize = 3;
+impl std::fmt::Display for GameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::IllegalMove => write!(f, "Illegal move"),
+            Self::PlayerCountMismatch => {
+                write!(f, "The number of players does not match the game state")
+            }
+            Self::InvalidGameState(violations) => {
+                write!(f, "Invalid game state:")?;
+                for violation in violations {
+                    write!(f, "\n  {}", violation)?;
+                }
+                Ok(())
+            }
+            Self::Cancelled => write!(f, "The match was cancelled"),
+            Self::ImpossibleRefill => {
+                write!(
+                    f,
+                    "The factories can't be refilled with the tiles in the bag"
+                )
+            }
+        }
+    }
+}
 
-#[cfg(feature = "four_players")]
-pub const NUM_PLAYERS: usize = 4;
+impl std::error::Error for GameError {}
 
-#[cfg(not(any(feature = "three_players", feature = "four_players")))]
-pub const NUM_PLAYERS: usize = 2;
+pub const MIN_PLAYERS: usize = 2;
+pub const MAX_PLAYERS: usize = 4;
 
 use log::LevelFilter;
 use log4rs::append::console::ConsoleAppender;
@@ -1,118 +1,582 @@
 use rand::{rngs::SmallRng, SeedableRng};
 
+use crate::time_control::Clocks;
+use crate::wall;
 use crate::{
-    formatting::display_gamestate, game_state::MoveGenerationResult, GameError, GameState, Move,
-    MoveList, Player, PlayerMarker, NUM_PLAYERS,
+    formatting::display_gamestate, game_state::MoveGenerationResult, record::current_date,
+    GameError, GameRecord, GameState, IllegalMoveReason, Move, MoveList, Player, PlayerMarker,
+    RecordEvent, ReplayError, ScoreReport, TimeControl,
 };
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 #[derive(Default, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MatchStatistcs {
     pub num_turns: u32,
     pub num_factory_refills: u32,
     pub executed_moves: Vec<(GameState, PlayerMarker, Move, u64)>,
-    pub player_statistics: [PlayerStatistics; NUM_PLAYERS],
+    pub player_statistics: Vec<PlayerStatistics>,
     pub branching_factor: Vec<u32>,
+    pub seed: u64,                  // Seed of the factory refills, see replay
+    pub record: Option<GameRecord>, // Game record that can be written to an AGN file
+    pub result: MatchResult,
+    pub forfeit: Option<Forfeit>, // The game ended before its end because a player forfeited
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum ForfeitReason {
+    Time { used: u64, available: u64 }, // The player took longer than the time it had left (in milliseconds)
+}
+
+impl std::fmt::Display for ForfeitReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Time { used, available } => {
+                write!(f, "time forfeit, used {}ms of {}ms", used, available)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Forfeit {
+    pub player: usize,
+    pub reason: ForfeitReason,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum MatchOutcome {
+    Win,
+    Draw, // Shared first place after the tie-break
+    Loss,
+}
+
+// Final scores and placements of a finished game, see GameState::final_ranking for the tie-break
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MatchResult {
+    pub scores: Vec<i16>,
+    pub complete_rows: Vec<u32>,
+    pub placements: Vec<usize>, // 0 is the winner, tied players share the placement
+}
+
+impl MatchResult {
+    pub fn new(game_state: &GameState) -> Self {
+        Self {
+            scores: game_state.get_scores().to_vec(),
+            complete_rows: game_state
+                .get_wall_ocupancy()
+                .iter()
+                .map(|occupancy| wall::count_complete_rows(*occupancy))
+                .collect(),
+            placements: game_state.final_ranking(),
+        }
+    }
+
+    // The player that forfeited is placed last, the others are ranked by the position at the time of the forfeit
+    pub fn forfeited(game_state: &GameState, player: usize) -> Self {
+        let mut result = Self::new(game_state);
+        let num_players = result.placements.len();
+        let ranking = result.placements.clone();
+        for (other, placement) in result.placements.iter_mut().enumerate() {
+            *placement = if other == player {
+                num_players - 1
+            } else {
+                (0..num_players)
+                    .filter(|&i| i != player && ranking[i] < ranking[other])
+                    .count()
+            };
+        }
+        result
+    }
+
+    // The players (counted from 0) that share the first place
+    pub fn winners(&self) -> Vec<usize> {
+        (0..self.placements.len())
+            .filter(|&player| self.placements[player] == 0)
+            .collect()
+    }
+
+    pub fn outcome(&self, player: usize) -> MatchOutcome {
+        if self.placements[player] > 0 {
+            MatchOutcome::Loss
+        } else if self.winners().len() > 1 {
+            MatchOutcome::Draw
+        } else {
+            MatchOutcome::Win
+        }
+    }
+
+    // The same result with the players in a different order, player i of the new order is player order[i] of this result
+    pub fn reordered(&self, order: &[usize]) -> Self {
+        Self {
+            scores: order.iter().map(|&player| self.scores[player]).collect(),
+            complete_rows: order
+                .iter()
+                .map(|&player| self.complete_rows[player])
+                .collect(),
+            placements: order
+                .iter()
+                .map(|&player| self.placements[player])
+                .collect(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PlayerStatistics {
     pub executed_moves: Vec<(GameState, Move, u64)>,
     pub num_moves: u32,
     pub final_score: i16,
 }
 
-pub async fn run_match(
-    mut game_state: GameState,
-    players: &mut [Box<dyn Player>],
-    verbose: bool,
-) -> Result<MatchStatistcs, GameError> {
-    let num_players = players.len();
-    if num_players != NUM_PLAYERS {
-        return Err(GameError::PlayerCountMismatch);
+// Gets notified about everything that happens in run_match, e.g. to show, record or stream the game.
+// All methods do nothing by default.
+pub trait MatchObserver: Send {
+    fn on_match_start(&mut self, _game_state: &GameState, _player_names: &[String]) {}
+    // The factories are empty and the round is about to be evaluated, the reports tell what every player scores
+    fn on_round_end(&mut self, _game_state: &GameState, _reports: &[ScoreReport]) {}
+    fn on_refill(&mut self, _game_state: &GameState) {}
+    // The position before and after the move of the player, who took response_time milliseconds for it
+    fn on_move(
+        &mut self,
+        _previous_game_state: &GameState,
+        _game_state: &GameState,
+        _player: usize,
+        _move_: Move,
+        _response_time: u64,
+    ) {
     }
+    // The player made a move that isn't in the move list, the reason is None if validate_move accepts it anyway
+    fn on_illegal_move(
+        &mut self,
+        _game_state: &GameState,
+        _player: usize,
+        _move_: Move,
+        _reason: Option<IllegalMoveReason>,
+        _move_list: &MoveList,
+    ) {
+    }
+    fn on_integrity_failure(&mut self, _game_state: &GameState, _error: &GameError) {}
+    // Also called after a forfeit, the game state is the position at that time
+    fn on_game_over(&mut self, _game_state: &GameState, _stats: &MatchStatistcs) {}
+}
 
-    let player_names = players
-        .iter()
-        .map(|player| player.get_name().to_string())
-        .collect::<Vec<_>>();
+// Prints the board after every event of the match to the console
+#[derive(Debug, Clone, Default)]
+pub struct ConsoleObserver {
+    player_names: Vec<String>,
+}
 
-    game_state.check_integrity()?;
-    let mut stats = MatchStatistcs::default();
+impl MatchObserver for ConsoleObserver {
+    fn on_match_start(&mut self, game_state: &GameState, player_names: &[String]) {
+        self.player_names = player_names.to_vec();
+        println!(
+            "{}",
+            display_gamestate(game_state, Some(&self.player_names))
+        );
+    }
 
-    let mut move_list = MoveList::default();
-    let mut rng = SmallRng::from_entropy();
-    loop {
-        if verbose {
-            println!("{}", display_gamestate(&game_state, Some(&player_names)));
-        }
-        let result = game_state.get_possible_moves(&mut move_list, &mut rng);
-        let is_game_over = matches!(result, MoveGenerationResult::GameOver);
-        let refilled_factories = matches!(result, MoveGenerationResult::RoundOver);
-        if is_game_over {
-            break;
-        }
-        if refilled_factories && verbose {
-            println!("Factories refilled");
-            println!("{}", display_gamestate(&game_state, Some(&player_names)));
-        }
-        stats.num_factory_refills += refilled_factories as u32;
-        stats.num_turns += 1;
-
-        let current_player_marker: PlayerMarker = game_state.get_current_player();
-        let current_player = usize::from(current_player_marker);
-
-        let request_time = std::time::Instant::now();
-        let players_move: Move = players[current_player].get_move(&game_state).await;
-        println!("{}: {}", player_names[current_player], players_move);
-        let response_time = std::time::Instant::now();
-        let response_time = response_time.duration_since(request_time).as_millis() as u64;
-
-        if !move_list.contains(players_move) {
-            // If the move is not legal, return an error
+    fn on_round_end(&mut self, _game_state: &GameState, reports: &[ScoreReport]) {
+        println!("End of the round");
+        for (report, name) in reports.iter().zip(&self.player_names) {
+            println!("{}:\n{}", name, report);
+        }
+    }
+
+    fn on_refill(&mut self, game_state: &GameState) {
+        println!("Factories refilled");
+        println!(
+            "{}",
+            display_gamestate(game_state, Some(&self.player_names))
+        );
+    }
+
+    fn on_move(
+        &mut self,
+        previous_game_state: &GameState,
+        game_state: &GameState,
+        player: usize,
+        move_: Move,
+        _response_time: u64,
+    ) {
+        println!("{}: {}", self.player_names[player], move_);
+        // On the free wall the tiles are scored when they are placed
+        if let Some(placement) = previous_game_state.wall_tiling_placement(move_) {
             println!(
-                "Player {} made an illegal move: {:?}",
-                current_player, players_move
+                "{} at row {}, column {}: {:+}",
+                placement.color,
+                placement.row + 1,
+                placement.column + 1,
+                placement.points()
             );
-            println!("Move list: {:?}", move_list);
-            println!("{}", display_gamestate(&game_state, Some(&player_names)));
-            return Err(GameError::IllegalMove);
-        }
-
-        stats.branching_factor.push(move_list.len() as u32);
-        stats.executed_moves.push((
-            game_state.clone(),
-            current_player_marker,
-            players_move,
-            response_time,
-        ));
-        stats.player_statistics[current_player]
-            .executed_moves
-            .push((game_state.clone(), players_move, response_time));
-        game_state.do_move(players_move);
-        stats.player_statistics[current_player].num_moves += 1;
+        }
+        println!(
+            "{}",
+            display_gamestate(game_state, Some(&self.player_names))
+        );
+    }
 
-        for player in players.iter_mut() {
-            player.notify_move(&game_state, players_move).await;
+    fn on_illegal_move(
+        &mut self,
+        game_state: &GameState,
+        player: usize,
+        move_: Move,
+        reason: Option<IllegalMoveReason>,
+        move_list: &MoveList,
+    ) {
+        let reason = match reason {
+            Some(reason) => reason.to_string(),
+            None => "Not in the move list".to_string(),
+        };
+        println!(
+            "{} made an illegal move: {:?} ({})",
+            self.player_names[player], move_, reason
+        );
+        println!("Move list: {:?}", move_list);
+        println!(
+            "{}",
+            display_gamestate(game_state, Some(&self.player_names))
+        );
+    }
+
+    fn on_integrity_failure(&mut self, _game_state: &GameState, error: &GameError) {
+        println!("{}", error);
+    }
+
+    fn on_game_over(&mut self, _game_state: &GameState, stats: &MatchStatistcs) {
+        if let Some(forfeit) = stats.forfeit {
+            println!(
+                "{} forfeits the game: {}",
+                self.player_names[forfeit.player], forfeit.reason
+            );
+        }
+        // After a forfeit the placements follow the forfeit, see MatchResult::forfeited
+        for (player, name) in self.player_names.iter().enumerate() {
+            println!(
+                "{}. {}: {} points",
+                stats.result.placements[player] + 1,
+                name,
+                stats.result.scores[player]
+            );
         }
+    }
+}
+
+// Cancels a running match from another task, e.g. when the client of the match disconnected.
+// All clones share the same state, a cancelled token stays cancelled.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+    notify: Arc<tokio::sync::Notify>,
+}
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        self.notify.notify_waiters();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
 
-        game_state.check_integrity()?;
+    // Completes once the token is cancelled
+    pub async fn cancelled(&self) {
+        loop {
+            // The future is registered before the check, so a cancel in between isn't missed
+            let notified = self.notify.notified();
+            if self.is_cancelled() {
+                return;
+            }
+            notified.await;
+        }
     }
-    if verbose {
-        println!("{}", display_gamestate(&game_state, Some(&player_names)));
+}
+
+// Plays matches with the same settings, used by everything that runs games (CLI, test server and backend).
+// Every player has a clock with the time control, a player that runs out of time forfeits the game (see MatchStatistcs::forfeit).
+// A cancelled match stops at the next move request or while waiting for a move and returns GameError::Cancelled.
+#[derive(Debug, Clone, Default)]
+pub struct MatchDriver {
+    time_control: TimeControl,
+    cancel_token: CancelToken,
+}
+
+impl MatchDriver {
+    pub fn new(time_control: TimeControl) -> Self {
+        Self {
+            time_control,
+            cancel_token: CancelToken::default(),
+        }
     }
 
-    // The game is over, we can get the scores
-    let scores: [i16; NUM_PLAYERS] = game_state.get_scores();
-    for (i, score) in scores.iter().enumerate() {
-        stats.player_statistics[i].final_score = *score;
+    pub fn with_cancel_token(mut self, cancel_token: CancelToken) -> Self {
+        self.cancel_token = cancel_token;
+        self
     }
 
-    // Reset the players
-    for player in players.iter_mut() {
-        player.reset().await;
+    pub fn cancel_token(&self) -> CancelToken {
+        self.cancel_token.clone()
     }
 
-    Ok(stats)
+    // Plays a game from the given position. All factory refills are drawn from a generator seeded with the seed,
+    // so the game can be rebuilt from the start, the seed and the moves (see replay).
+    // Games that start with GameState::new_without_factories depend on nothing but the seed and the moves.
+    // The observers are notified about every event of the match.
+    pub async fn run(
+        &self,
+        mut game_state: GameState,
+        players: &mut [Box<dyn Player>],
+        seed: u64,
+        observers: &mut [&mut dyn MatchObserver],
+    ) -> Result<MatchStatistcs, GameError> {
+        let time_control = self.time_control;
+        let num_players = players.len();
+        if num_players != game_state.get_num_players() {
+            return Err(GameError::PlayerCountMismatch);
+        }
+
+        let player_names = players
+            .iter()
+            .map(|player| player.get_name().to_string())
+            .collect::<Vec<_>>();
+
+        if let Err(error) = game_state.check_integrity() {
+            for observer in observers.iter_mut() {
+                observer.on_integrity_failure(&game_state, &error);
+            }
+            return Err(error);
+        }
+        let mut stats = MatchStatistcs {
+            player_statistics: vec![PlayerStatistics::default(); num_players],
+            seed,
+            ..Default::default()
+        };
+
+        let mut record = GameRecord::new(player_names.clone(), &game_state);
+        record.seed = Some(seed);
+        record.date = Some(current_date());
+
+        let mut move_list = MoveList::default();
+        let mut rng = SmallRng::seed_from_u64(seed);
+        let mut clocks = Clocks::new(time_control, num_players);
+        for observer in observers.iter_mut() {
+            observer.on_match_start(&game_state, &player_names);
+        }
+        // A game that starts without factories (see GameState::new_without_factories) has no round to report before the first refill
+        let mut round_played = false;
+        loop {
+            if round_played
+                && game_state.get_factories().is_empty()
+                && game_state.get_pending_wall_tiling().is_none()
+            {
+                let reports = game_state.round_score_reports();
+                for observer in observers.iter_mut() {
+                    observer.on_round_end(&game_state, &reports);
+                }
+            }
+            let result = game_state.get_possible_moves(&mut move_list, &mut rng);
+            let is_game_over = matches!(result, MoveGenerationResult::GameOver);
+            let refilled_factories = matches!(result, MoveGenerationResult::RoundOver);
+            if is_game_over {
+                break;
+            }
+            if refilled_factories {
+                for observer in observers.iter_mut() {
+                    observer.on_refill(&game_state);
+                }
+                record
+                    .events
+                    .push(RecordEvent::Refill(game_state.get_factories().clone()));
+            }
+            stats.num_factory_refills += refilled_factories as u32;
+            stats.num_turns += 1;
+
+            let current_player_marker: PlayerMarker = game_state.get_current_player();
+            let current_player = usize::from(current_player_marker);
+
+            let time_left = clocks.time_left(current_player);
+            if let Some(time_left) = time_left {
+                players[current_player]
+                    .set_time_left(time_control, time_left)
+                    .await;
+            }
+            // A player that doesn't answer within the time left is stopped once the clock runs out
+            let clock_expired = async {
+                match time_left {
+                    Some(time_left) => {
+                        tokio::time::sleep(std::time::Duration::from_millis(time_left + 1)).await
+                    }
+                    None => std::future::pending().await,
+                }
+            };
+            let request_time = tokio::time::Instant::now();
+            let players_move = tokio::select! {
+                // A match that was already cancelled doesn't ask for another move
+                biased;
+                _ = self.cancel_token.cancelled() => {
+                    for player in players.iter_mut() {
+                        player.reset().await;
+                    }
+                    return Err(GameError::Cancelled);
+                }
+                players_move = players[current_player].get_move(&game_state) => Some(players_move),
+                _ = clock_expired => None,
+            };
+            let response_time = request_time.elapsed().as_millis() as u64;
+            let in_time = clocks.charge(current_player, response_time);
+            let players_move = match players_move {
+                Some(players_move) if in_time => players_move,
+                _ => {
+                    let reason = ForfeitReason::Time {
+                        used: response_time,
+                        available: time_left.unwrap_or_default(),
+                    };
+                    record.termination =
+                        Some(format!("{} {}", player_names[current_player], reason));
+                    stats.forfeit = Some(Forfeit {
+                        player: current_player,
+                        reason,
+                    });
+                    break;
+                }
+            };
+            if !move_list.contains(players_move) {
+                // If the move is not legal, the match stops like a cancelled one
+                let reason = game_state.validate_move(players_move).err();
+                for observer in observers.iter_mut() {
+                    observer.on_illegal_move(
+                        &game_state,
+                        current_player,
+                        players_move,
+                        reason,
+                        &move_list,
+                    );
+                }
+                log::error!("Game record:\n{}", record.write());
+                for player in players.iter_mut() {
+                    player.reset().await;
+                }
+                return Err(GameError::IllegalMove);
+            }
+
+            stats.branching_factor.push(move_list.len() as u32);
+            stats.executed_moves.push((
+                game_state.clone(),
+                current_player_marker,
+                players_move,
+                response_time,
+            ));
+            stats.player_statistics[current_player]
+                .executed_moves
+                .push((game_state.clone(), players_move, response_time));
+            let previous_game_state = game_state.clone();
+            game_state.do_move(players_move);
+            record.events.push(RecordEvent::Move(players_move));
+            round_played = true;
+            stats.player_statistics[current_player].num_moves += 1;
+
+            for player in players.iter_mut() {
+                player.notify_move(&game_state, players_move).await;
+            }
+            for observer in observers.iter_mut() {
+                observer.on_move(
+                    &previous_game_state,
+                    &game_state,
+                    current_player,
+                    players_move,
+                    response_time,
+                );
+            }
+
+            if let Err(error) = game_state.check_integrity() {
+                for observer in observers.iter_mut() {
+                    observer.on_integrity_failure(&game_state, &error);
+                }
+                log::error!("Game record:\n{}", record.write());
+                for player in players.iter_mut() {
+                    player.reset().await;
+                }
+                return Err(error);
+            }
+        }
+        // The game is over, we can get the scores. After a forfeit these are the scores at the time of the forfeit.
+        let scores = game_state.get_scores();
+        for (i, score) in scores.iter().enumerate() {
+            stats.player_statistics[i].final_score = *score;
+        }
+        stats.result = match stats.forfeit {
+            Some(forfeit) => MatchResult::forfeited(&game_state, forfeit.player),
+            None => {
+                let result = MatchResult::new(&game_state);
+                record.final_scores = Some(result.scores.clone());
+                record.complete_rows = Some(result.complete_rows.clone());
+                result
+            }
+        };
+        stats.record = Some(record);
+        for observer in observers.iter_mut() {
+            observer.on_game_over(&game_state, &stats);
+        }
+
+        // Reset the players
+        for player in players.iter_mut() {
+            player.reset().await;
+        }
+
+        Ok(stats)
+    }
+}
+
+// Plays a single match with a new driver, see MatchDriver::run
+pub async fn run_match(
+    game_state: GameState,
+    players: &mut [Box<dyn Player>],
+    seed: u64,
+    time_control: TimeControl,
+    observers: &mut [&mut dyn MatchObserver],
+) -> Result<MatchStatistcs, GameError> {
+    MatchDriver::new(time_control)
+        .run(game_state, players, seed, observers)
+        .await
+}
+
+// Rebuilds a game played by run_match from its start position, seed and moves.
+// Returns the position every move was played in, followed by the position after the last move
+// (with the next refill drawn or, if the game is over, the final scores evaluated).
+pub fn replay(start: &GameState, seed: u64, moves: &[Move]) -> Result<Vec<GameState>, ReplayError> {
+    let mut game_state = start.clone();
+    let mut move_list = MoveList::default();
+    let mut rng = SmallRng::seed_from_u64(seed);
+    let mut states = Vec::with_capacity(moves.len() + 1);
+    for (index, mov) in moves.iter().enumerate() {
+        if game_state.get_possible_moves(&mut move_list, &mut rng) == MoveGenerationResult::GameOver
+        {
+            return Err(ReplayError::GameOver { index });
+        }
+        game_state
+            .validate_move(*mov)
+            .map_err(|reason| ReplayError::IllegalMove { index, reason })?;
+        states.push(game_state.clone());
+        game_state.do_move(*mov);
+    }
+    game_state.get_possible_moves(&mut move_list, &mut rng);
+    states.push(game_state);
+    Ok(states)
 }
 
 #[cfg(test)]
@@ -138,28 +602,385 @@ mod tests {
         }
     }
 
+    // Counts the events of a match
+    #[derive(Default)]
+    struct CountingObserver {
+        starts: usize,
+        round_ends: usize,
+        refills: usize,
+        moves: usize,
+        response_time: u64,
+        illegal_moves: usize,
+        game_overs: usize,
+    }
+
+    impl MatchObserver for CountingObserver {
+        fn on_match_start(&mut self, _game_state: &GameState, player_names: &[String]) {
+            assert!(!player_names.is_empty());
+            self.starts += 1;
+        }
+
+        fn on_round_end(&mut self, game_state: &GameState, reports: &[ScoreReport]) {
+            assert!(game_state.get_factories().is_empty());
+            assert_eq!(reports.len(), game_state.get_num_players());
+            self.round_ends += 1;
+        }
+
+        fn on_refill(&mut self, _game_state: &GameState) {
+            self.refills += 1;
+        }
+
+        fn on_move(
+            &mut self,
+            previous_game_state: &GameState,
+            game_state: &GameState,
+            player: usize,
+            move_: Move,
+            response_time: u64,
+        ) {
+            assert_eq!(
+                usize::from(previous_game_state.get_current_player()),
+                player
+            );
+            let mut expected = previous_game_state.clone();
+            expected.do_move(move_);
+            assert_eq!(expected, *game_state);
+            self.moves += 1;
+            self.response_time += response_time;
+        }
+
+        fn on_illegal_move(
+            &mut self,
+            game_state: &GameState,
+            player: usize,
+            move_: Move,
+            reason: Option<IllegalMoveReason>,
+            move_list: &MoveList,
+        ) {
+            assert_eq!(usize::from(game_state.get_current_player()), player);
+            assert!(!move_list.contains(move_));
+            assert_eq!(reason, game_state.validate_move(move_).err());
+            self.illegal_moves += 1;
+        }
+
+        fn on_game_over(&mut self, _game_state: &GameState, stats: &MatchStatistcs) {
+            assert!(stats.record.is_some());
+            self.game_overs += 1;
+        }
+    }
+
+    fn mock_players(num_players: usize) -> Vec<Box<dyn Player>> {
+        (0..num_players)
+            .map(|i| {
+                Box::new(MockPlayer {
+                    name: format!("Player {}", i + 1),
+                }) as Box<dyn Player>
+            })
+            .collect()
+    }
+
     #[tokio::test]
     async fn test_match() {
-        let player1: Box<dyn Player> = Box::new(MockPlayer {
-            name: "Player 1".to_string(),
-        });
-        let player2: Box<dyn Player> = Box::new(MockPlayer {
-            name: "Player 2".to_string(),
+        for num_players in crate::MIN_PLAYERS..=crate::MAX_PLAYERS {
+            let mut players = mock_players(num_players);
+            let mut rng = SmallRng::seed_from_u64(0);
+            let mut observer = CountingObserver::default();
+            let stats = run_match(
+                GameState::new(num_players, &mut rng),
+                &mut players,
+                0,
+                TimeControl::Unlimited,
+                &mut [&mut observer],
+            )
+            .await
+            .unwrap();
+            assert_eq!(stats.player_statistics.len(), num_players);
+
+            // The observer saw every event, the last round end is the end of the game
+            assert_eq!(observer.starts, 1);
+            assert_eq!(observer.game_overs, 1);
+            assert_eq!(observer.moves, stats.executed_moves.len());
+            assert_eq!(observer.refills as u32, stats.num_factory_refills);
+            assert_eq!(observer.round_ends as u32, stats.num_factory_refills + 1);
+            let response_time: u64 = stats
+                .executed_moves
+                .iter()
+                .map(|(_, _, _, time)| time)
+                .sum();
+            assert_eq!(observer.response_time, response_time);
+
+            // The record replays to the same final scores
+            let record = GameRecord::parse(&stats.record.unwrap().write()).unwrap();
+            let final_state = record.replay().unwrap().pop().unwrap();
+            for (player_statistics, score) in
+                stats.player_statistics.iter().zip(final_state.get_scores())
+            {
+                assert_eq!(player_statistics.final_score, *score);
+            }
+            assert_eq!(stats.result.scores, final_state.get_scores());
+            assert_eq!(stats.result.placements, final_state.final_ranking());
+            assert_eq!(record.winners(), stats.result.winners());
+        }
+    }
+
+    // Plays like MockPlayer, but takes its time from the second move on
+    pub struct SlowPlayer {
+        mock_player: MockPlayer,
+        num_moves: usize,
+    }
+
+    #[async_trait::async_trait]
+    impl Player for SlowPlayer {
+        fn get_name(&self) -> &str {
+            self.mock_player.get_name()
+        }
+
+        // Hangs from the second move on
+        async fn get_move(&mut self, game_state: &GameState) -> Move {
+            self.num_moves += 1;
+            if self.num_moves > 1 {
+                std::future::pending::<()>().await;
+            }
+            self.mock_player.get_move(game_state).await
+        }
+    }
+
+    // The time is paused, so the clocks only move while a player waits and the test doesn't depend on the speed of the machine
+    #[tokio::test(start_paused = true)]
+    async fn test_time_forfeit() {
+        let mut players = mock_players(2);
+        players[1] = Box::new(SlowPlayer {
+            mock_player: MockPlayer {
+                name: "Slow".to_string(),
+            },
+            num_moves: 0,
         });
-        let player3: Box<dyn Player> = Box::new(MockPlayer {
-            name: "Player 3".to_string(),
+        let mut rng = SmallRng::seed_from_u64(0);
+        let stats = run_match(
+            GameState::new(2, &mut rng),
+            &mut players,
+            0,
+            TimeControl::PerMove(10),
+            &mut [],
+        )
+        .await
+        .unwrap();
+
+        // The second player hangs in its second move and forfeits once the clock runs out
+        let forfeit = stats.forfeit.unwrap();
+        assert_eq!(forfeit.player, 1);
+        assert_eq!(
+            forfeit.reason,
+            ForfeitReason::Time {
+                used: 11,
+                available: 10
+            }
+        );
+        assert_eq!(stats.executed_moves.len(), 3);
+        assert_eq!(stats.result.placements, vec![0, 1]);
+        assert_eq!(stats.result.outcome(0), MatchOutcome::Win);
+
+        // The record keeps the reason and has no result
+        let record = GameRecord::parse(&stats.record.unwrap().write()).unwrap();
+        assert!(record.termination.unwrap().contains("time forfeit"));
+        assert!(record.final_scores.is_none());
+    }
+
+    // Never answers, like a client that disconnected
+    pub struct SilentPlayer;
+
+    #[async_trait::async_trait]
+    impl Player for SilentPlayer {
+        fn get_name(&self) -> &str {
+            "Silent"
+        }
+
+        async fn get_move(&mut self, _game_state: &GameState) -> Move {
+            std::future::pending().await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cancel_match() {
+        let driver = MatchDriver::new(TimeControl::Unlimited);
+        let mut rng = SmallRng::seed_from_u64(0);
+
+        // The driver can run several matches
+        for seed in 0..2 {
+            let stats = driver
+                .run(
+                    GameState::new(2, &mut rng),
+                    &mut mock_players(2),
+                    seed,
+                    &mut [],
+                )
+                .await
+                .unwrap();
+            assert!(stats.forfeit.is_none());
+        }
+
+        // Cancelling stops a match that waits for a move
+        let mut players = mock_players(2);
+        players[1] = Box::new(SilentPlayer);
+        let cancel_token = driver.cancel_token();
+        tokio::spawn(async move {
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            cancel_token.cancel();
         });
-        let player4: Box<dyn Player> = Box::new(MockPlayer {
-            name: "Player 4".to_string(),
+        let result = driver
+            .run(GameState::new(2, &mut rng), &mut players, 0, &mut [])
+            .await;
+        assert_eq!(result.err(), Some(GameError::Cancelled));
+
+        // A cancelled driver doesn't start another match
+        let result = driver
+            .run(
+                GameState::new(2, &mut rng),
+                &mut mock_players(2),
+                0,
+                &mut [],
+            )
+            .await;
+        assert_eq!(result.err(), Some(GameError::Cancelled));
+    }
+
+    #[tokio::test]
+    async fn test_round_end_without_factories() {
+        let mut observer = CountingObserver::default();
+        let stats = run_match(
+            GameState::new_without_factories(3, crate::Ruleset::OFFICIAL),
+            &mut mock_players(3),
+            0,
+            TimeControl::Unlimited,
+            &mut [&mut observer],
+        )
+        .await
+        .unwrap();
+        // The first refill isn't preceded by a round end, the end of the game is
+        assert_eq!(observer.refills as u32, stats.num_factory_refills);
+        assert_eq!(observer.round_ends as u32, stats.num_factory_refills);
+    }
+
+    // Takes the tiles of an empty factory and counts how often it was reset
+    pub struct IllegalPlayer {
+        resets: Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    #[async_trait::async_trait]
+    impl Player for IllegalPlayer {
+        fn get_name(&self) -> &str {
+            "Illegal"
+        }
+
+        async fn get_move(&mut self, game_state: &GameState) -> Move {
+            let factory = (0..crate::MAX_FACTORIES)
+                .find(|&index| game_state.get_factories()[index].iter().all(|&n| n == 0))
+                .unwrap();
+            Move {
+                take_from_factory_index: factory as u8,
+                color: crate::TileColor::Blue,
+                pattern: [1, 0, 0, 0, 0, 0],
+            }
+        }
+
+        async fn reset(&mut self) {
+            self.resets.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_illegal_move() {
+        let resets = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let mut players = mock_players(2);
+        players[1] = Box::new(IllegalPlayer {
+            resets: resets.clone(),
         });
-        let mut players = match NUM_PLAYERS {
-            2 => vec![player1, player2],
-            3 => vec![player1, player2, player3],
-            _ => vec![player1, player2, player3, player4],
-        };
+        let mut observer = CountingObserver::default();
         let mut rng = SmallRng::seed_from_u64(0);
-        run_match(GameState::new(&mut rng), &mut players, false)
+        let result = run_match(
+            GameState::new(2, &mut rng),
+            &mut players,
+            0,
+            TimeControl::Unlimited,
+            &mut [&mut observer],
+        )
+        .await;
+        // The observers learn about the illegal move and the players are reset like after a cancelled match
+        assert_eq!(result.err(), Some(GameError::IllegalMove));
+        assert_eq!(observer.moves, 1);
+        assert_eq!(observer.illegal_moves, 1);
+        assert_eq!(observer.game_overs, 0);
+        assert_eq!(resets.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_match_player_count_mismatch() {
+        let mut players = mock_players(3);
+        let mut rng = SmallRng::seed_from_u64(0);
+        let result = run_match(
+            GameState::new(2, &mut rng),
+            &mut players,
+            0,
+            TimeControl::Unlimited,
+            &mut [],
+        )
+        .await;
+        assert_eq!(result.err(), Some(GameError::PlayerCountMismatch));
+    }
+
+    #[tokio::test]
+    async fn test_seeded_match_replay() {
+        for seed in 0..3 {
+            let start = GameState::new_without_factories(2, crate::Ruleset::OFFICIAL);
+            let first = run_match(
+                start.clone(),
+                &mut mock_players(2),
+                seed,
+                TimeControl::Unlimited,
+                &mut [],
+            )
             .await
             .unwrap();
+            let second = run_match(
+                start.clone(),
+                &mut mock_players(2),
+                seed,
+                TimeControl::Unlimited,
+                &mut [],
+            )
+            .await
+            .unwrap();
+            let mut first_record = first.record.clone().unwrap();
+            let mut second_record = second.record.unwrap();
+            assert!(first_record.start_position.is_none());
+            // Only the date may differ if the games are played around midnight
+            first_record.date = None;
+            second_record.date = None;
+            assert_eq!(first_record, second_record);
+            assert_eq!(
+                first_record.replay().unwrap().last().unwrap().get_scores(),
+                first_record.final_scores.as_ref().unwrap().as_slice()
+            );
+
+            let moves: Vec<Move> = first
+                .executed_moves
+                .iter()
+                .map(|(_, _, mov, _)| *mov)
+                .collect();
+            let states = replay(&start, seed, &moves).unwrap();
+            assert_eq!(states.len(), moves.len() + 1);
+            for (state, (executed_state, _, _, _)) in states.iter().zip(&first.executed_moves) {
+                assert_eq!(state, executed_state);
+            }
+            let final_scores: Vec<i16> = first
+                .player_statistics
+                .iter()
+                .map(|player_statistics| player_statistics.final_score)
+                .collect();
+            assert_eq!(states.last().unwrap().get_scores(), final_scores.as_slice());
+
+            // The same moves are illegal with other factory draws
+            assert!(replay(&start, seed + 100, &moves).is_err());
+        }
     }
 }
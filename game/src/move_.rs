@@ -1,6 +1,9 @@
-use crate::factories::CENTER_FACTORY_INDEX;
-use crate::tile_color::TileColor;
+use crate::tile_color::{InvalidTileColor, TileColor};
 use std::fmt::Write as _;
+use std::str::FromStr;
+
+// Factory index of the moves that tile a full pattern line on a free wall, the pattern holds column + 1 in the row of the line
+pub const WALL_TILING_INDEX: u8 = u8::MAX;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Move {
@@ -16,15 +19,36 @@ impl Move {
         pattern: [255; 6],
     };
 
+    pub fn new_wall_tiling(row: usize, column: usize, color: TileColor) -> Self {
+        let mut pattern = [0; 6];
+        pattern[row] = column as u8 + 1;
+        Self {
+            take_from_factory_index: WALL_TILING_INDEX,
+            color,
+            pattern,
+        }
+    }
+
+    #[inline]
+    pub fn is_wall_tiling(&self) -> bool {
+        self.take_from_factory_index == WALL_TILING_INDEX
+    }
+
+    // Row and column of the wall field a wall tiling move places its tile on
+    pub fn get_wall_tiling_field(&self) -> (usize, usize) {
+        debug_assert!(self.is_wall_tiling());
+        let row = self.pattern.iter().take(5).position(|&x| x > 0).unwrap();
+        (row, self.pattern[row] as usize - 1)
+    }
+
     pub fn serialize_string(&self) -> String {
         let mut result = String::new();
-        write!(
-            result,
-            "{}{}",
-            self.take_from_factory_index,
-            char::from(self.color)
-        )
-        .unwrap();
+        if self.is_wall_tiling() {
+            result.push('T');
+        } else {
+            write!(result, "{}", self.take_from_factory_index).unwrap();
+        }
+        result.push(char::from(self.color));
 
         self.pattern.iter().fold(&mut result, |acc, &x| {
             write!(acc, "{:02}", x).unwrap();
@@ -34,57 +58,160 @@ impl Move {
         result
     }
 
-    pub fn deserialize_string(string: &str) -> Self {
-        let mut chars = string.chars();
-        let take_from_factory_index = chars.next().unwrap().to_digit(10).unwrap() as u8;
-        let color = TileColor::from(chars.next().unwrap());
+    pub fn deserialize_string(string: &str) -> Result<Self, MoveParseError> {
+        string.parse()
+    }
+
+    pub fn is_discard_only(&self) -> bool {
+        !self.is_wall_tiling() && self.pattern.iter().take(5).all(|&x| x == 0)
+    }
+}
+
+// Describes which part of a serialized move could not be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MoveParseError {
+    MissingFactoryIndex,
+    InvalidFactoryIndex(char),
+    MissingColor,
+    InvalidColor(char),
+    // Number of characters found for the pattern, 12 are expected
+    InvalidPatternLength(usize),
+    // Line 0-4 are the pattern lines, line 5 is the floor line
+    InvalidPatternEntry { line: usize, value: String },
+}
+
+impl std::fmt::Display for MoveParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MissingFactoryIndex => write!(f, "Missing factory index"),
+            Self::InvalidFactoryIndex(c) => write!(
+                f,
+                "Invalid factory index '{}', expected a digit or 'T' for a wall tiling",
+                c
+            ),
+            Self::MissingColor => write!(f, "Missing tile color"),
+            Self::InvalidColor(c) => write!(f, "{}", InvalidTileColor(*c)),
+            Self::InvalidPatternLength(length) => write!(
+                f,
+                "Invalid pattern length {}, expected 12 digits (two per pattern line and floor line)",
+                length
+            ),
+            Self::InvalidPatternEntry { line: 5, value } => {
+                write!(f, "Invalid tile count '{}' for the floor line", value)
+            }
+            Self::InvalidPatternEntry { line, value } => write!(
+                f,
+                "Invalid tile count '{}' for pattern line {}",
+                value,
+                line + 1
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MoveParseError {}
+
+impl FromStr for Move {
+    type Err = MoveParseError;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        let mut chars = string.trim().chars();
+        let take_from_factory_index = match chars.next() {
+            None => return Err(MoveParseError::MissingFactoryIndex),
+            Some('T') => WALL_TILING_INDEX,
+            Some(c) => match c.to_digit(10) {
+                Some(index) => index as u8,
+                None => return Err(MoveParseError::InvalidFactoryIndex(c)),
+            },
+        };
+        let color = match chars.next() {
+            None => return Err(MoveParseError::MissingColor),
+            Some(c) => TileColor::try_from(c).map_err(|_| MoveParseError::InvalidColor(c))?,
+        };
+
+        let pattern_chars: Vec<char> = chars.collect();
+        if pattern_chars.len() != 12 {
+            return Err(MoveParseError::InvalidPatternLength(pattern_chars.len()));
+        }
         let mut pattern = [0; 6];
-        let pattern_str: String = chars.collect();
-        for (i, chunk) in pattern_str.as_bytes().chunks(2).enumerate() {
-            pattern[i] = std::str::from_utf8(chunk).unwrap().parse::<u8>().unwrap();
+        for (line, chunk) in pattern_chars.chunks(2).enumerate() {
+            match (chunk[0].to_digit(10), chunk[1].to_digit(10)) {
+                (Some(tens), Some(ones)) => pattern[line] = (tens * 10 + ones) as u8,
+                _ => {
+                    return Err(MoveParseError::InvalidPatternEntry {
+                        line,
+                        value: chunk.iter().collect(),
+                    })
+                }
+            }
         }
-        Self {
+
+        Ok(Self {
             take_from_factory_index,
             color,
             pattern,
-        }
-    }
-
-    pub fn is_discard_only(&self) -> bool {
-        self.pattern.iter().take(5).all(|&x| x == 0)
+        })
     }
 }
 
+// Moves are displayed in the short notation, see notation.rs
 impl std::fmt::Display for Move {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         if self == &Self::DUMMY {
             return write!(f, "Dummy Move");
         }
-        let factory = if self.take_from_factory_index == CENTER_FACTORY_INDEX as u8 {
-            "c".to_string()
-        } else {
-            format!("{}", self.take_from_factory_index + 1)
-        };
-        let taken_tile_count = self.pattern.iter().sum::<u8>();
-        let discards = self.pattern[5];
-        let color_string = self.color.to_string();
-
-        let mut destination_string = String::new();
-        for i in 0..5 {
-            if self.pattern[i] > 0 {
-                destination_string.push_str(&format!("{}@{}", self.pattern[i], i + 1));
-                destination_string.push(' ');
-            }
-        }
-        if discards > 0 {
-            destination_string.push_str(&format!("D{}", discards));
-        }
-        let destination_string = destination_string.trim_end();
+        write!(f, "{}", self.to_notation())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        write!(
-            f,
-            "{}{}{}->{}",
-            taken_tile_count, color_string, factory, destination_string
-        )
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!("".parse::<Move>(), Err(MoveParseError::MissingFactoryIndex));
+        assert_eq!(
+            "xR000000000000".parse::<Move>(),
+            Err(MoveParseError::InvalidFactoryIndex('x'))
+        );
+        assert_eq!("3".parse::<Move>(), Err(MoveParseError::MissingColor));
+        assert_eq!(
+            "3G000000000000".parse::<Move>(),
+            Err(MoveParseError::InvalidColor('G'))
+        );
+        assert_eq!(
+            "3R0000".parse::<Move>(),
+            Err(MoveParseError::InvalidPatternLength(4))
+        );
+        assert_eq!(
+            "3R00000a000000".parse::<Move>(),
+            Err(MoveParseError::InvalidPatternEntry {
+                line: 2,
+                value: "0a".to_string()
+            })
+        );
+        assert_eq!(
+            "3R0000000000-1".parse::<Move>(),
+            Err(MoveParseError::InvalidPatternEntry {
+                line: 5,
+                value: "-1".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_round_trip() {
+        let moves = [
+            Move {
+                take_from_factory_index: 9,
+                color: TileColor::Green,
+                pattern: [1, 0, 0, 2, 0, 1],
+            },
+            Move::new_wall_tiling(3, 4, TileColor::White),
+        ];
+        for mov in moves {
+            assert_eq!(mov.serialize_string().parse::<Move>(), Ok(mov));
+        }
     }
 }
@@ -0,0 +1,302 @@
+use crate::factories::CENTER_FACTORY_INDEX;
+use crate::game_state::{GameState, IllegalMoveReason};
+use crate::move_::{Move, WALL_TILING_INDEX};
+use crate::tile_color::TileColor;
+
+/*
+    Short move notation for logs, principal variations and the command line: <source><color>><targets>
+
+    source:  Factory 1-9 (counted from 1) or C for the center
+    color:   B (blue), Y (yellow), R (red), G (green) or W (white)
+    targets: One character per tile, 1-5 for a pattern line and F for the floor line
+
+    Examples: 3R>22F takes three red tiles from factory 3, two go to pattern line 2 and one to the floor line.
+              CB>F takes one blue tile from the center and discards it.
+    A wall tiling on the free wall is written T<color>><row>@<column>, e.g. TR>3@2 tiles the red pattern line 3 to column 2.
+
+    Green is G, as on the colored board that Display prints for a GameState. Move::serialize_string, which the test client
+    protocol uses, writes green as K (char::from(TileColor)) and keeps it for the existing clients, so K is accepted too.
+
+    Letters are case insensitive. Given a position (GameState::parse_move_notation), targets may be abbreviated:
+    The tiles that are not written go to the written pattern lines as far as they fit and the rest to the floor line.
+    With 3 red tiles in factory 3 and an empty pattern line 2, 3R>2 is the same move as 3R>22F.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotationError {
+    Empty,
+    InvalidSource(char),
+    MissingColor,
+    InvalidColor(char),
+    MissingTargets,
+    InvalidTarget(char),
+    InvalidWallField,
+    IllegalMove(IllegalMoveReason),
+}
+
+impl std::fmt::Display for NotationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Empty => write!(f, "Empty move"),
+            Self::InvalidSource(c) => write!(
+                f,
+                "Invalid source '{}', expected a factory 1-9, C for the center or T for a wall tiling",
+                c
+            ),
+            Self::MissingColor => write!(f, "Missing tile color"),
+            Self::InvalidColor(c) => write!(
+                f,
+                "Invalid tile color '{}', expected one of B, Y, R, G, W",
+                c
+            ),
+            Self::MissingTargets => write!(f, "Missing '>' followed by the target lines"),
+            Self::InvalidTarget(c) => write!(
+                f,
+                "Invalid target '{}', expected a pattern line 1-5 or F for the floor line",
+                c
+            ),
+            Self::InvalidWallField => write!(
+                f,
+                "Invalid wall field, expected <row>@<column> with row and column between 1 and 5"
+            ),
+            Self::IllegalMove(reason) => write!(f, "Illegal move: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for NotationError {}
+
+pub(crate) fn color_from_notation(c: char) -> Result<TileColor, NotationError> {
+    match c {
+        'B' => Ok(TileColor::Blue),
+        'Y' => Ok(TileColor::Yellow),
+        'R' => Ok(TileColor::Red),
+        'G' | 'K' => Ok(TileColor::Green),
+        'W' => Ok(TileColor::White),
+        _ => Err(NotationError::InvalidColor(c)),
+    }
+}
+
+pub(crate) fn color_to_notation(color: TileColor) -> char {
+    match color {
+        TileColor::Blue => 'B',
+        TileColor::Yellow => 'Y',
+        TileColor::Red => 'R',
+        TileColor::Green => 'G',
+        TileColor::White => 'W',
+    }
+}
+
+fn parse_wall_field(targets: &str) -> Result<(usize, usize), NotationError> {
+    let (row, column) = targets
+        .split_once('@')
+        .ok_or(NotationError::InvalidWallField)?;
+    let parse = |value: &str| match value.parse::<usize>() {
+        Ok(value) if (1..=5).contains(&value) => Ok(value - 1),
+        _ => Err(NotationError::InvalidWallField),
+    };
+    Ok((parse(row)?, parse(column)?))
+}
+
+impl Move {
+    pub fn to_notation(&self) -> String {
+        let mut result = String::new();
+        if self.is_wall_tiling() {
+            let (row, column) = self.get_wall_tiling_field();
+            result.push('T');
+            result.push(color_to_notation(self.color));
+            result.push_str(&format!(">{}@{}", row + 1, column + 1));
+            return result;
+        }
+
+        if self.take_from_factory_index as usize == CENTER_FACTORY_INDEX {
+            result.push('C');
+        } else {
+            result.push_str(&(self.take_from_factory_index + 1).to_string());
+        }
+        result.push(color_to_notation(self.color));
+        result.push('>');
+        for (line, &count) in self.pattern.iter().enumerate() {
+            let target = if line == 5 {
+                'F'
+            } else {
+                char::from(b'1' + line as u8)
+            };
+            for _ in 0..count {
+                result.push(target);
+            }
+        }
+        result
+    }
+
+    // Parses the full notation, every tile of the move has to be written
+    pub fn from_notation(notation: &str) -> Result<Self, NotationError> {
+        let notation = notation.trim().to_ascii_uppercase();
+        let mut chars = notation.chars();
+
+        let take_from_factory_index = match chars.next() {
+            None => return Err(NotationError::Empty),
+            Some('T') => WALL_TILING_INDEX,
+            Some('C') => CENTER_FACTORY_INDEX as u8,
+            Some(c @ '1'..='9') => c as u8 - b'1',
+            Some(c) => return Err(NotationError::InvalidSource(c)),
+        };
+        let color = color_from_notation(chars.next().ok_or(NotationError::MissingColor)?)?;
+        let targets = match chars.as_str().strip_prefix('>') {
+            Some(targets) if !targets.is_empty() => targets,
+            _ => return Err(NotationError::MissingTargets),
+        };
+
+        if take_from_factory_index == WALL_TILING_INDEX {
+            let (row, column) = parse_wall_field(targets)?;
+            return Ok(Self::new_wall_tiling(row, column, color));
+        }
+
+        let mut pattern = [0u8; 6];
+        for target in targets.chars() {
+            let line = match target {
+                '1'..='5' => target as usize - '1' as usize,
+                'F' => 5,
+                _ => return Err(NotationError::InvalidTarget(target)),
+            };
+            pattern[line] = pattern[line].saturating_add(1);
+        }
+        Ok(Self {
+            take_from_factory_index,
+            color,
+            pattern,
+        })
+    }
+}
+
+impl GameState {
+    // Parses a move in this position and checks that it is legal, the targets may be abbreviated
+    pub fn parse_move_notation(&self, notation: &str) -> Result<Move, NotationError> {
+        let mut mov = Move::from_notation(notation)?;
+        if mov.is_wall_tiling() {
+            self.validate_move(mov)
+                .map_err(NotationError::IllegalMove)?;
+            return Ok(mov);
+        }
+
+        let factory_index = mov.take_from_factory_index as usize;
+        let available = if self
+            .get_factories()
+            .active_indices()
+            .any(|i| i == factory_index)
+        {
+            self.get_factories()[factory_index][mov.color as usize]
+        } else {
+            0
+        };
+        let written: u32 = mov.pattern.iter().map(|&x| x as u32).sum();
+        if written < available as u32 {
+            let mut remaining = available - written as u8;
+            let player = usize::from(self.get_current_player());
+            let occupancy = self.get_pattern_lines_occupancy()[player];
+            for (line, tiles) in mov.pattern.iter_mut().take(5).enumerate() {
+                if *tiles == 0 {
+                    continue;
+                }
+                let space = (line as u8 + 1).saturating_sub(occupancy[line].saturating_add(*tiles));
+                let added = space.min(remaining);
+                *tiles += added;
+                remaining -= added;
+            }
+            mov.pattern[5] += remaining;
+        }
+
+        self.validate_move(mov)
+            .map_err(NotationError::IllegalMove)?;
+        Ok(mov)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::random_positions;
+    use crate::Ruleset;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_notation_round_trip() {
+        for position in random_positions(6) {
+            for move_ in &position.moves {
+                let notation = move_.to_notation();
+                assert_eq!(Move::from_notation(&notation), Ok(*move_));
+                assert_eq!(
+                    position.game_state.parse_move_notation(&notation),
+                    Ok(*move_)
+                );
+                assert_eq!(move_.to_string(), notation);
+            }
+        }
+    }
+
+    #[test]
+    fn test_notation_examples() {
+        let mov = Move::from_notation("3R>22F").unwrap();
+        assert_eq!(mov.take_from_factory_index, 2);
+        assert_eq!(mov.color, TileColor::Red);
+        assert_eq!(mov.pattern, [0, 2, 0, 0, 0, 1]);
+
+        let mov = Move::from_notation("cb>f").unwrap();
+        assert_eq!(mov.take_from_factory_index as usize, CENTER_FACTORY_INDEX);
+        assert_eq!(mov.color, TileColor::Blue);
+        assert_eq!(mov.pattern, [0, 0, 0, 0, 0, 1]);
+
+        let mov = Move::from_notation("TR>3@2").unwrap();
+        assert_eq!(mov, Move::new_wall_tiling(2, 1, TileColor::Red));
+
+        assert_eq!(Move::from_notation(""), Err(NotationError::Empty));
+        assert_eq!(
+            Move::from_notation("0R>1"),
+            Err(NotationError::InvalidSource('0'))
+        );
+        assert_eq!(Move::from_notation("3"), Err(NotationError::MissingColor));
+        assert_eq!(
+            Move::from_notation("3X>1"),
+            Err(NotationError::InvalidColor('X'))
+        );
+        assert_eq!(
+            Move::from_notation("3R"),
+            Err(NotationError::MissingTargets)
+        );
+        assert_eq!(
+            Move::from_notation("3R>6"),
+            Err(NotationError::InvalidTarget('6'))
+        );
+        assert_eq!(
+            Move::from_notation("TR>3@6"),
+            Err(NotationError::InvalidWallField)
+        );
+    }
+
+    #[test]
+    fn test_abbreviated_notation() {
+        let mut rng: rand::rngs::SmallRng = SeedableRng::seed_from_u64(0);
+        let mut game_state = GameState::new_with_ruleset(2, Ruleset::OFFICIAL, &mut rng);
+        let mut factories = crate::Factories::empty(2);
+        factories[2][TileColor::Red as usize] = 3;
+        factories[2][TileColor::Blue as usize] = 1;
+        game_state.set_factories(factories);
+
+        let mov = game_state.parse_move_notation("3R>2").unwrap();
+        assert_eq!(mov.pattern, [0, 2, 0, 0, 0, 1]);
+        let mov = game_state.parse_move_notation("3R>F").unwrap();
+        assert_eq!(mov.pattern, [0, 0, 0, 0, 0, 3]);
+        assert_eq!(
+            game_state.parse_move_notation("3R>12"),
+            Err(NotationError::IllegalMove(
+                IllegalMoveReason::MultiplePatternLines
+            ))
+        );
+        assert!(matches!(
+            game_state.parse_move_notation("1R>2"),
+            Err(NotationError::IllegalMove(
+                IllegalMoveReason::ColorNotInFactory { .. }
+            ))
+        ));
+    }
+}
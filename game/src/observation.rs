@@ -0,0 +1,232 @@
+use crate::factories::{CENTER_FACTORY_INDEX, MAX_FACTORIES};
+use crate::game_state::GameState;
+use crate::move_::Move;
+use crate::ruleset::PatternLinePlacement;
+use crate::tile_color::{TileColor, NUM_TILE_COLORS};
+use crate::wall;
+use crate::MAX_PLAYERS;
+
+/*
+    Encoding of a position as a flat f32 vector for machine learning, and a fixed action space for the moves.
+
+    The players are seen from the perspective of the player to move: Board 0 is the current player, board 1 the next
+    player and so on. Boards of missing players (fewer than MAX_PLAYERS) are all zero. Counts are scaled to about 0..1.
+
+    Observation (OBSERVATION_SIZE values):
+    - Factory 1-9: Tiles of each color / 4, unused factories are zero (45)
+    - Center, bag, lid: Tiles of each color / 20 (3 * 5)
+    - First player marker still in the center (1)
+    - Number of players, one hot for 2, 3 and 4 (3)
+    - Ruleset: Single placement, free wall (2)
+    - For each board (PLAYER_FEATURES values):
+      - Player present (1)
+      - Wall: One plane of 25 fields per color, row by row (5 * 25)
+      - Pattern lines: Filled part of the line (tiles / capacity) in the channel of its color (5 lines * 5 colors)
+      - Floor line: One value per slot that is taken, including the first player marker (7)
+      - Score / 100 (1)
+      - Holds the first player marker (1)
+
+    Actions (NUM_ACTIONS): A move from a factory or the center is <source> * 30 + <color> * 6 + <target>.
+    The source is the factory index (the center is CENTER_FACTORY_INDEX), the target is the first pattern line that
+    receives tiles or 5 if all tiles go to the floor line. A wall tiling on the free wall is 300 + row * 5 + column.
+    With single placement every legal move has its own action. With spread placement moves that spread the tiles over
+    several lines share the action of their first line, action_to_move then gives the move that fills the target line
+    as far as possible and puts the rest on the floor line.
+*/
+
+const GLOBAL_FEATURES: usize =
+    (MAX_FACTORIES - 1) * NUM_TILE_COLORS + 3 * NUM_TILE_COLORS + 1 + 3 + 2;
+pub const PLAYER_FEATURES: usize = 1 + NUM_TILE_COLORS * 25 + 5 * NUM_TILE_COLORS + 7 + 1 + 1;
+pub const OBSERVATION_SIZE: usize = GLOBAL_FEATURES + MAX_PLAYERS * PLAYER_FEATURES;
+
+const TARGETS: usize = 6; // 5 pattern lines and the floor line
+const WALL_TILING_ACTIONS: usize = MAX_FACTORIES * NUM_TILE_COLORS * TARGETS;
+pub const NUM_ACTIONS: usize = WALL_TILING_ACTIONS + 25;
+
+const TILES_PER_COLOR: f32 = 20.;
+
+pub fn encode(game_state: &GameState) -> Vec<f32> {
+    let mut observation = vec![0.; OBSERVATION_SIZE];
+    encode_into(game_state, &mut observation);
+    observation
+}
+
+// Writes the observation to the first OBSERVATION_SIZE values of the buffer
+pub fn encode_into(game_state: &GameState, buffer: &mut [f32]) {
+    let observation = &mut buffer[..OBSERVATION_SIZE];
+    observation.fill(0.);
+    let mut values = observation.iter_mut();
+    let mut push = |value: f32| *values.next().unwrap() = value;
+
+    let factories = game_state.get_factories();
+    for factory in 0..CENTER_FACTORY_INDEX {
+        for &tiles in factories[factory].iter() {
+            push(tiles as f32 / 4.);
+        }
+    }
+    for bag in [
+        factories[CENTER_FACTORY_INDEX],
+        game_state.get_bag(),
+        game_state.get_out_of_bag(),
+    ] {
+        for tiles in bag {
+            push(tiles as f32 / TILES_PER_COLOR);
+        }
+    }
+    push(!game_state.get_tile_taken_from_center() as u8 as f32);
+    let num_players = game_state.get_num_players();
+    for players in 2..=MAX_PLAYERS {
+        push((num_players == players) as u8 as f32);
+    }
+    let ruleset = game_state.get_ruleset();
+    push((ruleset.pattern_line_placement == PatternLinePlacement::Single) as u8 as f32);
+    push(ruleset.is_free_wall() as u8 as f32);
+
+    let current_player = usize::from(game_state.get_current_player());
+    for board in 0..num_players {
+        let player = (current_player + board) % num_players;
+        push(1.);
+        let walls = &game_state.get_walls()[player];
+        for color_board in walls.iter() {
+            for row in 0..5 {
+                for column in 0..5 {
+                    push((color_board & wall::field_at(row, column) > 0) as u8 as f32);
+                }
+            }
+        }
+        let occupancy = game_state.get_pattern_lines_occupancy()[player];
+        let colors = game_state.get_pattern_lines_colors()[player];
+        for line in 0..5 {
+            for color in 0..NUM_TILE_COLORS {
+                let filled = match colors[line] {
+                    Some(line_color) if line_color as usize == color => {
+                        occupancy[line] as f32 / (line + 1) as f32
+                    }
+                    _ => 0.,
+                };
+                push(filled);
+            }
+        }
+        let floor_line = game_state.get_floor_line_progress()[player] as usize;
+        for slot in 0..7 {
+            push((slot < floor_line) as u8 as f32);
+        }
+        push(game_state.get_scores()[player] as f32 / 100.);
+        let holds_marker = game_state.get_tile_taken_from_center()
+            && usize::from(game_state.get_next_round_starting_player()) == player;
+        push(holds_marker as u8 as f32);
+    }
+}
+
+pub fn action_index(mov: &Move) -> usize {
+    if mov.is_wall_tiling() {
+        let (row, column) = mov.get_wall_tiling_field();
+        return WALL_TILING_ACTIONS + row * 5 + column;
+    }
+    let target = (0..5).find(|&line| mov.pattern[line] > 0).unwrap_or(5);
+    (mov.take_from_factory_index as usize * NUM_TILE_COLORS + mov.color as usize) * TARGETS + target
+}
+
+// The legal move for the action, None if the action isn't legal in this position
+pub fn action_to_move(game_state: &GameState, action: usize) -> Option<Move> {
+    if action >= NUM_ACTIONS {
+        return None;
+    }
+    let mov = if action >= WALL_TILING_ACTIONS {
+        let (player, line) = game_state.get_pending_wall_tiling()?;
+        let field = action - WALL_TILING_ACTIONS;
+        let color = game_state.get_pattern_lines_colors()[usize::from(player)][line]?;
+        Move::new_wall_tiling(field / 5, field % 5, color)
+    } else {
+        let factory = action / (NUM_TILE_COLORS * TARGETS);
+        let color = action / TARGETS % NUM_TILE_COLORS;
+        let target = action % TARGETS;
+        let tiles = game_state.get_factories()[factory][color];
+        if tiles == 0 {
+            return None;
+        }
+        let mut pattern = [0; 6];
+        if target < 5 {
+            let player = usize::from(game_state.get_current_player());
+            let free = (target as u8 + 1)
+                .saturating_sub(game_state.get_pattern_lines_occupancy()[player][target]);
+            if free == 0 {
+                return None;
+            }
+            pattern[target] = tiles.min(free);
+        }
+        pattern[5] = tiles - pattern[..5].iter().sum::<u8>();
+        Move {
+            take_from_factory_index: factory as u8,
+            color: TileColor::from(color),
+            pattern,
+        }
+    };
+    game_state.validate_move(mov).ok().map(|_| mov)
+}
+
+pub fn legal_action_mask(game_state: &GameState) -> Vec<bool> {
+    (0..NUM_ACTIONS)
+        .map(|action| action_to_move(game_state, action).is_some())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::random_positions;
+    use crate::GameStateBuilder;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_actions_match_legal_moves() {
+        for position in random_positions(12) {
+            let game_state = &position.game_state;
+            let legal_actions: HashSet<usize> = position.moves.iter().map(action_index).collect();
+            let mask = legal_action_mask(game_state);
+            for (action, is_legal) in mask.iter().enumerate() {
+                assert_eq!(*is_legal, legal_actions.contains(&action));
+            }
+            for mov in &position.moves {
+                let action_move = action_to_move(game_state, action_index(mov)).unwrap();
+                assert_eq!(action_index(&action_move), action_index(mov));
+                if game_state.get_ruleset().pattern_line_placement == PatternLinePlacement::Single {
+                    assert_eq!(action_move, *mov);
+                }
+            }
+            assert_eq!(encode(game_state).len(), OBSERVATION_SIZE);
+        }
+    }
+
+    #[test]
+    fn test_observation_perspective() {
+        let game_state = GameStateBuilder::new(3)
+            .factory(0, "BBYR")
+            .wall_row(1, 0, ".Y...")
+            .pattern_line(2, 2, TileColor::Red, 2)
+            .score(1, 40)
+            .current_player(1)
+            .build()
+            .unwrap();
+        let observation = encode(&game_state);
+        assert_eq!(&observation[..5], &[0.5, 0.25, 0.25, 0., 0.]);
+        // Three players
+        assert_eq!(
+            &observation[GLOBAL_FEATURES - 5..GLOBAL_FEATURES - 2],
+            &[0., 1., 0.]
+        );
+
+        // Player 2 is to move and comes first, player 3 second and player 1 third, the fourth board is empty
+        let board = |index: usize| {
+            let start = GLOBAL_FEATURES + index * PLAYER_FEATURES;
+            &observation[start..start + PLAYER_FEATURES]
+        };
+        assert_eq!(board(0)[0], 1.);
+        assert_eq!(board(0)[1 + TileColor::Yellow as usize * 25 + 1], 1.);
+        assert_eq!(board(0)[PLAYER_FEATURES - 2], 0.4);
+        let red_line_3 = 1 + 5 * 25 + 2 * NUM_TILE_COLORS + TileColor::Red as usize;
+        assert_eq!(board(1)[red_line_3], 2. / 3.);
+        assert_eq!(board(2)[0], 1.);
+        assert!(board(3).iter().all(|&value| value == 0.));
+    }
+}
@@ -0,0 +1,256 @@
+use rand::{rngs::SmallRng, SeedableRng};
+
+use crate::game_state::{GameState, RefillUndo, RoundUndo};
+use crate::move_::Move;
+use crate::move_list::MoveList;
+
+/*
+    Perft counts the leaf nodes of the game tree up to a fixed number of moves, the usual way to validate a move generator.
+    A factory refill is not counted as a move. When the tree reaches the end of a round, the refill is drawn from a generator
+    seeded with the perft seed and the hash of the position, so the counts don't depend on the order in which the tree is searched.
+    Positions in which the game is over before the depth is reached don't count as leaf nodes.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PerftReference {
+    pub name: &'static str,
+    pub position: &'static str, // Serialized game state, see GameState::serialize_string
+    pub seed: u64,
+    pub depth: usize,
+    pub nodes: u64,
+}
+
+impl PerftReference {
+    pub fn game_state(&self) -> GameState {
+        GameState::deserialize_string(self.position).expect("Invalid perft reference position")
+    }
+}
+
+// Leaf node counts of the current move generation, every change to them has to be explained by a rule change.
+// The positions at the end of a round and of the game cover the round evaluation, the refills and the final scoring.
+pub const PERFT_REFERENCES: [PerftReference; 10] = [
+    PerftReference {
+        name: "new game, 2 players",
+        position: "2_0_0_86236206100_0_0-0-0-0-0-0_65537000_0_0-0_0-0_1099511627775-1099511627775_0_0",
+        seed: 0,
+        depth: 3,
+        nodes: 2162784,
+    },
+    PerftReference {
+        name: "new game, 2 players",
+        position: "2_0_0_86236206100_0_0-0-0-0-0-0_65537000_0_0-0_0-0_1099511627775-1099511627775_0_0",
+        seed: 0,
+        depth: 4,
+        nodes: 217375976,
+    },
+    PerftReference {
+        name: "new game, 3 players, free wall",
+        position: "3_0_0_86236206100_0_0-0-0-0-0-0-0-0_4295032833000_0_0:0:0:0:0-0:0:0:0:0-0:0:0:0:0_0-0-0_1099511627775-1099511627775-1099511627775_0_3",
+        seed: 0,
+        depth: 4,
+        nodes: 72853992,
+    },
+    PerftReference {
+        name: "new game, 4 players",
+        position: "4_0_0_86236206100_0_0-0-0-0-0-0-0-0-0-0_281479271743489000_0_0-0-0-0_0-0-0-0_1099511627775-1099511627775-1099511627775-1099511627775_0_0",
+        seed: 0,
+        depth: 3,
+        nodes: 32836624,
+    },
+    PerftReference {
+        name: "end of round 3, 2 players",
+        position: "2_1_1_56019782670_12918719235_0-0-0-0-69649-4345298946_64685027_770_512-8_12901745153-4311810305_8640463873-8606777857_1_0",
+        seed: 0,
+        depth: 5,
+        nodes: 11214,
+    },
+    PerftReference {
+        name: "end of round 3, 3 players, free wall",
+        position: "3_2_1_43084025354_25871319298_0-0-0-0-0-0-0-67108864_4273557865444_132358_0:4:0:524288:0-8:0:4194304:0:0-0:0:1:0:0_8590000385-4345364736-17213424128_21458060036-8590196991-4345233663_1_3",
+        seed: 0,
+        depth: 5,
+        nodes: 85663,
+    },
+    PerftReference {
+        name: "end of round 3, 4 players",
+        position: "4_1_0_30081551113_21643002373_0-0-0-0-0-0-0-0-0-4328653312_281479267448587238_84083719_1032-1040-4-256_8606843137-4328653057-12935299329-12918456833_67305988-17180131584-8623620608-4345234180_1_0",
+        seed: 0,
+        depth: 5,
+        nodes: 100048,
+    },
+    PerftReference {
+        name: "end of game, 2 players",
+        position: "2_0_0_64659982603_16777218_0-0-0-0-64-4295033600_62849994_259_525907-4194846_12901810688-17213489409_8657240319-8590000128_1_0",
+        seed: 0,
+        depth: 4,
+        nodes: 320,
+    },
+    PerftReference {
+        name: "end of game, 3 players, free wall",
+        position: "3_1_1_768_38856624392_0-0-0-0-0-0-0-0_4226310472626_460803_65537:4:8:524368:16777218-264:0:4194818:4:128-1032:67108880:134217857:66:8704_50462720-8640463361-33686017_1095216988159-8590196993-1095250411780_1_3",
+        seed: 0,
+        depth: 4,
+        nodes: 8,
+    },
+    PerftReference {
+        name: "end of game, 4 players",
+        position: "4_2_0_21474837248_4429185799_8194-0-0-0-0-0-0-0-0-67110144_274442246999573441_67438850_268440603-9243-23-16778646_4362273024-17230332160-17230332160-8640397312_17246978815-17179935231-8623620863-8623685631_1_0",
+        seed: 0,
+        depth: 5,
+        nodes: 25758,
+    },
+];
+
+// Draws the refill at the end of a round and generates the moves. Returns None if the game is over.
+fn generate_moves(
+    game_state: &mut GameState,
+    seed: u64,
+    move_list: &mut MoveList,
+) -> Option<Option<(RoundUndo, RefillUndo)>> {
+    let mut round_end = None;
+    if game_state.get_factories().is_empty() && game_state.get_pending_wall_tiling().is_none() {
+        let mut rng = SmallRng::seed_from_u64(seed ^ game_state.get_hash());
+        let (is_game_over, round_undo) = game_state.evaluate_round_with_undo();
+        if is_game_over {
+            game_state.undo_evaluate_round(round_undo);
+            return None;
+        }
+        let refill_undo = game_state.fill_factories(&mut rng);
+        round_end = Some((round_undo, refill_undo));
+    }
+    // The factories are filled, so the move generation doesn't draw from the generator
+    let mut rng = SmallRng::seed_from_u64(seed);
+    game_state.get_possible_moves(move_list, &mut rng);
+    Some(round_end)
+}
+
+fn undo_round_end(game_state: &mut GameState, round_end: Option<(RoundUndo, RefillUndo)>) {
+    if let Some((round_undo, refill_undo)) = round_end {
+        game_state.undo_fill_factories(refill_undo);
+        game_state.undo_evaluate_round(round_undo);
+    }
+}
+
+impl GameState {
+    // Counts the positions that are reached after exactly depth moves
+    pub fn perft(&mut self, depth: usize, seed: u64) -> u64 {
+        if depth == 0 {
+            return 1;
+        }
+        let mut move_list = MoveList::default();
+        let round_end = match generate_moves(self, seed, &mut move_list) {
+            Some(round_end) => round_end,
+            None => return 0,
+        };
+        let nodes = if depth == 1 {
+            move_list.len() as u64
+        } else {
+            let mut nodes = 0;
+            for mov in &move_list {
+                let undo = self.do_move(*mov);
+                nodes += self.perft(depth - 1, seed);
+                self.undo_move(undo);
+            }
+            nodes
+        };
+        undo_round_end(self, round_end);
+        nodes
+    }
+
+    // Perft split up by the first move, to find the move that leads to a different count
+    pub fn perft_divide(&mut self, depth: usize, seed: u64) -> Vec<(Move, u64)> {
+        let mut move_list = MoveList::default();
+        if depth == 0 {
+            return Vec::new();
+        }
+        let round_end = match generate_moves(self, seed, &mut move_list) {
+            Some(round_end) => round_end,
+            None => return Vec::new(),
+        };
+        let mut result = Vec::with_capacity(move_list.len());
+        for mov in &move_list {
+            let undo = self.do_move(*mov);
+            result.push((*mov, self.perft(depth - 1, seed)));
+            self.undo_move(undo);
+        }
+        undo_round_end(self, round_end);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Ruleset;
+    use rand::Rng;
+
+    // Straightforward perft on copies of the game state, without any undo
+    fn perft_by_copy(game_state: &GameState, depth: usize, seed: u64) -> u64 {
+        if depth == 0 {
+            return 1;
+        }
+        let mut game_state = game_state.clone();
+        let mut move_list = MoveList::default();
+        let mut rng = SmallRng::seed_from_u64(seed ^ game_state.get_hash());
+        if game_state.get_possible_moves(&mut move_list, &mut rng)
+            == crate::MoveGenerationResult::GameOver
+        {
+            return 0;
+        }
+        let mut nodes = 0;
+        for mov in &move_list {
+            let mut child = game_state.clone();
+            child.do_move(*mov);
+            nodes += perft_by_copy(&child, depth - 1, seed);
+        }
+        nodes
+    }
+
+    #[test]
+    fn test_perft_matches_copy_make() {
+        for seed in 0..3 {
+            let mut rng = SmallRng::seed_from_u64(seed);
+            let ruleset = if seed % 2 == 0 {
+                Ruleset::default()
+            } else {
+                Ruleset::OFFICIAL_FREE_WALL
+            };
+            let mut game_state = GameState::new_without_factories(2 + seed as usize % 3, ruleset);
+            let mut move_list = MoveList::default();
+            // Random positions during the game, including the ends of rounds
+            for step in 0..40 {
+                if step % 4 == 0 {
+                    let before = game_state.clone();
+                    let nodes = perft_by_copy(&game_state, 2, seed);
+                    assert_eq!(game_state.perft(2, seed), nodes);
+                    assert_eq!(game_state, before);
+
+                    let divide = game_state.perft_divide(2, seed);
+                    assert_eq!(divide.iter().map(|(_, nodes)| nodes).sum::<u64>(), nodes);
+                    assert_eq!(game_state, before);
+                }
+
+                if game_state.get_possible_moves(&mut move_list, &mut rng)
+                    == crate::MoveGenerationResult::GameOver
+                {
+                    break;
+                }
+                game_state.do_move(move_list[rng.gen_range(0..move_list.len())]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_perft_references() {
+        // The deep references take too long in debug builds, they are checked by `azul perft --check`
+        for reference in PERFT_REFERENCES.iter().filter(|r| r.nodes < 200_000) {
+            let mut game_state = reference.game_state();
+            assert_eq!(
+                game_state.perft(reference.depth, reference.seed),
+                reference.nodes,
+                "{:?}",
+                reference
+            );
+        }
+    }
+}
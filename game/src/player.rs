@@ -1,7 +1,11 @@
-use super::NUM_PLAYERS;
-use crate::{move_::Move, GameState};
+use crate::{move_::Move, GameState, TimeControl};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
 pub struct PlayerMarker(u8);
 
 impl PlayerMarker {
@@ -11,8 +15,8 @@ impl PlayerMarker {
     }
 
     #[inline]
-    pub fn next(&self) -> Self {
-        Self((self.0 + 1) % (NUM_PLAYERS as u8))
+    pub fn next(&self, num_players: usize) -> Self {
+        Self((self.0 + 1) % (num_players as u8))
     }
 }
 
@@ -37,6 +41,12 @@ pub trait Player: Send + Sync {
     // Optional methods for settings and state updates that not all players need
     async fn notify_move(&mut self, _new_game_state: &GameState, _move_: Move) {}
     async fn set_time(&mut self, _time: u64) {}
+    // Sent with every move request of a match with a time limit. By default the player gets a per move budget from it.
+    async fn set_time_left(&mut self, time_control: TimeControl, time_left: u64) {
+        if let Some(budget) = time_control.move_budget(time_left) {
+            self.set_time(budget).await;
+        }
+    }
     async fn set_pondering(&mut self, _pondering: bool) {}
     async fn reset(&mut self) {}
 }
@@ -71,7 +81,9 @@ mod tests {
     #[test]
     fn test_player_marker() {
         let marker = PlayerMarker::new(0);
-        assert_eq!(marker.next(), PlayerMarker::new(1));
+        assert_eq!(marker.next(2), PlayerMarker::new(1));
+        assert_eq!(PlayerMarker::new(1).next(2), PlayerMarker::new(0));
+        assert_eq!(PlayerMarker::new(2).next(4), PlayerMarker::new(3));
     }
 
     #[tokio::test]
@@ -80,7 +92,7 @@ mod tests {
             name: "MockPlayer".to_string(),
         };
         let mut rng = SmallRng::seed_from_u64(0);
-        let game_state = GameState::new(&mut rng);
+        let game_state = GameState::new(2, &mut rng);
         let _move: Move = player.get_move(&game_state).await;
 
         assert_eq!(player.get_name(), "MockPlayer");
@@ -0,0 +1,475 @@
+use crate::factories::{num_factories_for_players, Factories, Factory};
+use crate::game_state::{Bag, GameState, GameStateParseError, IntegrityViolation};
+use crate::notation::{color_from_notation, color_to_notation};
+use crate::player::PlayerMarker;
+use crate::ruleset::{PatternLinePlacement, Ruleset, WallVariant};
+use crate::tile_color::{TileColor, NUM_TILE_COLORS};
+use crate::wall;
+use crate::{MAX_PLAYERS, MIN_PLAYERS};
+use std::collections::HashMap;
+
+/*
+    Versioned position format, a readable replacement of GameState::serialize_string for logs, records and the command line.
+    One line of space separated key=value fields after the version, e.g. for two players:
+
+    v1 players=2 factories=6 ruleset=single,colored current=1 next=2 center_taken=false bag=16,20,18,20,19 lid=0,0,0,0,0
+    tiles=BBYR,RGGW,YYYW,BRGW,BBRW,- p1.score=0 p1.floor=0 p1.wall=...../...../...../...../..... p1.lines=-/-/-/-/-
+    p2.score=4 p2.floor=1 p2.wall=.Y.../...../...../...../..... p2.lines=-/-/RR/-/-
+
+    - players, factories: The number of players and of factories including the center, both have to match.
+    - ruleset: <spread|single>,<colored|free>
+    - current, next: The player to move and the player starting the next round, counted from 1.
+    - center_taken: Whether a tile was taken from the center in this round (the first player marker is gone).
+    - bag, lid: Number of tiles of each color (blue, yellow, red, green, white) in the bag and in the box lid.
+    - tiles: The tiles of every factory, the center last. One letter per tile (see notation.rs), "-" if empty.
+    - p<n>.score, p<n>.floor: Score and number of tiles on the floor line (including the first player marker).
+    - p<n>.wall: The rows of the wall from top to bottom, one letter per tile and "." for an empty field.
+    - p<n>.lines: The pattern lines from top to bottom as one letter per tile, "-" if empty.
+
+    The fields may come in any order. GameState::parse_position reads this format and the legacy format of
+    GameState::serialize_string, which is recognized by the missing version prefix.
+*/
+
+pub const POSITION_FORMAT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PositionParseError {
+    UnsupportedVersion(String),
+    MissingField(String),
+    DuplicateField(String),
+    UnknownField(String),
+    InvalidField {
+        field: String,
+        value: String,
+    },
+    FactoryCountMismatch {
+        num_players: usize,
+        expected: usize,
+        found: usize,
+    },
+    InvalidGameState(Vec<IntegrityViolation>),
+    Legacy(GameStateParseError), // The string has no version and isn't a valid legacy position either
+}
+
+impl std::fmt::Display for PositionParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnsupportedVersion(version) => write!(
+                f,
+                "Unsupported position version '{}', expected v{}",
+                version, POSITION_FORMAT_VERSION
+            ),
+            Self::MissingField(field) => write!(f, "Missing {}", field),
+            Self::DuplicateField(field) => write!(f, "{} is given more than once", field),
+            Self::UnknownField(field) => write!(f, "Unknown field {}", field),
+            Self::InvalidField { field, value } => write!(f, "Invalid {} '{}'", field, value),
+            Self::FactoryCountMismatch {
+                num_players,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Expected {} factories for {} players, got {}",
+                expected, num_players, found
+            ),
+            Self::InvalidGameState(violations) => {
+                write!(f, "Invalid game state:")?;
+                for violation in violations {
+                    write!(f, "\n  {}", violation)?;
+                }
+                Ok(())
+            }
+            Self::Legacy(error) => write!(f, "Invalid legacy position: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for PositionParseError {}
+
+fn tiles_to_string(tiles: impl Iterator<Item = (TileColor, u8)>) -> String {
+    let string: String = tiles
+        .flat_map(|(color, count)| std::iter::repeat_n(color_to_notation(color), count as usize))
+        .collect();
+    if string.is_empty() {
+        "-".to_string()
+    } else {
+        string
+    }
+}
+
+fn join<T: ToString>(values: &[T], separator: &str) -> String {
+    values
+        .iter()
+        .map(|value| value.to_string())
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
+struct Fields<'a> {
+    values: HashMap<&'a str, &'a str>,
+}
+
+impl<'a> Fields<'a> {
+    fn get(&mut self, field: &str) -> Result<&'a str, PositionParseError> {
+        self.values
+            .remove(field)
+            .ok_or_else(|| PositionParseError::MissingField(field.to_string()))
+    }
+
+    fn parse<T: std::str::FromStr>(&mut self, field: &str) -> Result<T, PositionParseError> {
+        let value = self.get(field)?;
+        value.parse().map_err(|_| invalid_field(field, value))
+    }
+}
+
+fn invalid_field(field: &str, value: &str) -> PositionParseError {
+    PositionParseError::InvalidField {
+        field: field.to_string(),
+        value: value.to_string(),
+    }
+}
+
+fn parse_bag(field: &str, value: &str) -> Result<Bag, PositionParseError> {
+    let counts = value
+        .split(',')
+        .map(|count| count.parse::<u8>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| invalid_field(field, value))?;
+    counts.try_into().map_err(|_| invalid_field(field, value))
+}
+
+// Counts the tiles of each color in a string of letters, "-" is an empty list
+fn parse_tiles(field: &str, value: &str) -> Result<Factory, PositionParseError> {
+    let mut tiles = [0; NUM_TILE_COLORS];
+    if value == "-" {
+        return Ok(tiles);
+    }
+    for c in value.chars() {
+        let color = color_from_notation(c).map_err(|_| invalid_field(field, value))?;
+        tiles[color as usize] = tiles[color as usize]
+            .checked_add(1)
+            .ok_or_else(|| invalid_field(field, value))?;
+    }
+    Ok(tiles)
+}
+
+impl GameState {
+    pub fn to_position_string(&self) -> String {
+        let placement = match self.ruleset.pattern_line_placement {
+            PatternLinePlacement::Spread => "spread",
+            PatternLinePlacement::Single => "single",
+        };
+        let wall_variant = match self.ruleset.wall_variant {
+            WallVariant::Colored => "colored",
+            WallVariant::Free => "free",
+        };
+        let factory_tiles = self
+            .factories
+            .active_indices()
+            .map(|factory| {
+                tiles_to_string(
+                    self.factories[factory]
+                        .iter()
+                        .enumerate()
+                        .map(|(color, count)| (TileColor::from(color), *count)),
+                )
+            })
+            .collect::<Vec<_>>();
+        let mut fields = vec![
+            format!("v{}", POSITION_FORMAT_VERSION),
+            format!("players={}", self.get_num_players()),
+            format!("factories={}", self.factories.num_factories()),
+            format!("ruleset={},{}", placement, wall_variant),
+            format!("current={}", usize::from(self.current_player) + 1),
+            format!("next={}", usize::from(self.next_round_starting_player) + 1),
+            format!("center_taken={}", self.tile_taken_from_center),
+            format!("bag={}", join(&self.bag, ",")),
+            format!("lid={}", join(&self.out_of_bag, ",")),
+            format!("tiles={}", factory_tiles.join(",")),
+        ];
+        for player in 0..self.get_num_players() {
+            let rows = (0..5)
+                .map(|row| {
+                    (0..5)
+                        .map(|column| {
+                            let field = wall::field_at(row, column);
+                            (0..NUM_TILE_COLORS)
+                                .find(|&color| self.walls[player][color] & field > 0)
+                                .map_or('.', |color| color_to_notation(TileColor::from(color)))
+                        })
+                        .collect::<String>()
+                })
+                .collect::<Vec<_>>();
+            let lines = (0..5)
+                .map(|line| {
+                    let color = self.pattern_lines_colors[player][line];
+                    let count = self.pattern_lines_occupancy[player][line];
+                    tiles_to_string(color.map(|color| (color, count)).into_iter())
+                })
+                .collect::<Vec<_>>();
+            let key = format!("p{}", player + 1);
+            fields.push(format!("{}.score={}", key, self.scores[player]));
+            fields.push(format!(
+                "{}.floor={}",
+                key, self.floor_line_progress[player]
+            ));
+            fields.push(format!("{}.wall={}", key, rows.join("/")));
+            fields.push(format!("{}.lines={}", key, lines.join("/")));
+        }
+        fields.join(" ")
+    }
+
+    // Parses a position of any version or a legacy string of GameState::serialize_string
+    pub fn parse_position(string: &str) -> Result<Self, PositionParseError> {
+        let string = string.trim();
+        let mut words = string.split_whitespace();
+        let version = match words.next() {
+            Some(version) if version.starts_with('v') => version,
+            _ => return GameState::deserialize_string(string).map_err(PositionParseError::Legacy),
+        };
+        if version != format!("v{}", POSITION_FORMAT_VERSION) {
+            return Err(PositionParseError::UnsupportedVersion(version.to_string()));
+        }
+
+        let mut fields = Fields {
+            values: HashMap::new(),
+        };
+        for word in words {
+            let (key, value) = word
+                .split_once('=')
+                .ok_or_else(|| invalid_field("field", word))?;
+            if fields.values.insert(key, value).is_some() {
+                return Err(PositionParseError::DuplicateField(key.to_string()));
+            }
+        }
+
+        let num_players: usize = fields.parse("players")?;
+        if !(MIN_PLAYERS..=MAX_PLAYERS).contains(&num_players) {
+            return Err(invalid_field("players", &num_players.to_string()));
+        }
+        let expected = num_factories_for_players(num_players);
+        let num_factories: usize = fields.parse("factories")?;
+        let factory_tiles = fields.get("tiles")?.split(',').collect::<Vec<_>>();
+        for found in [num_factories, factory_tiles.len()] {
+            if found != expected {
+                return Err(PositionParseError::FactoryCountMismatch {
+                    num_players,
+                    expected,
+                    found,
+                });
+            }
+        }
+
+        let ruleset_value = fields.get("ruleset")?;
+        let pattern_line_placement = match ruleset_value.split_once(',') {
+            Some(("spread", _)) => PatternLinePlacement::Spread,
+            Some(("single", _)) => PatternLinePlacement::Single,
+            _ => return Err(invalid_field("ruleset", ruleset_value)),
+        };
+        let wall_variant = match ruleset_value.split_once(',') {
+            Some((_, "colored")) => WallVariant::Colored,
+            Some((_, "free")) => WallVariant::Free,
+            _ => return Err(invalid_field("ruleset", ruleset_value)),
+        };
+        let ruleset = Ruleset {
+            pattern_line_placement,
+            wall_variant,
+        };
+
+        let mut parse_player = |field: &str| {
+            let player: usize = fields.parse(field)?;
+            match player {
+                1..=MAX_PLAYERS if player <= num_players => Ok(PlayerMarker::new(player as u8 - 1)),
+                _ => Err(invalid_field(field, &player.to_string())),
+            }
+        };
+        let current_player = parse_player("current")?;
+        let next_round_starting_player = parse_player("next")?;
+
+        let mut game_state = GameState::new_without_factories(num_players, ruleset);
+        game_state.current_player = current_player;
+        game_state.next_round_starting_player = next_round_starting_player;
+        game_state.tile_taken_from_center = fields.parse("center_taken")?;
+        game_state.bag = parse_bag("bag", fields.get("bag")?)?;
+        game_state.out_of_bag = parse_bag("lid", fields.get("lid")?)?;
+
+        let mut factories = Factories::empty(num_players);
+        let factory_indices = factories.active_indices().collect::<Vec<_>>();
+        for (factory, tiles) in factory_indices.into_iter().zip(factory_tiles) {
+            factories[factory] = parse_tiles("tiles", tiles)?;
+        }
+        game_state.factories = factories;
+
+        for player in 0..num_players {
+            let key = format!("p{}", player + 1);
+            game_state.scores[player] = fields.parse(&format!("{}.score", key))?;
+            game_state.floor_line_progress[player] = fields.parse(&format!("{}.floor", key))?;
+
+            let wall_field = format!("{}.wall", key);
+            let wall_value = fields.get(&wall_field)?;
+            let rows = wall_value.split('/').collect::<Vec<_>>();
+            if rows.len() != 5 || rows.iter().any(|row| row.chars().count() != 5) {
+                return Err(invalid_field(&wall_field, wall_value));
+            }
+            for (row, fields) in rows.iter().enumerate() {
+                for (column, c) in fields.chars().enumerate() {
+                    if c == '.' {
+                        continue;
+                    }
+                    let color = color_from_notation(c)
+                        .map_err(|_| invalid_field(&wall_field, wall_value))?;
+                    game_state.walls[player][color as usize] |= wall::field_at(row, column);
+                    game_state.wall_occupancy[player] |= wall::field_at(row, column);
+                }
+            }
+
+            let lines_field = format!("{}.lines", key);
+            let lines_value = fields.get(&lines_field)?;
+            let lines = lines_value.split('/').collect::<Vec<_>>();
+            if lines.len() != 5 {
+                return Err(invalid_field(&lines_field, lines_value));
+            }
+            for (line, tiles) in lines.iter().enumerate() {
+                let tiles = parse_tiles(&lines_field, tiles)?;
+                let mut colors = (0..NUM_TILE_COLORS).filter(|&color| tiles[color] > 0);
+                let color = colors.next();
+                if colors.next().is_some() {
+                    return Err(invalid_field(&lines_field, lines_value));
+                }
+                game_state.pattern_lines_colors[player][line] = color.map(TileColor::from);
+                game_state.pattern_lines_occupancy[player][line] =
+                    color.map_or(0, |color| tiles[color]);
+            }
+        }
+
+        if let Some(field) = fields.values.keys().min() {
+            return Err(PositionParseError::UnknownField(field.to_string()));
+        }
+
+        game_state.hash = game_state.compute_hash();
+        let violations = game_state.integrity_violations();
+        if !violations.is_empty() {
+            return Err(PositionParseError::InvalidGameState(violations));
+        }
+        Ok(game_state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::random_games;
+    use crate::{GameStateBuilder, PERFT_REFERENCES};
+
+    #[test]
+    fn test_position_round_trip() {
+        for game in random_games(12) {
+            let game_states = game
+                .positions
+                .iter()
+                .flat_map(|position| [&position.before_generation, &position.game_state])
+                .chain([&game.final_state]);
+            for game_state in game_states {
+                let string = game_state.to_position_string();
+                assert_eq!(GameState::parse_position(&string), Ok(game_state.clone()));
+                // Legacy strings are migrated
+                let legacy = game_state.serialize_string();
+                assert_eq!(GameState::parse_position(&legacy), Ok(game_state.clone()));
+            }
+        }
+        for reference in PERFT_REFERENCES {
+            let game_state = GameState::parse_position(reference.position).unwrap();
+            assert_eq!(game_state, reference.game_state());
+        }
+    }
+
+    #[test]
+    fn test_position_format() {
+        let game_state = GameStateBuilder::new(2)
+            .factory(0, "BBYR")
+            .wall_row(1, 0, ".Y...")
+            .pattern_line(1, 2, TileColor::Red, 2)
+            .score(1, 4)
+            .build()
+            .unwrap();
+        let string = game_state.to_position_string();
+        assert!(
+            string.starts_with("v1 players=2 factories=6 ruleset=spread,colored current=1 next=1")
+        );
+        assert!(string.contains(" tiles=BBYR,-,-,-,-,- "));
+        assert!(string.ends_with(
+            "p2.score=4 p2.floor=0 p2.wall=.Y.../...../...../...../..... p2.lines=-/-/RR/-/-"
+        ));
+
+        // The fields may come in any order
+        let mut words = string.split(' ').collect::<Vec<_>>();
+        words[1..].reverse();
+        assert_eq!(GameState::parse_position(&words.join(" ")), Ok(game_state));
+    }
+
+    #[test]
+    fn test_position_errors() {
+        let string = GameState::new_without_factories(3, Ruleset::default()).to_position_string();
+        let replace =
+            |from: &str, to: &str| GameState::parse_position(&string.replacen(from, to, 1));
+
+        assert_eq!(
+            replace("v1", "v2"),
+            Err(PositionParseError::UnsupportedVersion("v2".to_string()))
+        );
+        // A 3 player position with the factory count of 2 players
+        assert_eq!(
+            replace("factories=8", "factories=6"),
+            Err(PositionParseError::FactoryCountMismatch {
+                num_players: 3,
+                expected: 8,
+                found: 6
+            })
+        );
+        assert_eq!(
+            replace("tiles=-,", "tiles="),
+            Err(PositionParseError::FactoryCountMismatch {
+                num_players: 3,
+                expected: 8,
+                found: 7
+            })
+        );
+        assert_eq!(
+            replace(" p3.score=0", ""),
+            Err(PositionParseError::MissingField("p3.score".to_string()))
+        );
+        assert_eq!(
+            replace("p3.score=0", "p3.score=0 p4.score=0"),
+            Err(PositionParseError::UnknownField("p4.score".to_string()))
+        );
+        assert_eq!(
+            replace("p1.score=0", "p1.score=0 p1.score=1"),
+            Err(PositionParseError::DuplicateField("p1.score".to_string()))
+        );
+        assert_eq!(
+            replace("current=1", "current=4"),
+            Err(invalid_field("current", "4"))
+        );
+        assert_eq!(
+            replace("p1.lines=-", "p1.lines=RB"),
+            Err(invalid_field("p1.lines", "RB/-/-/-/-"))
+        );
+        assert!(matches!(
+            replace("p1.lines=-", "p1.lines=RR"),
+            Err(PositionParseError::InvalidGameState(_))
+        ));
+        let many_tiles = format!("p1.lines={}", "R".repeat(257));
+        assert_eq!(
+            replace("p1.lines=-", &many_tiles),
+            Err(invalid_field("p1.lines", &"R".repeat(257)))
+        );
+        assert_eq!(
+            replace("tiles=-", &format!("tiles={}", "B".repeat(300))),
+            Err(invalid_field("tiles", &"B".repeat(300)))
+        );
+        assert!(matches!(
+            GameState::parse_position("3_0_0"),
+            Err(PositionParseError::Legacy(_))
+        ));
+    }
+}
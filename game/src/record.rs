@@ -0,0 +1,749 @@
+use crate::factories::{Factories, CENTER_FACTORY_INDEX};
+use crate::game_state::{GameState, IllegalMoveReason};
+use crate::match_::MatchResult;
+use crate::move_::Move;
+use crate::notation::{color_from_notation, color_to_notation, NotationError};
+use crate::position::PositionParseError;
+use crate::ruleset::Ruleset;
+use crate::score_report::ranking;
+use crate::tile_color::TileColor;
+use crate::{MAX_PLAYERS, MIN_PLAYERS};
+
+/*
+    Azul Game Notation (AGN): A PGN like text format that stores a complete game.
+
+    [Player1 "Monte Carlo Tree Search"]
+    [Player2 "Greedy Player"]
+    [Seed "42"]
+    [Ruleset "single placement, colored wall"]
+    [Date "2024.05.01"]
+    [Result "1"]
+
+    Refill BBYR RGGW YYYW BRGW BBRW
+    3R>11F CB>FF ...
+    Refill ...
+    ...
+    Scores 54 32
+    Rows 1 0
+
+    The header holds one tag per line, values are quoted and escape '"' and '\' with a backslash.
+    Player1..Player4 name the players in turn order, Seed (optional) is the seed the game was played with,
+    Date (optional) is written as YYYY.MM.DD and Result lists the winning players after the tie-break or is "*" for an unfinished game.
+    A game that doesn't start with empty boards has a Position tag with the game state before the first event (see position.rs).
+    Termination (optional) tells why a game ended early, such a game has no final scores.
+
+    The body lists the events of the game in order:
+    - "Refill" followed by the content of factory 1, 2, ... after the factories were filled, "-" for an empty factory.
+      If the factories were empty before, the round is evaluated first (except for the first refill of a new game).
+    - Moves in the short move notation (see notation.rs), the moves of a round are written on one line.
+    - "Scores" followed by the final score of every player once the game is over.
+    - "Rows" followed by the complete wall rows of every player at the end, they break ties of the final scores.
+    Lines starting with ';' are comments.
+*/
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordEvent {
+    Refill(Factories),
+    Move(Move),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GameRecord {
+    pub players: Vec<String>,
+    pub seed: Option<u64>,
+    pub ruleset: Ruleset,
+    pub date: Option<String>,
+    // The game state before the first event if the game didn't start with empty boards and factories
+    pub start_position: Option<GameState>,
+    pub events: Vec<RecordEvent>,
+    pub final_scores: Option<Vec<i16>>,
+    // Complete wall rows of every player at the end of the game, the tie-break of equal final scores
+    pub complete_rows: Option<Vec<u32>>,
+    // Why the game ended before its end, e.g. a time forfeit
+    pub termination: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordParseError {
+    InvalidTag { line: usize },
+    InvalidTagValue { tag: String, value: String },
+    InvalidPlayers,
+    InvalidPosition(PositionParseError),
+    InvalidRefill { line: usize, factory: String },
+    InvalidMove { line: usize, error: NotationError },
+    InvalidScores { line: usize },
+    InvalidRows { line: usize },
+}
+
+impl std::fmt::Display for RecordParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidTag { line } => write!(f, "Line {}: Invalid tag", line),
+            Self::InvalidTagValue { tag, value } => {
+                write!(f, "Invalid value '{}' for tag {}", value, tag)
+            }
+            Self::InvalidPlayers => write!(
+                f,
+                "Expected the tags Player1 to PlayerN for {} to {} players",
+                MIN_PLAYERS, MAX_PLAYERS
+            ),
+            Self::InvalidPosition(error) => write!(f, "Invalid position: {}", error),
+            Self::InvalidRefill { line, factory } => {
+                write!(f, "Line {}: Invalid factory '{}'", line, factory)
+            }
+            Self::InvalidMove { line, error } => write!(f, "Line {}: {}", line, error),
+            Self::InvalidScores { line } => write!(f, "Line {}: Invalid scores", line),
+            Self::InvalidRows { line } => write!(f, "Line {}: Invalid complete rows", line),
+        }
+    }
+}
+
+impl std::error::Error for RecordParseError {}
+
+// Why a record can't be replayed, the index is the position of the event in GameRecord::events
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplayError {
+    IllegalMove {
+        index: usize,
+        reason: IllegalMoveReason,
+    },
+    ImpossibleRefill {
+        index: usize,
+    },
+    GameOver {
+        index: usize,
+    },
+    GameNotOver,
+    ScoreMismatch {
+        recorded: Vec<i16>,
+        replayed: Vec<i16>,
+    },
+    CompleteRowsMismatch {
+        recorded: Vec<u32>,
+        replayed: Vec<u32>,
+    },
+}
+
+impl std::fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::IllegalMove { index, reason } => {
+                write!(f, "Event {}: Illegal move: {}", index + 1, reason)
+            }
+            Self::ImpossibleRefill { index } => write!(
+                f,
+                "Event {}: The factories can't be refilled like this",
+                index + 1
+            ),
+            Self::GameOver { index } => {
+                write!(f, "Event {}: The game is already over", index + 1)
+            }
+            Self::GameNotOver => write!(f, "The record has final scores but the game isn't over"),
+            Self::ScoreMismatch { recorded, replayed } => write!(
+                f,
+                "The recorded scores {:?} differ from the replayed scores {:?}",
+                recorded, replayed
+            ),
+            Self::CompleteRowsMismatch { recorded, replayed } => write!(
+                f,
+                "The recorded complete rows {:?} differ from the replayed complete rows {:?}",
+                recorded, replayed
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+fn factories_to_string(factories: &Factories) -> String {
+    factories
+        .iter()
+        .take(factories.num_factories() - 1)
+        .map(|factory| {
+            let tiles: String = factory
+                .iter()
+                .enumerate()
+                .flat_map(|(color, &number_of_tiles)| {
+                    std::iter::repeat_n(
+                        color_to_notation(TileColor::from(color)),
+                        number_of_tiles as usize,
+                    )
+                })
+                .collect();
+            if tiles.is_empty() {
+                "-".to_string()
+            } else {
+                tiles
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// Parses a header line of the form [Tag "value"]
+fn parse_tag(line: &str) -> Option<(&str, String)> {
+    let line = line.strip_prefix('[')?.strip_suffix(']')?;
+    let (tag, value) = line.split_once(' ')?;
+    let value = value.trim().strip_prefix('"')?.strip_suffix('"')?;
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unescaped.push(chars.next()?),
+            '"' => return None,
+            c => unescaped.push(c),
+        }
+    }
+    Some((tag, unescaped))
+}
+
+// Date in the YYYY.MM.DD format of the Date tag
+pub fn current_date() -> String {
+    let seconds = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    // Conversion of the days since 1970-01-01 to a date in the proleptic Gregorian calendar
+    let days = (seconds / 86400) as i64 + 719468;
+    let era = days / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    format!("{:04}.{:02}.{:02}", year, month, day)
+}
+
+impl GameRecord {
+    // Starts the record of a game that is played from the given position
+    pub fn new(players: Vec<String>, game_state: &GameState) -> Self {
+        let mut record = Self {
+            players,
+            seed: None,
+            ruleset: game_state.get_ruleset(),
+            date: None,
+            start_position: Some(game_state.clone()),
+            events: Vec::new(),
+            final_scores: None,
+            complete_rows: None,
+            termination: None,
+        };
+
+        // A new game is stored as the empty start and the first refill, so the header stays short
+        let mut start = GameState::new_without_factories(
+            game_state.get_num_players(),
+            game_state.get_ruleset(),
+        );
+        if start == *game_state {
+            // The factories will be filled by the first refill of the game
+            record.start_position = None;
+        } else if start
+            .fill_factories_with(game_state.get_factories())
+            .is_ok()
+            && start == *game_state
+        {
+            record.start_position = None;
+            record
+                .events
+                .push(RecordEvent::Refill(game_state.get_factories().clone()));
+        }
+        record
+    }
+
+    pub fn num_players(&self) -> usize {
+        self.players.len()
+    }
+
+    // The winning players (counted from 0), empty if the game isn't over. Ties are broken by the complete rows
+    // (see GameState::final_ranking), a record without them is ranked by the scores alone.
+    pub fn winners(&self) -> Vec<usize> {
+        let Some(scores) = &self.final_scores else {
+            return Vec::new();
+        };
+        let complete_rows = match &self.complete_rows {
+            Some(complete_rows) => complete_rows.clone(),
+            None => vec![0; scores.len()],
+        };
+        let placements = ranking(scores, &complete_rows);
+        (0..scores.len()).filter(|&i| placements[i] == 0).collect()
+    }
+
+    pub fn write(&self) -> String {
+        let mut result = String::new();
+        for (i, player) in self.players.iter().enumerate() {
+            result.push_str(&format!("[Player{} \"{}\"]\n", i + 1, escape(player)));
+        }
+        if let Some(seed) = self.seed {
+            result.push_str(&format!("[Seed \"{}\"]\n", seed));
+        }
+        result.push_str(&format!("[Ruleset \"{}\"]\n", self.ruleset));
+        if let Some(date) = &self.date {
+            result.push_str(&format!("[Date \"{}\"]\n", escape(date)));
+        }
+        let game_result = match self.final_scores {
+            Some(_) => self
+                .winners()
+                .iter()
+                .map(|winner| (winner + 1).to_string())
+                .collect::<Vec<_>>()
+                .join(","),
+            None => "*".to_string(),
+        };
+        result.push_str(&format!("[Result \"{}\"]\n", game_result));
+        if let Some(termination) = &self.termination {
+            result.push_str(&format!("[Termination \"{}\"]\n", escape(termination)));
+        }
+        if let Some(start_position) = &self.start_position {
+            result.push_str(&format!(
+                "[Position \"{}\"]\n",
+                start_position.to_position_string()
+            ));
+        }
+
+        let mut line = String::new();
+        for event in &self.events {
+            match event {
+                RecordEvent::Refill(factories) => {
+                    if !line.is_empty() {
+                        result.push_str(&line);
+                        result.push('\n');
+                        line.clear();
+                    }
+                    result.push_str(&format!("\nRefill {}\n", factories_to_string(factories)));
+                }
+                RecordEvent::Move(mov) => {
+                    if !line.is_empty() {
+                        line.push(' ');
+                    }
+                    line.push_str(&mov.to_notation());
+                }
+            }
+        }
+        if !line.is_empty() {
+            result.push_str(&line);
+            result.push('\n');
+        }
+        if let Some(scores) = &self.final_scores {
+            let scores = scores
+                .iter()
+                .map(|score| score.to_string())
+                .collect::<Vec<_>>()
+                .join(" ");
+            result.push_str(&format!("\nScores {}\n", scores));
+        }
+        if let Some(complete_rows) = &self.complete_rows {
+            let complete_rows = complete_rows
+                .iter()
+                .map(|rows| rows.to_string())
+                .collect::<Vec<_>>()
+                .join(" ");
+            result.push_str(&format!("Rows {}\n", complete_rows));
+        }
+        result
+    }
+
+    pub fn parse(string: &str) -> Result<Self, RecordParseError> {
+        let mut players: Vec<(usize, String)> = Vec::new();
+        let mut seed = None;
+        let mut ruleset = Ruleset::default();
+        let mut date = None;
+        let mut start_position = None;
+        let mut events = Vec::new();
+        let mut final_scores = None;
+        let mut complete_rows = None;
+        // The lines of the scores and the complete rows, to report them if they don't match the players
+        let mut scores_line = 0;
+        let mut rows_line = 0;
+        let mut termination = None;
+
+        let invalid_value = |tag: &str, value: &str| RecordParseError::InvalidTagValue {
+            tag: tag.to_string(),
+            value: value.to_string(),
+        };
+
+        for (line_index, line) in string.lines().enumerate() {
+            let line_number = line_index + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') {
+                continue;
+            }
+
+            if line.starts_with('[') {
+                let (tag, value) =
+                    parse_tag(line).ok_or(RecordParseError::InvalidTag { line: line_number })?;
+                match tag {
+                    "Seed" => {
+                        seed = Some(value.parse().map_err(|_| invalid_value(tag, &value))?);
+                    }
+                    "Ruleset" => {
+                        ruleset = value.parse().map_err(|_| invalid_value(tag, &value))?;
+                    }
+                    "Date" => date = Some(value),
+                    "Termination" => termination = Some(value),
+                    "Position" => {
+                        start_position = Some(
+                            GameState::parse_position(&value)
+                                .map_err(RecordParseError::InvalidPosition)?,
+                        );
+                    }
+                    // The result follows from the scores and the final position, other tags are kept for other tools
+                    "Result" => {}
+                    _ => {
+                        if let Some(number) = tag.strip_prefix("Player") {
+                            let number: usize =
+                                number.parse().map_err(|_| invalid_value(tag, &value))?;
+                            players.push((number, value));
+                        }
+                    }
+                }
+                continue;
+            }
+
+            if let Some(factory_strings) = line.strip_prefix("Refill") {
+                let num_players = players.len().clamp(MIN_PLAYERS, MAX_PLAYERS);
+                let mut factories = Factories::empty(num_players);
+                let factory_strings: Vec<&str> = factory_strings.split_whitespace().collect();
+                for (factory_index, factory_string) in factory_strings.iter().enumerate() {
+                    if factory_index >= CENTER_FACTORY_INDEX {
+                        return Err(RecordParseError::InvalidRefill {
+                            line: line_number,
+                            factory: factory_string.to_string(),
+                        });
+                    }
+                    if *factory_string == "-" {
+                        continue;
+                    }
+                    // A factory holds at most 4 tiles
+                    if factory_string.chars().count() > 4 {
+                        return Err(RecordParseError::InvalidRefill {
+                            line: line_number,
+                            factory: factory_string.to_string(),
+                        });
+                    }
+                    for c in factory_string.chars() {
+                        let color = color_from_notation(c).map_err(|_| {
+                            RecordParseError::InvalidRefill {
+                                line: line_number,
+                                factory: factory_string.to_string(),
+                            }
+                        })?;
+                        factories[factory_index][color as usize] += 1;
+                    }
+                }
+                // The number of factories has to match the number of players
+                if factory_strings.len() != factories.num_factories() - 1 {
+                    return Err(RecordParseError::InvalidRefill {
+                        line: line_number,
+                        factory: factory_strings.join(" "),
+                    });
+                }
+                events.push(RecordEvent::Refill(factories));
+                continue;
+            }
+
+            if let Some(scores) = line.strip_prefix("Scores") {
+                let scores = scores
+                    .split_whitespace()
+                    .map(|score| score.parse::<i16>())
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|_| RecordParseError::InvalidScores { line: line_number })?;
+                final_scores = Some(scores);
+                scores_line = line_number;
+                continue;
+            }
+
+            if let Some(rows) = line.strip_prefix("Rows") {
+                let rows = rows
+                    .split_whitespace()
+                    .map(|rows| rows.parse::<u32>())
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|_| RecordParseError::InvalidRows { line: line_number })?;
+                complete_rows = Some(rows);
+                rows_line = line_number;
+                continue;
+            }
+
+            for token in line.split_whitespace() {
+                let mov =
+                    Move::from_notation(token).map_err(|error| RecordParseError::InvalidMove {
+                        line: line_number,
+                        error,
+                    })?;
+                events.push(RecordEvent::Move(mov));
+            }
+        }
+
+        // The players have to be numbered from 1 without gaps
+        players.sort_by_key(|(number, _)| *number);
+        if !(MIN_PLAYERS..=MAX_PLAYERS).contains(&players.len())
+            || players
+                .iter()
+                .enumerate()
+                .any(|(i, (number, _))| *number != i + 1)
+        {
+            return Err(RecordParseError::InvalidPlayers);
+        }
+        let players: Vec<String> = players.into_iter().map(|(_, name)| name).collect();
+        if let Some(start_position) = &start_position {
+            if start_position.get_num_players() != players.len() {
+                return Err(RecordParseError::InvalidPlayers);
+            }
+            ruleset = start_position.get_ruleset();
+        }
+        if let Some(scores) = &final_scores {
+            if scores.len() != players.len() {
+                return Err(RecordParseError::InvalidScores { line: scores_line });
+            }
+        }
+        // Complete rows only come with final scores
+        if let Some(rows) = &complete_rows {
+            if final_scores.is_none() || rows.len() != players.len() {
+                return Err(RecordParseError::InvalidRows { line: rows_line });
+            }
+        }
+
+        Ok(Self {
+            players,
+            seed,
+            ruleset,
+            date,
+            start_position,
+            events,
+            final_scores,
+            complete_rows,
+            termination,
+        })
+    }
+
+    // Rebuilds the game: The first state is the start of the game, followed by the state after each event.
+    // If the record has final scores, the state after the final round evaluation is appended.
+    pub fn replay(&self) -> Result<Vec<GameState>, ReplayError> {
+        let mut game_state = match &self.start_position {
+            Some(start_position) => start_position.clone(),
+            None => GameState::new_without_factories(self.num_players(), self.ruleset),
+        };
+        let mut is_new_game = self.start_position.is_none();
+        let mut states = Vec::with_capacity(self.events.len() + 2);
+        states.push(game_state.clone());
+
+        for (index, event) in self.events.iter().enumerate() {
+            match event {
+                RecordEvent::Refill(factories) => {
+                    if !is_new_game
+                        && game_state.get_factories().is_empty()
+                        && game_state.get_pending_wall_tiling().is_none()
+                        && game_state.evaluate_round()
+                    {
+                        return Err(ReplayError::GameOver { index });
+                    }
+                    game_state
+                        .fill_factories_with(factories)
+                        .map_err(|_| ReplayError::ImpossibleRefill { index })?;
+                }
+                RecordEvent::Move(mov) => {
+                    game_state
+                        .validate_move(*mov)
+                        .map_err(|reason| ReplayError::IllegalMove { index, reason })?;
+                    game_state.do_move(*mov);
+                }
+            }
+            is_new_game = false;
+            states.push(game_state.clone());
+        }
+
+        if let Some(recorded) = &self.final_scores {
+            if !game_state.get_factories().is_empty()
+                || game_state.get_pending_wall_tiling().is_some()
+                || !game_state.evaluate_round()
+            {
+                return Err(ReplayError::GameNotOver);
+            }
+            let replayed = game_state.get_scores().to_vec();
+            if replayed != *recorded {
+                return Err(ReplayError::ScoreMismatch {
+                    recorded: recorded.clone(),
+                    replayed,
+                });
+            }
+            if let Some(recorded) = &self.complete_rows {
+                let replayed = MatchResult::new(&game_state).complete_rows;
+                if replayed != *recorded {
+                    return Err(ReplayError::CompleteRowsMismatch {
+                        recorded: recorded.clone(),
+                        replayed,
+                    });
+                }
+            }
+            states.push(game_state);
+        }
+        Ok(states)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::random_game;
+    use crate::MoveList;
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    // The record of a shared random game, see test_support
+    fn random_record(seed: u64) -> GameRecord {
+        let game = random_game(seed);
+        let start = &game.positions[0].before_generation;
+        let players = (0..start.get_num_players())
+            .map(|i| format!("Player \"{}\"", i + 1))
+            .collect();
+        let mut record = GameRecord::new(players, start);
+        record.seed = Some(seed);
+        record.date = Some("2024.05.01".to_string());
+        for position in &game.positions {
+            let before = &position.before_generation;
+            if before.get_factories().is_empty() && before.get_pending_wall_tiling().is_none() {
+                let factories = position.game_state.get_factories().clone();
+                record.events.push(RecordEvent::Refill(factories));
+            }
+            record.events.push(RecordEvent::Move(position.move_));
+        }
+        let result = MatchResult::new(&game.final_state);
+        record.final_scores = Some(result.scores);
+        record.complete_rows = Some(result.complete_rows);
+        record
+    }
+
+    #[test]
+    fn test_write_parse_replay() {
+        for seed in 0..12 {
+            let record = random_record(seed);
+            assert!(record.start_position.is_none());
+
+            let string = record.write();
+            let parsed = GameRecord::parse(&string).unwrap();
+            assert_eq!(parsed, record, "{}", string);
+
+            let states = parsed.replay().unwrap();
+            assert_eq!(states.len(), record.events.len() + 2);
+            assert_eq!(
+                states.last().unwrap().get_scores(),
+                record.final_scores.as_ref().unwrap().as_slice()
+            );
+        }
+    }
+
+    #[test]
+    fn test_record_from_position() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        let mut game_state = GameState::new(2, &mut rng);
+        let mut move_list = MoveList::default();
+        game_state.get_possible_moves(&mut move_list, &mut rng);
+        game_state.do_move(move_list[0]);
+
+        let mut record = GameRecord::new(vec!["A".to_string(), "B".to_string()], &game_state);
+        assert_eq!(record.start_position.as_ref(), Some(&game_state));
+        game_state.get_possible_moves(&mut move_list, &mut rng);
+        record.events.push(RecordEvent::Move(move_list[0]));
+
+        let parsed = GameRecord::parse(&record.write()).unwrap();
+        assert_eq!(parsed, record);
+        let states = parsed.replay().unwrap();
+        game_state.do_move(move_list[0]);
+        assert_eq!(states.last(), Some(&game_state));
+    }
+
+    #[test]
+    fn test_winners() {
+        let header = "[Player1 \"A\"]\n[Player2 \"B\"]\n[Player3 \"C\"]\n";
+        let winners = |body: &str| {
+            GameRecord::parse(&format!("{}{}", header, body)).map(|record| record.winners())
+        };
+        // The complete rows break the tie of the scores, the game doesn't have to be replayed
+        assert_eq!(winners("Scores 30 30 12\nRows 0 1 2"), Ok(vec![1]));
+        assert_eq!(winners("Scores 30 30 12\nRows 1 1 2"), Ok(vec![0, 1]));
+        assert_eq!(winners("Scores 30 30 12"), Ok(vec![0, 1]));
+        assert_eq!(winners(""), Ok(vec![]));
+        assert_eq!(
+            winners("Scores 30 30 12\nRows 0 1"),
+            Err(RecordParseError::InvalidRows { line: 5 })
+        );
+        assert_eq!(
+            winners("Rows 0 1 2"),
+            Err(RecordParseError::InvalidRows { line: 4 })
+        );
+        assert_eq!(
+            winners("Rows 0 1\nScores 30 30 12\n1R>1"),
+            Err(RecordParseError::InvalidRows { line: 4 })
+        );
+        assert_eq!(
+            winners("Scores 30 30\nRows 0 1 2\n1R>1"),
+            Err(RecordParseError::InvalidScores { line: 4 })
+        );
+    }
+
+    #[test]
+    fn test_replay_rejects_altered_record() {
+        let mut record = random_record(1);
+        record.final_scores.as_mut().unwrap()[0] += 1;
+        assert!(matches!(
+            record.replay(),
+            Err(ReplayError::ScoreMismatch { .. })
+        ));
+
+        let mut record = random_record(1);
+        record.complete_rows.as_mut().unwrap()[0] += 1;
+        assert!(matches!(
+            record.replay(),
+            Err(ReplayError::CompleteRowsMismatch { .. })
+        ));
+
+        // The second move takes the same tiles again
+        let mut record = random_record(1);
+        record.events[2] = record.events[1].clone();
+        assert!(matches!(
+            record.replay(),
+            Err(ReplayError::IllegalMove { index: 2, .. })
+        ));
+
+        assert_eq!(
+            GameRecord::parse("[Player1 \"A\"]\n[Player2 \"B\"]\n3R>2x"),
+            Err(RecordParseError::InvalidMove {
+                line: 3,
+                error: NotationError::InvalidTarget('X')
+            })
+        );
+        assert_eq!(
+            GameRecord::parse("[Player1 \"A\"]\n\n3R>2"),
+            Err(RecordParseError::InvalidPlayers)
+        );
+
+        // A factory holds at most 4 tiles
+        let refill = format!("Refill {} YYYY RRRR KKKK WWWW", "B".repeat(300));
+        assert_eq!(
+            GameRecord::parse(&format!("[Player1 \"A\"]\n[Player2 \"B\"]\n{}", refill)),
+            Err(RecordParseError::InvalidRefill {
+                line: 3,
+                factory: "B".repeat(300)
+            })
+        );
+        let mut record = random_record(1);
+        let RecordEvent::Refill(factories) = &mut record.events[0] else {
+            panic!("The game starts with a refill");
+        };
+        factories[0] = [4, 4, 0, 0, 0];
+        factories[1] = [0, 0, 0, 0, 0];
+        assert!(matches!(
+            record.replay(),
+            Err(ReplayError::ImpossibleRefill { index: 0 })
+        ));
+    }
+}
@@ -0,0 +1,165 @@
+// How the tiles drafted in one move may be placed on the pattern lines
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum PatternLinePlacement {
+    // The tiles may be spread over several pattern lines and the floor line
+    #[default]
+    Spread,
+    // All tiles go into one pattern line and the overflow goes to the floor line, as in the printed rules
+    Single,
+}
+
+// Which side of the player board is used
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum WallVariant {
+    // Every color has a fixed field in each row (wall::WALL_COLOR_MASKS)
+    #[default]
+    Colored,
+    // Gray side: The player chooses the column when a pattern line is tiled, a color may not repeat in a row or column
+    Free,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Ruleset {
+    pub pattern_line_placement: PatternLinePlacement,
+    pub wall_variant: WallVariant,
+}
+
+impl Ruleset {
+    // The rules as printed in the rulebook of the board game
+    pub const OFFICIAL: Self = Self {
+        pattern_line_placement: PatternLinePlacement::Single,
+        wall_variant: WallVariant::Colored,
+    };
+
+    // The official variant played on the gray side of the player boards
+    pub const OFFICIAL_FREE_WALL: Self = Self {
+        pattern_line_placement: PatternLinePlacement::Single,
+        wall_variant: WallVariant::Free,
+    };
+
+    // Check whether a move pattern (5 pattern lines + floor line) places the tiles according to this ruleset
+    #[inline]
+    pub fn allows_placement(&self, pattern: &[u8; 6]) -> bool {
+        match self.pattern_line_placement {
+            PatternLinePlacement::Spread => true,
+            PatternLinePlacement::Single => pattern.iter().take(5).filter(|&&x| x > 0).count() <= 1,
+        }
+    }
+
+    #[inline]
+    pub fn is_free_wall(&self) -> bool {
+        self.wall_variant == WallVariant::Free
+    }
+
+    // Compact representation used by the serialization, one bit per rule
+    pub fn to_bits(self) -> u8 {
+        let placement = match self.pattern_line_placement {
+            PatternLinePlacement::Spread => 0,
+            PatternLinePlacement::Single => 1,
+        };
+        let wall_variant = match self.wall_variant {
+            WallVariant::Colored => 0,
+            WallVariant::Free => 1,
+        };
+        placement | wall_variant << 1
+    }
+
+    pub fn from_bits(bits: u8) -> Option<Self> {
+        if bits > 0b11 {
+            return None;
+        }
+        let pattern_line_placement = match bits & 1 {
+            0 => PatternLinePlacement::Spread,
+            _ => PatternLinePlacement::Single,
+        };
+        let wall_variant = match bits >> 1 {
+            0 => WallVariant::Colored,
+            _ => WallVariant::Free,
+        };
+        Some(Self {
+            pattern_line_placement,
+            wall_variant,
+        })
+    }
+}
+
+impl std::fmt::Display for Ruleset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let placement = match self.pattern_line_placement {
+            PatternLinePlacement::Spread => "spread",
+            PatternLinePlacement::Single => "single",
+        };
+        let wall_variant = match self.wall_variant {
+            WallVariant::Colored => "colored",
+            WallVariant::Free => "free",
+        };
+        write!(f, "{} placement, {} wall", placement, wall_variant)
+    }
+}
+
+// Parses the format written by Display, e.g. "single placement, colored wall"
+impl std::str::FromStr for Ruleset {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        let (placement, wall_variant) = string
+            .split_once(',')
+            .ok_or_else(|| format!("Invalid ruleset {}", string))?;
+        let pattern_line_placement = match placement.trim() {
+            "spread placement" => PatternLinePlacement::Spread,
+            "single placement" => PatternLinePlacement::Single,
+            _ => return Err(format!("Invalid pattern line placement {}", placement)),
+        };
+        let wall_variant = match wall_variant.trim() {
+            "colored wall" => WallVariant::Colored,
+            "free wall" => WallVariant::Free,
+            _ => return Err(format!("Invalid wall variant {}", wall_variant)),
+        };
+        Ok(Self {
+            pattern_line_placement,
+            wall_variant,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bits_round_trip() {
+        let spread_free_wall = Ruleset {
+            pattern_line_placement: PatternLinePlacement::Spread,
+            wall_variant: WallVariant::Free,
+        };
+        for ruleset in [
+            Ruleset::default(),
+            Ruleset::OFFICIAL,
+            Ruleset::OFFICIAL_FREE_WALL,
+            spread_free_wall,
+        ] {
+            assert_eq!(Ruleset::from_bits(ruleset.to_bits()), Some(ruleset));
+        }
+        assert_eq!(Ruleset::from_bits(255), None);
+    }
+
+    #[test]
+    fn test_display_round_trip() {
+        for bits in 0..4 {
+            let ruleset = Ruleset::from_bits(bits).unwrap();
+            assert_eq!(ruleset.to_string().parse::<Ruleset>(), Ok(ruleset));
+        }
+        assert!("single placement".parse::<Ruleset>().is_err());
+    }
+
+    #[test]
+    fn test_allows_placement() {
+        let spread = [1, 1, 0, 0, 0, 1];
+        let single = [0, 0, 3, 0, 0, 2];
+        let discard = [0, 0, 0, 0, 0, 4];
+        assert!(Ruleset::default().allows_placement(&spread));
+        assert!(!Ruleset::OFFICIAL.allows_placement(&spread));
+        assert!(Ruleset::OFFICIAL.allows_placement(&single));
+        assert!(Ruleset::OFFICIAL.allows_placement(&discard));
+    }
+}
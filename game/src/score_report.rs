@@ -0,0 +1,401 @@
+use crate::game_state::{GameState, FLOOR_LINE_PENALTY};
+use crate::move_::Move;
+use crate::tile_color::TileColor;
+use crate::wall::{self, WALL_COLOR_MASKS};
+
+/*
+    Breakdown of the points a player scores at the end of a round: The tiles that are placed on the wall, the floor line penalty
+    and, at the end of the game, the bonuses for complete rows, columns and colors. The total is exactly what evaluate_round adds to the score.
+    On the free wall the tiles are placed (and scored) by the wall tiling moves before the round is evaluated, see GameState::wall_tiling_placement.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WallPlacement {
+    pub row: usize,
+    pub column: usize,
+    pub color: TileColor,
+    pub row_points: u8, // Length of the horizontal line of tiles the tile is part of, 0 without horizontal neighbors
+    pub column_points: u8, // Length of the vertical line of tiles the tile is part of, 0 without vertical neighbors
+}
+
+impl WallPlacement {
+    fn new(occupancy: u32, row: usize, column: usize, color: TileColor) -> Self {
+        let position = wall::field_at(row, column).trailing_zeros() as u8;
+        let (row_points, column_points) =
+            wall::get_placed_tile_score_breakdown(occupancy, position);
+        Self {
+            row,
+            column,
+            color,
+            row_points: row_points as u8,
+            column_points: column_points as u8,
+        }
+    }
+
+    pub fn points(&self) -> i16 {
+        // A tile without neighbors scores a single point
+        (self.row_points + self.column_points).max(1) as i16
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EndOfGameBonus {
+    pub complete_rows: u8,
+    pub complete_columns: u8,
+    pub complete_colors: u8,
+}
+
+impl EndOfGameBonus {
+    pub const ROW_POINTS: i16 = 2;
+    pub const COLUMN_POINTS: i16 = 7;
+    pub const COLOR_POINTS: i16 = 10;
+
+    pub fn row_bonus(&self) -> i16 {
+        self.complete_rows as i16 * Self::ROW_POINTS
+    }
+
+    pub fn column_bonus(&self) -> i16 {
+        self.complete_columns as i16 * Self::COLUMN_POINTS
+    }
+
+    pub fn color_bonus(&self) -> i16 {
+        self.complete_colors as i16 * Self::COLOR_POINTS
+    }
+
+    pub fn total(&self) -> i16 {
+        self.row_bonus() + self.column_bonus() + self.color_bonus()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ScoreReport {
+    pub placements: Vec<WallPlacement>,
+    pub floor_tiles: u8,
+    pub floor_penalty: u8, // Points that are subtracted for the floor line
+    pub end_of_game: Option<EndOfGameBonus>,
+}
+
+impl ScoreReport {
+    pub fn placement_points(&self) -> i16 {
+        self.placements
+            .iter()
+            .map(|placement| placement.points())
+            .sum()
+    }
+
+    pub fn total(&self) -> i16 {
+        self.placement_points() - self.floor_penalty as i16
+            + self.end_of_game.map_or(0, |bonus| bonus.total())
+    }
+}
+
+impl std::fmt::Display for ScoreReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for placement in &self.placements {
+            write!(
+                f,
+                "  {} at row {}, column {}: {:+}",
+                placement.color,
+                placement.row + 1,
+                placement.column + 1,
+                placement.points()
+            )?;
+            if placement.row_points > 0 || placement.column_points > 0 {
+                write!(
+                    f,
+                    " ({} in the row, {} in the column)",
+                    placement.row_points, placement.column_points
+                )?;
+            }
+            writeln!(f)?;
+        }
+        if self.floor_tiles > 0 {
+            writeln!(
+                f,
+                "  {} tiles on the floor line: -{}",
+                self.floor_tiles, self.floor_penalty
+            )?;
+        }
+        if let Some(bonus) = self.end_of_game {
+            writeln!(
+                f,
+                "  {} complete rows: {:+}",
+                bonus.complete_rows,
+                bonus.row_bonus()
+            )?;
+            writeln!(
+                f,
+                "  {} complete columns: {:+}",
+                bonus.complete_columns,
+                bonus.column_bonus()
+            )?;
+            writeln!(
+                f,
+                "  {} complete colors: {:+}",
+                bonus.complete_colors,
+                bonus.color_bonus()
+            )?;
+        }
+        write!(f, "  Total: {:+}", self.total())
+    }
+}
+
+// Placements for the final scores and complete rows of the players, see GameState::final_ranking
+pub(crate) fn ranking(scores: &[i16], complete_rows: &[u32]) -> Vec<usize> {
+    let keys: Vec<(i16, u32)> = scores
+        .iter()
+        .copied()
+        .zip(complete_rows.iter().copied())
+        .collect();
+    keys.iter()
+        .map(|key| keys.iter().filter(|other| *other > key).count())
+        .collect()
+}
+
+impl GameState {
+    // What evaluate_round would score for every player in this position, all factories have to be empty.
+    // On the free wall the pending wall tilings have to be done first.
+    pub fn round_score_reports(&self) -> Vec<ScoreReport> {
+        self.score_round(false, false)
+    }
+
+    // What every player would score if the round ended now. The full pattern lines are placed on the wall, on the free wall
+    // each on the field that scores the most points. Pattern lines that are not full don't score.
+    // With end_of_game_bonus the bonuses for the complete rows, columns and colors are included even if the game doesn't end.
+    pub fn projected_score_reports(&self, end_of_game_bonus: bool) -> Vec<ScoreReport> {
+        self.score_round(true, end_of_game_bonus)
+    }
+
+    // The scores of all players plus the points they would score if the round ended now, see projected_score_reports
+    pub fn projected_scores(&self, end_of_game_bonus: bool) -> Vec<i16> {
+        self.projected_score_reports(end_of_game_bonus)
+            .iter()
+            .zip(self.get_scores())
+            .map(|(report, score)| score + report.total())
+            .collect()
+    }
+
+    fn score_round(&self, tile_free_wall: bool, end_of_game_bonus: bool) -> Vec<ScoreReport> {
+        let num_players = self.get_num_players();
+        let mut reports = vec![ScoreReport::default(); num_players];
+        let mut occupancies = self.get_wall_ocupancy()[..num_players].to_vec();
+        let mut walls = self.get_walls()[..num_players].to_vec();
+        for (player, report) in reports.iter_mut().enumerate() {
+            let mut floor_tiles = self.get_floor_line_progress()[player];
+            for (pattern_line_index, &tiles) in self.get_pattern_lines_occupancy()[player]
+                .iter()
+                .enumerate()
+            {
+                if tiles as usize != pattern_line_index + 1 {
+                    continue;
+                }
+                let color = self.get_pattern_lines_colors()[player][pattern_line_index].unwrap(); // Must be Some because the pattern line is full
+                let new_tile = if !self.get_ruleset().is_free_wall() {
+                    wall::get_row_mask(pattern_line_index) & WALL_COLOR_MASKS[color as usize]
+                } else if tile_free_wall {
+                    // The field with the most points, 0 if the line can't be tiled
+                    let mut candidates =
+                        wall::get_free_wall_candidates(&walls[player], pattern_line_index, color);
+                    let mut best_field = 0;
+                    let mut best_score = 0;
+                    while candidates > 0 {
+                        let field = candidates & candidates.wrapping_neg();
+                        candidates &= candidates - 1;
+                        let score = wall::get_placed_tile_score(
+                            occupancies[player],
+                            field.trailing_zeros() as u8,
+                        );
+                        if score > best_score {
+                            best_field = field;
+                            best_score = score;
+                        }
+                    }
+                    best_field
+                } else {
+                    0
+                };
+                if new_tile == 0 {
+                    // The lines that could not be tiled go to the floor line
+                    floor_tiles += tiles;
+                    continue;
+                }
+                let column = new_tile.trailing_zeros() as usize % 6;
+                report.placements.push(WallPlacement::new(
+                    occupancies[player],
+                    pattern_line_index,
+                    column,
+                    color,
+                ));
+                occupancies[player] |= new_tile;
+                walls[player][color as usize] |= new_tile;
+            }
+            report.floor_tiles = floor_tiles;
+            report.floor_penalty =
+                FLOOR_LINE_PENALTY[floor_tiles.min(FLOOR_LINE_PENALTY.len() as u8 - 1) as usize];
+        }
+
+        // The game ends after the round in which any player completes a row
+        let is_game_over = occupancies
+            .iter()
+            .any(|&occupancy| wall::check_complete_row_exists(occupancy));
+        if is_game_over || end_of_game_bonus {
+            for (player, report) in reports.iter_mut().enumerate() {
+                report.end_of_game = Some(EndOfGameBonus {
+                    complete_rows: wall::count_complete_rows(occupancies[player]) as u8,
+                    complete_columns: wall::count_complete_columns(occupancies[player]) as u8,
+                    complete_colors: walls[player]
+                        .iter()
+                        .filter(|color_board| color_board.count_ones() == 5)
+                        .count() as u8,
+                });
+            }
+        }
+        reports
+    }
+
+    // Placement of every player by the official rules, 0 is the winner. The highest score wins, a tie is broken by the most
+    // complete horizontal rows. Players that are still tied share the placement, the next placement is skipped (e.g. 0, 0, 2).
+    pub fn final_ranking(&self) -> Vec<usize> {
+        let complete_rows: Vec<u32> = self
+            .get_wall_ocupancy()
+            .iter()
+            .map(|occupancy| wall::count_complete_rows(*occupancy))
+            .collect();
+        ranking(self.get_scores(), &complete_rows)
+    }
+
+    // The tile a wall tiling move places on the free wall, with the points it scores
+    pub fn wall_tiling_placement(&self, mov: Move) -> Option<WallPlacement> {
+        if !mov.is_wall_tiling() {
+            return None;
+        }
+        let (row, column) = mov.get_wall_tiling_field();
+        let player = usize::from(self.get_current_player());
+        Some(WallPlacement::new(
+            self.get_wall_ocupancy()[player],
+            row,
+            column,
+            mov.color,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::random_games;
+    use crate::{MoveList, Ruleset};
+    use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+    // The reports of a round end add up to the scores after the evaluation
+    fn check_round_score_reports(before: &GameState, after: &GameState) -> bool {
+        let reports = before.round_score_reports();
+        for (player, report) in reports.iter().enumerate() {
+            assert_eq!(
+                before.get_scores()[player] + report.total(),
+                after.get_scores()[player]
+            );
+        }
+        reports[0].end_of_game.is_some()
+    }
+
+    #[test]
+    fn test_score_reports_match_scores() {
+        for game in random_games(12) {
+            for position in &game.positions {
+                let before = &position.before_generation;
+                if before.get_factories().is_empty() && before.get_pending_wall_tiling().is_none() {
+                    assert!(!check_round_score_reports(before, &position.game_state));
+                }
+
+                let game_state = &position.game_state;
+                let placement = game_state.wall_tiling_placement(position.move_);
+                let player = usize::from(game_state.get_current_player());
+                let mut after_move = game_state.clone();
+                after_move.do_move(position.move_);
+                if let Some(placement) = placement {
+                    assert_eq!(
+                        game_state.get_scores()[player] + placement.points(),
+                        after_move.get_scores()[player]
+                    );
+                }
+            }
+            assert!(check_round_score_reports(&game.end, &game.final_state));
+        }
+    }
+
+    #[test]
+    fn test_projected_scores() {
+        let mut move_list = MoveList::default();
+        for seed in 0..6 {
+            let mut rng = SmallRng::seed_from_u64(seed);
+            let ruleset = if seed % 2 == 0 {
+                Ruleset::default()
+            } else {
+                Ruleset::OFFICIAL_FREE_WALL
+            };
+            let mut game_state = GameState::new_with_ruleset(2, ruleset, &mut rng);
+            // Play a round with the greedy tiling of the projection
+            while !game_state.get_factories().is_empty() {
+                game_state.get_possible_moves(&mut move_list, &mut rng);
+                game_state.do_move(move_list[rng.gen_range(0..move_list.len())]);
+            }
+            let projected = game_state.projected_scores(false);
+            while let Some((player, pattern_line_index)) = game_state.get_pending_wall_tiling() {
+                let reports = game_state.projected_score_reports(false);
+                let placement = reports[usize::from(player)]
+                    .placements
+                    .iter()
+                    .find(|placement| placement.row == pattern_line_index)
+                    .unwrap();
+                let move_ =
+                    crate::Move::new_wall_tiling(placement.row, placement.column, placement.color);
+                game_state.do_move(move_);
+            }
+            game_state.evaluate_round();
+            assert_eq!(projected, game_state.get_scores());
+
+            // The bonuses are only added on request before the end of the game
+            let with_bonus = game_state.projected_score_reports(true);
+            assert!(with_bonus.iter().all(|report| report.end_of_game.is_some()));
+        }
+    }
+
+    #[test]
+    fn test_placement_points() {
+        // A tile between two tiles in its row and below one tile in its column
+        let occupancy = wall::field_at(2, 1) | wall::field_at(2, 3) | wall::field_at(1, 2);
+        let placement = WallPlacement::new(occupancy, 2, 2, TileColor::Blue);
+        assert_eq!(placement.row_points, 3);
+        assert_eq!(placement.column_points, 2);
+        assert_eq!(placement.points(), 5);
+
+        let placement = WallPlacement::new(0, 0, 0, TileColor::Blue);
+        assert_eq!(placement.points(), 1);
+    }
+
+    #[test]
+    fn test_final_ranking() {
+        // Player 1 and 3 are tied on points, player 3 has the complete row. Player 2 and 4 are tied on points and rows.
+        let game_state = crate::GameStateBuilder::new(4)
+            .wall_row(1, 0, "B....")
+            .wall_row(2, 0, "BYRKW")
+            .wall_row(3, 1, ".B...")
+            .score(0, 40)
+            .score(1, 35)
+            .score(2, 40)
+            .score(3, 35)
+            .build()
+            .unwrap();
+        assert_eq!(game_state.final_ranking(), vec![1, 2, 0, 2]);
+
+        // Without the tie-break the highest score wins
+        let game_state = crate::GameStateBuilder::new(2)
+            .wall_row(0, 0, "BYRKW")
+            .score(0, 20)
+            .score(1, 21)
+            .build()
+            .unwrap();
+        assert_eq!(game_state.final_ranking(), vec![1, 0]);
+    }
+}
@@ -0,0 +1,336 @@
+use crate::factories::{num_factories_for_players, Factories, Factory, CENTER_FACTORY_INDEX};
+use crate::game_state::{Bag, GameState};
+use crate::move_::Move;
+use crate::player::PlayerMarker;
+use crate::record::GameRecord;
+use crate::ruleset::Ruleset;
+use crate::tile_color::{TileColor, NUM_TILE_COLORS};
+use crate::wall;
+use crate::{MAX_PLAYERS, MIN_PLAYERS};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/*
+    Serde support (feature "serde"). The simple types derive their implementation, the types below are written in a readable
+    form that doesn't depend on their internal layout:
+    - Ruleset: The text of its Display implementation, e.g. "single placement, colored wall".
+    - Move: The short notation of notation.rs, e.g. "3R>22F" or "TR>3@2" for a wall tiling.
+    - Factories: The tiles of every factory in use as counts per color (blue, yellow, red, green, white), the center last.
+    - GameState: See GameStateData, the hash and the wall occupancy are recomputed and the state has to pass check_integrity.
+    - GameRecord: The AGN text of the game, see record.rs.
+
+    {
+      "ruleset": "single placement, colored wall",
+      "bag": [16, 20, 18, 20, 19], "lid": [0, 0, 0, 0, 0],
+      "factories": [[2, 0, 1, 0, 1], ..., [0, 0, 0, 0, 0]],
+      "current_player": 0, "next_round_starting_player": 0, "tile_taken_from_center": false,
+      "players": [{
+        "score": 0, "floor_line": 0,
+        "wall": [[null, "yellow", null, null, null], ...],
+        "pattern_lines": [{"color": "red", "tiles": 1}, {"color": null, "tiles": 0}, ...]
+      }, ...]
+    }
+*/
+
+impl Serialize for Ruleset {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Ruleset {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let string = String::deserialize(deserializer)?;
+        string.parse().map_err(D::Error::custom)
+    }
+}
+
+impl Serialize for Move {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_notation())
+    }
+}
+
+impl<'de> Deserialize<'de> for Move {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let string = String::deserialize(deserializer)?;
+        Move::from_notation(&string).map_err(D::Error::custom)
+    }
+}
+
+impl Serialize for Factories {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.active_indices().map(|index| &self[index]))
+    }
+}
+
+impl<'de> Deserialize<'de> for Factories {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let factories = Vec::<Factory>::deserialize(deserializer)?;
+        let num_players = (MIN_PLAYERS..=MAX_PLAYERS)
+            .find(|&num_players| num_factories_for_players(num_players) == factories.len())
+            .ok_or_else(|| {
+                D::Error::custom(format!("Invalid number of factories: {}", factories.len()))
+            })?;
+        let mut result = Factories::empty(num_players);
+        let (center, others) = factories
+            .split_last()
+            .expect("There is at least one factory");
+        for (index, factory) in others.iter().enumerate() {
+            result[index] = *factory;
+        }
+        result[CENTER_FACTORY_INDEX] = *center;
+        Ok(result)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct PatternLineData {
+    color: Option<TileColor>,
+    tiles: u8,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PlayerData {
+    score: i16,
+    floor_line: u8, // Tiles on the floor line, including the first player marker
+    wall: [[Option<TileColor>; 5]; 5],
+    pattern_lines: [PatternLineData; 5],
+}
+
+#[derive(Serialize, Deserialize)]
+struct GameStateData {
+    ruleset: Ruleset,
+    bag: Bag,
+    lid: Bag, // Tiles that left the game and go back into the bag when it is empty
+    factories: Factories,
+    current_player: PlayerMarker,
+    next_round_starting_player: PlayerMarker,
+    tile_taken_from_center: bool,
+    players: Vec<PlayerData>,
+}
+
+impl Serialize for GameState {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let players = (0..self.get_num_players())
+            .map(|player| {
+                let walls = &self.walls[player];
+                let wall = std::array::from_fn(|row| {
+                    std::array::from_fn(|column| {
+                        let field = wall::field_at(row, column);
+                        (0..NUM_TILE_COLORS)
+                            .find(|&color| walls[color] & field > 0)
+                            .map(TileColor::from)
+                    })
+                });
+                let pattern_lines = std::array::from_fn(|line| PatternLineData {
+                    color: self.pattern_lines_colors[player][line],
+                    tiles: self.pattern_lines_occupancy[player][line],
+                });
+                PlayerData {
+                    score: self.scores[player],
+                    floor_line: self.floor_line_progress[player],
+                    wall,
+                    pattern_lines,
+                }
+            })
+            .collect();
+        GameStateData {
+            ruleset: self.ruleset,
+            bag: self.bag,
+            lid: self.out_of_bag,
+            factories: self.factories.clone(),
+            current_player: self.current_player,
+            next_round_starting_player: self.next_round_starting_player,
+            tile_taken_from_center: self.tile_taken_from_center,
+            players,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for GameState {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let data = GameStateData::deserialize(deserializer)?;
+        let num_players = data.players.len();
+        if !(MIN_PLAYERS..=MAX_PLAYERS).contains(&num_players) {
+            return Err(D::Error::custom(format!(
+                "Invalid number of players: {}",
+                num_players
+            )));
+        }
+        if data.factories.num_factories() != num_factories_for_players(num_players) {
+            return Err(D::Error::custom(format!(
+                "{} factories don't match {} players",
+                data.factories.num_factories(),
+                num_players
+            )));
+        }
+        for marker in [data.current_player, data.next_round_starting_player] {
+            if usize::from(marker) >= num_players {
+                return Err(D::Error::custom(format!(
+                    "Invalid player: {}",
+                    usize::from(marker)
+                )));
+            }
+        }
+
+        let mut game_state = GameState::new_without_factories(num_players, data.ruleset);
+        game_state.bag = data.bag;
+        game_state.out_of_bag = data.lid;
+        game_state.factories = data.factories;
+        game_state.current_player = data.current_player;
+        game_state.next_round_starting_player = data.next_round_starting_player;
+        game_state.tile_taken_from_center = data.tile_taken_from_center;
+        for (player, player_data) in data.players.iter().enumerate() {
+            game_state.scores[player] = player_data.score;
+            game_state.floor_line_progress[player] = player_data.floor_line;
+            for (row, fields) in player_data.wall.iter().enumerate() {
+                for (column, color) in fields.iter().enumerate() {
+                    if let Some(color) = color {
+                        game_state.walls[player][*color as usize] |= wall::field_at(row, column);
+                    }
+                }
+            }
+            game_state.wall_occupancy[player] = game_state.walls[player]
+                .iter()
+                .fold(0, |occupancy, color_board| occupancy | color_board);
+            for (line, pattern_line) in player_data.pattern_lines.iter().enumerate() {
+                game_state.pattern_lines_colors[player][line] = pattern_line.color;
+                game_state.pattern_lines_occupancy[player][line] = pattern_line.tiles;
+            }
+        }
+        game_state.hash = game_state.compute_hash();
+        game_state.check_integrity().map_err(D::Error::custom)?;
+        Ok(game_state)
+    }
+}
+
+impl Serialize for GameRecord {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.write())
+    }
+}
+
+impl<'de> Deserialize<'de> for GameRecord {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let string = String::deserialize(deserializer)?;
+        GameRecord::parse(&string).map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::match_::{run_match, MatchStatistcs};
+    use crate::test_support::random_games;
+    use crate::{GameStateBuilder, MoveList, Player, TimeControl};
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    fn round_trip<T: Serialize + for<'de> Deserialize<'de>>(value: &T) -> T {
+        let json = serde_json::to_string(value).unwrap();
+        serde_json::from_str(&json).unwrap()
+    }
+
+    #[test]
+    fn test_game_state_round_trip() {
+        for game in random_games(12) {
+            for position in &game.positions {
+                for game_state in [&position.before_generation, &position.game_state] {
+                    assert_eq!(round_trip(game_state), *game_state);
+                    assert_eq!(
+                        round_trip(game_state.get_factories()),
+                        *game_state.get_factories()
+                    );
+                }
+                assert_eq!(round_trip(&position.move_), position.move_);
+            }
+            assert_eq!(round_trip(&game.final_state), game.final_state);
+        }
+    }
+
+    #[test]
+    fn test_game_state_schema() {
+        let game_state = GameStateBuilder::new(2)
+            .factory(0, "BBYR")
+            .wall_row(1, 0, ".Y...")
+            .pattern_line(0, 2, TileColor::Red, 1)
+            .score(1, 4)
+            .build()
+            .unwrap();
+        let json = serde_json::to_value(&game_state).unwrap();
+        assert_eq!(json["ruleset"], "spread placement, colored wall");
+        assert_eq!(json["factories"].as_array().unwrap().len(), 6);
+        assert_eq!(json["factories"][0], serde_json::json!([2, 1, 1, 0, 0]));
+        assert_eq!(json["players"][1]["score"], 4);
+        assert_eq!(json["players"][1]["wall"][0][1], "yellow");
+        assert_eq!(
+            json["players"][0]["pattern_lines"][2],
+            serde_json::json!({ "color": "red", "tiles": 1 })
+        );
+
+        // A state that breaks an invariant is rejected
+        let mut json = json;
+        json["players"][0]["pattern_lines"][2]["tiles"] = serde_json::json!(4);
+        assert!(serde_json::from_value::<GameState>(json.clone()).is_err());
+        json["players"][0]["pattern_lines"][2]["tiles"] = serde_json::json!(1);
+        assert_eq!(
+            serde_json::from_value::<GameState>(json.clone()).unwrap(),
+            game_state
+        );
+        // Moves are written in the short notation
+        let mov = Move::from_notation("CR>33F").unwrap();
+        assert_eq!(serde_json::to_value(mov).unwrap(), "CR>33F");
+        assert_eq!(
+            serde_json::from_value::<Move>(serde_json::json!("TB>2@3")).unwrap(),
+            Move::new_wall_tiling(1, 2, TileColor::Blue)
+        );
+        assert!(serde_json::from_value::<Move>(serde_json::json!("XR>1")).is_err());
+
+        json["factories"]
+            .as_array_mut()
+            .unwrap()
+            .push(serde_json::json!([0, 0, 0, 0, 0]));
+        assert!(serde_json::from_value::<GameState>(json).is_err());
+    }
+
+    struct FirstMovePlayer;
+
+    #[async_trait::async_trait]
+    impl Player for FirstMovePlayer {
+        fn get_name(&self) -> &str {
+            "First move"
+        }
+
+        async fn get_move(&mut self, game_state: &GameState) -> Move {
+            let mut move_list = MoveList::default();
+            game_state
+                .clone()
+                .get_possible_moves(&mut move_list, &mut SmallRng::seed_from_u64(0));
+            move_list[0]
+        }
+    }
+
+    #[tokio::test]
+    async fn test_match_statistics_round_trip() {
+        let mut players: Vec<Box<dyn Player>> =
+            vec![Box::new(FirstMovePlayer), Box::new(FirstMovePlayer)];
+        let stats = run_match(
+            GameState::new_without_factories(2, Ruleset::OFFICIAL),
+            &mut players,
+            3,
+            TimeControl::Unlimited,
+            &mut [],
+        )
+        .await
+        .unwrap();
+        let copy: MatchStatistcs = round_trip(&stats);
+        assert_eq!(copy.record, stats.record);
+        assert_eq!(copy.result, stats.result);
+        assert_eq!(copy.executed_moves.len(), stats.executed_moves.len());
+        for (copy, original) in copy.executed_moves.iter().zip(&stats.executed_moves) {
+            assert_eq!(copy.0, original.0);
+            assert_eq!(copy.2, original.2);
+        }
+    }
+}
@@ -0,0 +1,57 @@
+use crate::{GameState, Move, MoveGenerationResult, MoveList, Ruleset};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+/*
+    Random games shared by the tests. Game i is played with 2 + i % 3 players and the ruleset Ruleset::from_bits(i % 4),
+    so the first 12 games cover every combination of player count and ruleset. The moves are chosen at random.
+*/
+
+pub(crate) struct RandomPosition {
+    pub before_generation: GameState, // After the previous move, before the round is evaluated and the factories are refilled
+    pub game_state: GameState,        // The position the move is played in
+    pub moves: Vec<Move>,             // The legal moves in the position
+    pub move_: Move,
+}
+
+pub(crate) struct RandomGame {
+    pub positions: Vec<RandomPosition>,
+    pub end: GameState, // After the last move, before the last round is evaluated
+    pub final_state: GameState, // After the last round was evaluated, with the final scores
+}
+
+pub(crate) fn random_game(seed: u64) -> RandomGame {
+    let mut rng = SmallRng::seed_from_u64(seed);
+    let num_players = 2 + seed as usize % 3;
+    let ruleset = Ruleset::from_bits(seed as u8 % 4).unwrap();
+    let mut game_state = GameState::new_with_ruleset(num_players, ruleset, &mut rng);
+    let mut move_list = MoveList::default();
+    let mut positions = Vec::new();
+    loop {
+        let before_generation = game_state.clone();
+        if game_state.get_possible_moves(&mut move_list, &mut rng) == MoveGenerationResult::GameOver
+        {
+            return RandomGame {
+                positions,
+                end: before_generation,
+                final_state: game_state,
+            };
+        }
+        let move_ = move_list[rng.gen_range(0..move_list.len())];
+        positions.push(RandomPosition {
+            before_generation,
+            game_state: game_state.clone(),
+            moves: move_list.into_iter().copied().collect(),
+            move_,
+        });
+        game_state.do_move(move_);
+    }
+}
+
+pub(crate) fn random_games(num_games: u64) -> impl Iterator<Item = RandomGame> {
+    (0..num_games).map(random_game)
+}
+
+// The positions of the first num_games random games, one game after the other
+pub(crate) fn random_positions(num_games: u64) -> impl Iterator<Item = RandomPosition> {
+    random_games(num_games).flat_map(|game| game.positions)
+}
@@ -2,6 +2,11 @@ pub const NUM_TILE_COLORS: usize = 5;
 
 #[repr(u8)]
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
 pub enum TileColor {
     Blue,
     Yellow,
@@ -51,15 +56,32 @@ impl From<TileColor> for char {
     }
 }
 
-impl From<char> for TileColor {
-    fn from(color: char) -> Self {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidTileColor(pub char);
+
+impl std::fmt::Display for InvalidTileColor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Invalid tile color '{}', expected one of B, Y, R, K, W",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for InvalidTileColor {}
+
+impl TryFrom<char> for TileColor {
+    type Error = InvalidTileColor;
+
+    fn try_from(color: char) -> Result<Self, Self::Error> {
         match color {
-            'B' => Self::Blue,
-            'Y' => Self::Yellow,
-            'R' => Self::Red,
-            'K' => Self::Green,
-            'W' => Self::White,
-            _ => panic!("Invalid color: {}", color),
+            'B' => Ok(Self::Blue),
+            'Y' => Ok(Self::Yellow),
+            'R' => Ok(Self::Red),
+            'K' => Ok(Self::Green),
+            'W' => Ok(Self::White),
+            _ => Err(InvalidTileColor(color)),
         }
     }
 }
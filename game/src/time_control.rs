@@ -0,0 +1,179 @@
+/*
+    Time controls of a match, all times are in milliseconds.
+    - Unlimited: The players decide how long they think, the time is only measured.
+    - Per move: Every move has to be made within the time, unused time is lost.
+    - Clock: Like a chess clock, every player starts with the base time and gets the increment after each move.
+      A player whose clock runs out forfeits the game.
+    Written as "unlimited", "1000/move" and "60000+500" (base + increment).
+*/
+
+// Part of the time that is kept back for the communication with the player, at most this many milliseconds
+const MOVE_OVERHEAD: u64 = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum TimeControl {
+    #[default]
+    Unlimited,
+    PerMove(u64),
+    Clock {
+        base: u64,
+        increment: u64,
+    },
+}
+
+impl TimeControl {
+    // The time a player has for the first move, None without a time limit
+    pub fn initial_time(&self) -> Option<u64> {
+        match self {
+            Self::Unlimited => None,
+            Self::PerMove(time) => Some(*time),
+            Self::Clock { base, .. } => Some(*base),
+        }
+    }
+
+    // How long a player with time_left on the clock should think about the next move
+    pub fn move_budget(&self, time_left: u64) -> Option<u64> {
+        let budget = match self {
+            Self::Unlimited => return None,
+            Self::PerMove(_) => time_left,
+            Self::Clock { increment, .. } => (time_left / 20 + increment).min(time_left / 2),
+        };
+        Some(budget - (budget / 10).min(MOVE_OVERHEAD))
+    }
+}
+
+impl std::fmt::Display for TimeControl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unlimited => write!(f, "unlimited"),
+            Self::PerMove(time) => write!(f, "{}/move", time),
+            Self::Clock { base, increment } => write!(f, "{}+{}", base, increment),
+        }
+    }
+}
+
+impl std::str::FromStr for TimeControl {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        let string = string.trim();
+        let parse_time = |time: &str| {
+            time.trim()
+                .parse::<u64>()
+                .map_err(|_| format!("Invalid time {}", time))
+        };
+        if string == "unlimited" {
+            Ok(Self::Unlimited)
+        } else if let Some(time) = string.strip_suffix("/move") {
+            Ok(Self::PerMove(parse_time(time)?))
+        } else if let Some((base, increment)) = string.split_once('+') {
+            Ok(Self::Clock {
+                base: parse_time(base)?,
+                increment: parse_time(increment)?,
+            })
+        } else {
+            Err(format!("Invalid time control {}", string))
+        }
+    }
+}
+
+// The clocks of all players in a match
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Clocks {
+    time_control: TimeControl,
+    time_left: Vec<u64>,
+}
+
+impl Clocks {
+    pub fn new(time_control: TimeControl, num_players: usize) -> Self {
+        Self {
+            time_control,
+            time_left: vec![time_control.initial_time().unwrap_or(0); num_players],
+        }
+    }
+
+    pub fn time_control(&self) -> TimeControl {
+        self.time_control
+    }
+
+    // The time the player has for the next move, None without a time limit
+    pub fn time_left(&self, player: usize) -> Option<u64> {
+        self.time_control
+            .initial_time()
+            .map(|_| self.time_left[player])
+    }
+
+    // Charges the player for a move that took elapsed milliseconds.
+    // Returns false if the player took longer than the time left, the clock then stays at 0.
+    pub fn charge(&mut self, player: usize, elapsed: u64) -> bool {
+        match self.time_control {
+            TimeControl::Unlimited => true,
+            TimeControl::PerMove(time) => elapsed <= time,
+            TimeControl::Clock { increment, .. } => {
+                let in_time = elapsed <= self.time_left[player];
+                self.time_left[player] = self.time_left[player].saturating_sub(elapsed);
+                if in_time {
+                    self.time_left[player] += increment;
+                }
+                in_time
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_time_control_round_trip() {
+        for time_control in [
+            TimeControl::Unlimited,
+            TimeControl::PerMove(1000),
+            TimeControl::Clock {
+                base: 60000,
+                increment: 500,
+            },
+        ] {
+            let string = time_control.to_string();
+            assert_eq!(string.parse::<TimeControl>(), Ok(time_control));
+        }
+        assert!("1000".parse::<TimeControl>().is_err());
+        assert!("a+500".parse::<TimeControl>().is_err());
+        assert!("x/move".parse::<TimeControl>().is_err());
+    }
+
+    #[test]
+    fn test_clocks() {
+        let mut clocks = Clocks::new(TimeControl::Unlimited, 2);
+        assert_eq!(clocks.time_left(0), None);
+        assert!(clocks.charge(0, u64::MAX));
+
+        let mut clocks = Clocks::new(TimeControl::PerMove(100), 2);
+        assert!(clocks.charge(0, 100));
+        assert_eq!(clocks.time_left(0), Some(100));
+        assert!(!clocks.charge(1, 101));
+
+        let mut clocks = Clocks::new(
+            TimeControl::Clock {
+                base: 1000,
+                increment: 100,
+            },
+            2,
+        );
+        assert!(clocks.charge(0, 600));
+        assert_eq!(clocks.time_left(0), Some(500));
+        assert_eq!(clocks.time_left(1), Some(1000));
+        assert!(clocks.charge(0, 500));
+        assert_eq!(clocks.time_left(0), Some(100));
+        assert!(!clocks.charge(0, 101));
+        assert_eq!(clocks.time_left(0), Some(0));
+
+        // The budget stays within the time left and keeps some of it back
+        let time_control = clocks.time_control();
+        assert_eq!(time_control.move_budget(1000), Some(135));
+        assert_eq!(TimeControl::PerMove(100).move_budget(100), Some(90));
+        assert_eq!(TimeControl::PerMove(2000).move_budget(2000), Some(1950));
+        assert_eq!(TimeControl::Unlimited.move_budget(2000), None);
+    }
+}
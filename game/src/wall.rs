@@ -23,6 +23,8 @@ pub const WALL_COLOR_MASKS: [u32; NUM_TILE_COLORS] = [
 ];
 
 pub const ROW_MASK: u32 = 0b11111;
+#[allow(clippy::unusual_byte_groupings)]
+pub const COLUMN_MASK: u32 = 0b00_0_00001_0_00001_0_00001_0_00001_0_00001;
 
 #[inline]
 pub fn field_at(row: usize, col: usize) -> u32 {
@@ -55,6 +57,16 @@ pub fn get_placed_tile_score(occupancy: u32, new_tile_pos: u8) -> u32 {
     }
 }
 
+// The points of a new tile split up by direction: The length of the horizontal and of the vertical line of tiles the new tile is part of,
+// 0 for a direction without neighbors. A tile without any neighbors scores a single point.
+pub fn get_placed_tile_score_breakdown(occupancy: u32, new_tile_pos: u8) -> (u32, u32) {
+    let points = |neighbors: u32| if neighbors > 1 { neighbors } else { 0 };
+    (
+        points(count_row_neighbors(occupancy, new_tile_pos)),
+        points(count_column_neighbors(occupancy, new_tile_pos)),
+    )
+}
+
 const ROW_NEIGHBORS_LOOKUP: [[u8; 32]; 5] = [
     [
         1, 1, 2, 2, 1, 1, 3, 3, 1, 1, 2, 2, 1, 1, 4, 4, 1, 1, 2, 2, 1, 1, 3, 3, 1, 1, 2, 2, 1, 1,
@@ -134,6 +146,27 @@ pub fn count_complete_columns(mut occupancy: u32) -> u32 {
     occupancy.count_ones()
 }
 
+// Free wall: The fields in a row where a color may be placed without repeating it in the row or in a column
+#[inline]
+pub fn get_free_wall_candidates(
+    walls: &[u32; NUM_TILE_COLORS],
+    row: usize,
+    color: TileColor,
+) -> u32 {
+    let row_mask = get_row_mask(row);
+    let color_board = walls[color as usize];
+    if color_board & row_mask > 0 {
+        return 0;
+    }
+    let occupancy = walls.iter().fold(0, |acc, board| acc | board);
+    // Fold all rows of the color onto the first row to get the columns that already contain the color
+    let mut color_columns = 0;
+    for r in 0..5 {
+        color_columns |= (color_board >> (r * 6)) & ROW_MASK;
+    }
+    row_mask & !occupancy & !(color_columns << (row * 6))
+}
+
 pub fn count_full_colors(occupancy: u32) -> u32 {
     let mut num_full_colors = 0;
     for color_mask in WALL_COLOR_MASKS.iter() {
@@ -197,6 +230,25 @@ mod test {
         assert_eq!(count_full_colors(occupancy), 2);
     }
 
+    #[test]
+    fn test_free_wall_candidates() {
+        let mut walls = [0; NUM_TILE_COLORS];
+        assert_eq!(
+            get_free_wall_candidates(&walls, 2, TileColor::Red),
+            get_row_mask(2)
+        );
+
+        // Red in row 0 column 1 blocks column 1 for red in every other row
+        walls[TileColor::Red as usize] |= field_at(0, 1);
+        // Blue in row 2 column 3 occupies that field
+        walls[TileColor::Blue as usize] |= field_at(2, 3);
+        let candidates = get_free_wall_candidates(&walls, 2, TileColor::Red);
+        assert_eq!(candidates, field_at(2, 0) | field_at(2, 2) | field_at(2, 4));
+
+        // A color can't be placed twice in the same row
+        assert_eq!(get_free_wall_candidates(&walls, 0, TileColor::Red), 0);
+    }
+
     fn count_row_neighbors_check(mut occupancy: u32, new_tile_pos: u8) -> u32 {
         // Create a bitboard with the new tile on it
         let new_tile: u32 = 1 << new_tile_pos;
@@ -0,0 +1,122 @@
+use crate::factories::MAX_FACTORIES;
+use crate::tile_color::{TileColor, NUM_TILE_COLORS};
+use crate::MAX_PLAYERS;
+
+/*
+    Zobrist keys: Every feature of a game state (e.g. "factory 3 holds 2 red tiles") has a random 64 bit key.
+    The hash of a game state is the xor of the keys of all its features, so a change of a feature only needs
+    the old key to be xored out and the new key to be xored in.
+    Features with a value of zero have no key, the hash of an empty factory is 0.
+*/
+
+// Tile counts of a color in the bag, the lid or a factory are at most 20, the table is a bit larger to stay a power of two
+const COUNT_SLOTS: usize = 32;
+const WALL_FIELDS: usize = 30;
+
+const fn splitmix64(state: u64) -> u64 {
+    let mut z = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+const fn generate_keys<const N: usize>(seed: u64) -> [u64; N] {
+    let mut keys = [0; N];
+    let mut i = 0;
+    while i < N {
+        keys[i] = splitmix64(seed.wrapping_mul(0x1000_0000_01B3).wrapping_add(i as u64));
+        i += 1;
+    }
+    keys
+}
+
+const BAG_KEYS: [u64; NUM_TILE_COLORS * COUNT_SLOTS] = generate_keys(1);
+const OUT_OF_BAG_KEYS: [u64; NUM_TILE_COLORS * COUNT_SLOTS] = generate_keys(2);
+const FACTORY_KEYS: [u64; MAX_FACTORIES * NUM_TILE_COLORS * COUNT_SLOTS] = generate_keys(3);
+const WALL_KEYS: [u64; MAX_PLAYERS * NUM_TILE_COLORS * WALL_FIELDS] = generate_keys(4);
+const PATTERN_LINE_KEYS: [u64; MAX_PLAYERS * 5 * 6] = generate_keys(5);
+const PATTERN_LINE_COLOR_KEYS: [u64; MAX_PLAYERS * 5 * NUM_TILE_COLORS] = generate_keys(6);
+const CURRENT_PLAYER_KEYS: [u64; MAX_PLAYERS] = generate_keys(7);
+const NEXT_ROUND_STARTING_PLAYER_KEYS: [u64; MAX_PLAYERS] = generate_keys(8);
+const SCORE_KEYS: [u64; MAX_PLAYERS] = generate_keys(9);
+const FLOOR_LINE_KEYS: [u64; MAX_PLAYERS] = generate_keys(10);
+const SETUP_KEY: u64 = splitmix64(11);
+pub const TILE_TAKEN_FROM_CENTER_KEY: u64 = splitmix64(12);
+
+#[inline]
+fn count_index(count: u8) -> usize {
+    count as usize & (COUNT_SLOTS - 1)
+}
+
+#[inline]
+pub fn bag(color: usize, count: u8) -> u64 {
+    if count == 0 {
+        return 0;
+    }
+    BAG_KEYS[color * COUNT_SLOTS + count_index(count)]
+}
+
+#[inline]
+pub fn out_of_bag(color: usize, count: u8) -> u64 {
+    if count == 0 {
+        return 0;
+    }
+    OUT_OF_BAG_KEYS[color * COUNT_SLOTS + count_index(count)]
+}
+
+#[inline]
+pub fn factory(factory_index: usize, color: usize, count: u8) -> u64 {
+    if count == 0 {
+        return 0;
+    }
+    FACTORY_KEYS[(factory_index * NUM_TILE_COLORS + color) * COUNT_SLOTS + count_index(count)]
+}
+
+// Position is the bit index of the field on the wall bitboard
+#[inline]
+pub fn wall(player: usize, color: usize, position: usize) -> u64 {
+    WALL_KEYS[(player * NUM_TILE_COLORS + color) * WALL_FIELDS + position]
+}
+
+#[inline]
+pub fn pattern_line(player: usize, line: usize, count: u8, color: Option<TileColor>) -> u64 {
+    let count_key = if count == 0 {
+        0
+    } else {
+        PATTERN_LINE_KEYS[(player * 5 + line) * 6 + count as usize % 6]
+    };
+    let color_key = match color {
+        Some(color) => {
+            PATTERN_LINE_COLOR_KEYS[(player * 5 + line) * NUM_TILE_COLORS + color as usize]
+        }
+        None => 0,
+    };
+    count_key ^ color_key
+}
+
+// Scores and floor lines have no fixed range, their keys are derived from the value
+#[inline]
+pub fn score(player: usize, score: i16) -> u64 {
+    splitmix64(SCORE_KEYS[player] ^ score as u16 as u64)
+}
+
+#[inline]
+pub fn floor_line(player: usize, progress: u8) -> u64 {
+    splitmix64(FLOOR_LINE_KEYS[player] ^ progress as u64)
+}
+
+#[inline]
+pub fn current_player(player: usize) -> u64 {
+    CURRENT_PLAYER_KEYS[player]
+}
+
+#[inline]
+pub fn next_round_starting_player(player: usize) -> u64 {
+    NEXT_ROUND_STARTING_PLAYER_KEYS[player]
+}
+
+// The number of players and the ruleset never change during a game
+#[inline]
+pub fn setup(num_players: usize, ruleset_bits: u8) -> u64 {
+    splitmix64(SETUP_KEY ^ (num_players as u64) << 8 ^ ruleset_bits as u64)
+}
@@ -39,7 +39,16 @@ impl Player for HumanCommandLinePlayer {
         let mut rng = SmallRng::from_entropy();
         game_state.get_possible_moves(&mut self.move_list, &mut rng);
 
+        // On a free wall the moves after the drafting only choose the column a full pattern line is tiled to
+        if self.move_list[0].is_wall_tiling() {
+            return self.prompt_for_wall_column(&game_state);
+        }
+
         loop {
+            if let Some(move_) = self.prompt_for_notation(&game_state) {
+                return move_;
+            }
+
             let mut remaining_moves = self.move_list.into_iter().cloned().collect::<Vec<_>>();
             let result1 = self.prompt_for_factory_number(&mut remaining_moves);
             if result1 == PromptResult::Reset {
@@ -78,6 +87,77 @@ impl Player for HumanCommandLinePlayer {
 }
 
 impl HumanCommandLinePlayer {
+    // Lets the user type the whole move at once, returns None if they want to select it step by step
+    fn prompt_for_notation(&self, game_state: &GameState) -> Option<Move> {
+        loop {
+            println!("Enter a move (e.g. 3R>2 or CB>F) or press enter to select it step by step:");
+            let mut input = String::new();
+            if std::io::stdin().read_line(&mut input).is_err() {
+                println!("Failed to read input");
+                continue;
+            }
+            if input.trim().is_empty() {
+                return None;
+            }
+
+            match game_state.parse_move_notation(&input) {
+                Ok(move_) => return Some(move_),
+                Err(e) => println!("{}", e),
+            }
+        }
+    }
+
+    fn prompt_for_wall_column(&self, game_state: &GameState) -> Move {
+        let (row, _) = self.move_list[0].get_wall_tiling_field();
+        let mut options = self
+            .move_list
+            .into_iter()
+            .map(|move_| move_.get_wall_tiling_field().1 + 1)
+            .collect::<Vec<_>>();
+        options.sort();
+
+        loop {
+            println!(
+                "Select the column of the wall to place the {} tile of pattern line {} on:",
+                self.move_list[0].color,
+                row + 1
+            );
+            let options_string = options
+                .iter()
+                .map(|option| option.to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            println!("Options: {}", options_string);
+
+            let mut input = String::new();
+            if std::io::stdin().read_line(&mut input).is_err() {
+                println!("Failed to read input");
+                continue;
+            }
+
+            // The tiling may also be entered in the move notation, e.g. TR>3@2
+            let column = match input.trim().parse::<usize>() {
+                Ok(column) => column,
+                Err(_) => match game_state.parse_move_notation(&input) {
+                    Ok(move_) => return move_,
+                    Err(e) => {
+                        println!("Invalid column: {}", e);
+                        continue;
+                    }
+                },
+            };
+
+            match self
+                .move_list
+                .into_iter()
+                .find(|move_| move_.get_wall_tiling_field().1 + 1 == column)
+            {
+                Some(move_) => return *move_,
+                None => println!("Column {} is not available", column),
+            }
+        }
+    }
+
     fn prompt_for_factory_number(&self, remaining_moves: &mut Vec<Move>) -> PromptResult {
         loop {
             let mut available_factories = HashSet::new();
@@ -1,16 +1,24 @@
-use game::{MoveList, Player};
+use game::{GameState, Move, MoveList, Player};
 use rand::{rngs::SmallRng, SeedableRng};
 
+// Plays the move after which its own projected score (see GameState::projected_scores) is the highest.
+// Ties are broken by the projected scores of the opponents, the move that leaves the best opponent with the fewest points is played.
 pub struct GreedyPlayer {
     name: String,
     move_list: MoveList,
+    rng: SmallRng,
 }
 
 impl Default for GreedyPlayer {
     fn default() -> Self {
         let name = "Greedy Player".to_string();
         let move_list = MoveList::default();
-        Self { name, move_list }
+        let rng = SmallRng::from_entropy();
+        Self {
+            name,
+            move_list,
+            rng,
+        }
     }
 }
 
@@ -24,18 +32,26 @@ impl Player for GreedyPlayer {
         self.name = name.to_string();
     }
 
-    async fn get_move(&mut self, game_state: &game::GameState) -> game::Move {
+    async fn get_move(&mut self, game_state: &GameState) -> Move {
         let mut game_state = game_state.clone();
-        game_state.get_possible_moves(&mut self.move_list, &mut SmallRng::from_entropy());
-        let mut best_move = self.move_list[0];
-        let mut best_score = -1000;
+        game_state.get_possible_moves(&mut self.move_list, &mut self.rng);
         let me = usize::from(game_state.get_current_player());
+        let mut best_move = self.move_list[0];
+        let mut best_evaluation = (i16::MIN, i16::MIN);
         for move_ in &self.move_list {
-            let mut game_state_clone = game_state.clone();
-            game_state_clone.do_move(*move_);
-            let score = game_state.get_scores()[me];
-            if score > best_score {
-                best_score = score;
+            let undo = game_state.do_move(*move_);
+            let scores = game_state.projected_scores(true);
+            game_state.undo_move(undo);
+            let best_opponent_score = scores
+                .iter()
+                .enumerate()
+                .filter(|(player, _)| *player != me)
+                .map(|(_, score)| *score)
+                .max()
+                .unwrap_or(0);
+            let evaluation = (scores[me], -best_opponent_score);
+            if evaluation > best_evaluation {
+                best_evaluation = evaluation;
                 best_move = *move_;
             }
         }
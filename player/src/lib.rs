@@ -2,3 +2,4 @@ pub mod command_line_player;
 pub mod greedy_player;
 pub mod mcts;
 pub mod random_player;
+pub mod solver;
@@ -5,10 +5,22 @@ pub struct ProbabilisticOutcome {
     pub factories: Factories,
     pub bag: Bag,
     pub out_of_bag: Bag,
+    pub probability: f64, // Probability of this refill, used to weight the chance children
+}
+
+impl From<RefillOutcome> for ProbabilisticOutcome {
+    fn from(outcome: RefillOutcome) -> Self {
+        Self {
+            factories: outcome.factories,
+            bag: outcome.bag,
+            out_of_bag: outcome.out_of_bag,
+            probability: outcome.probability,
+        }
+    }
 }
 
 impl ProbabilisticOutcome {
-    pub fn apply_to_game_state(&self, game_state: &mut GameState) {
+    pub fn apply_to_game_state(&self, game_state: &mut GameState) -> EventUndo {
         #[cfg(debug_assertions)]
         {
             let factories = game_state.get_factories();
@@ -17,7 +29,9 @@ impl ProbabilisticOutcome {
         #[cfg(debug_assertions)]
         let original_game_state = game_state.clone();
 
-        game_state.evaluate_round(); // This will move the tiles from the factories to the pattern lines
+        let (_, round_undo) = game_state.evaluate_round_with_undo(); // This will move the tiles from the factories to the pattern lines
+        let bag = game_state.get_bag();
+        let out_of_bag = game_state.get_out_of_bag();
         game_state.set_factories(self.factories.clone()); // Overwrite the factories with the outcome of the event
 
         // The number of tiles in and out of bag also changes when the factories are refilled, so overwrite those as well
@@ -34,6 +48,42 @@ impl ProbabilisticOutcome {
                 panic!();
             }
         }
+
+        EventUndo::Probabilistic {
+            round_undo,
+            bag,
+            out_of_bag,
+        }
+    }
+}
+
+// Restores the game state from before an event was applied
+#[derive(Debug, Clone, Copy)]
+pub enum EventUndo {
+    Deterministic(MoveUndo),
+    Probabilistic {
+        round_undo: RoundUndo,
+        bag: Bag,
+        out_of_bag: Bag,
+    },
+}
+
+impl EventUndo {
+    pub fn undo(self, game_state: &mut GameState) {
+        match self {
+            EventUndo::Deterministic(move_undo) => game_state.undo_move(move_undo),
+            EventUndo::Probabilistic {
+                round_undo,
+                bag,
+                out_of_bag,
+            } => {
+                // The factories were empty before they were refilled by the outcome
+                game_state.set_factories(Factories::empty(game_state.get_num_players()));
+                game_state.set_bag(bag);
+                game_state.set_out_of_bag(out_of_bag);
+                game_state.undo_evaluate_round(round_undo);
+            }
+        }
     }
 }
 
@@ -44,9 +94,9 @@ pub enum Event {
 }
 
 impl Event {
-    pub fn apply_to_game_state(&self, game_state: &mut GameState) {
+    pub fn apply_to_game_state(&self, game_state: &mut GameState) -> EventUndo {
         match self {
-            Event::Deterministic(move_) => game_state.do_move(*move_),
+            Event::Deterministic(move_) => EventUndo::Deterministic(game_state.do_move(*move_)),
             Event::Probabilistic(outcome) => {
                 #[cfg(debug_assertions)]
                 {
@@ -67,7 +117,7 @@ impl std::fmt::Display for Event {
                 let string = outcome
                     .factories
                     .iter()
-                    .take(NUM_FACTORIES - 1)
+                    .take(outcome.factories.num_factories() - 1)
                     .map(|factory| {
                         factory
                             .iter()
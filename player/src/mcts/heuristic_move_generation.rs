@@ -22,10 +22,42 @@ pub fn playout(mut game_state: GameState, rng: &mut SmallRng) -> Value {
         }
     }
 
-    Value::from_game_scores(game_state.get_scores())
+    Value::from_game_scores(game_state.get_scores(), &game_state.final_ranking())
+}
+
+// The field a tile of the color would be placed on in the row, on a free wall this is the candidate scoring the most points
+fn get_target_field(
+    walls: &[u32; NUM_TILE_COLORS],
+    row: usize,
+    color: usize,
+    free_wall: bool,
+) -> Option<u32> {
+    let occupancy = walls.iter().fold(0, |acc, board| acc | board);
+    if free_wall {
+        let candidates = wall::get_free_wall_candidates(walls, row, TileColor::from(color));
+        (0..5)
+            .map(|column| field_at(row, column))
+            .filter(|field| candidates & field > 0)
+            .max_by_key(|field| get_placed_tile_score(occupancy, field.trailing_zeros() as u8))
+    } else {
+        let tile = wall::get_row_mask(row) & wall::WALL_COLOR_MASKS[color];
+        (occupancy & tile == 0).then_some(tile)
+    }
+}
+
+fn get_wall_tiling_move(game_state: &GameState, player_index: usize, row: usize) -> Move {
+    let walls = game_state.get_walls()[player_index];
+    let color = game_state.get_pattern_lines_colors()[player_index][row].unwrap(); // Must be Some because the line is waiting to be tiled
+    let new_tile = get_target_field(&walls, row, color as usize, true).unwrap(); // Must be Some because the line has candidates
+    let column = new_tile.trailing_zeros() as usize - row * 6;
+    Move::new_wall_tiling(row, column, color)
 }
 
 pub fn get_random_move(game_state: &mut GameState, rng: &mut SmallRng) -> Option<Move> {
+    if let Some((player, row)) = game_state.get_pending_wall_tiling() {
+        return Some(get_wall_tiling_move(game_state, player.into(), row));
+    }
+
     let is_round_over = game_state.get_factories().is_empty();
 
     if is_round_over {
@@ -43,19 +75,23 @@ pub fn get_random_move(game_state: &mut GameState, rng: &mut SmallRng) -> Option
     let pattern_lines_occupancy = game_state.get_pattern_lines_occupancy()[current_player];
     let wall_occupancy = game_state.get_wall_ocupancy()[current_player];
     let factories = game_state.get_factories();
+    let free_wall = game_state.get_ruleset().is_free_wall();
 
     // Calculate the wall after the end of this round by placing all full pattern lines on the wall already
+    let mut walls_after_round = game_state.get_walls()[current_player];
     let mut wall_after_round = wall_occupancy;
     for (pattern_line_index, no_tiles_in_pattern_line) in pattern_lines_occupancy.iter().enumerate()
     {
         if *no_tiles_in_pattern_line as usize != pattern_line_index + 1 {
             continue;
         }
-        let color = pattern_line_colors[pattern_line_index].unwrap();
-        let color_mask = wall::WALL_COLOR_MASKS[color as usize];
-        let row_mask = wall::get_row_mask(pattern_line_index);
-        let new_tile = row_mask & color_mask;
-        wall_after_round |= new_tile;
+        let color = pattern_line_colors[pattern_line_index].unwrap() as usize;
+        if let Some(new_tile) =
+            get_target_field(&walls_after_round, pattern_line_index, color, free_wall)
+        {
+            walls_after_round[color] |= new_tile;
+            wall_after_round |= new_tile;
+        }
     }
 
     // Calculate the score we gain by placing a tile in each field of the wall and how many tiles we are missing in the pattern lines
@@ -64,14 +100,13 @@ pub fn get_random_move(game_state: &mut GameState, rng: &mut SmallRng) -> Option
 
     let current_complete_rows = wall::count_complete_rows(wall_after_round);
     let current_complete_columns = wall::count_complete_columns(wall_after_round);
-    let current_full_colors = wall::count_full_colors(wall_after_round);
 
-    for (color, color_mask) in wall::WALL_COLOR_MASKS.iter().enumerate() {
+    for color in 0..NUM_TILE_COLORS {
         for row in 0..5 {
-            let row_mask = wall::get_row_mask(row);
-            let tile = row_mask & color_mask;
+            let target_field = get_target_field(&walls_after_round, row, color, free_wall);
+            let tile = target_field.unwrap_or(0);
             let tile_pos = tile.trailing_zeros();
-            let already_occupied = wall_after_round & tile > 0;
+            let already_occupied = target_field.is_none();
 
             if let Some(line_color) = pattern_line_colors[row] {
                 if line_color != TileColor::from(color) || already_occupied {
@@ -95,8 +130,7 @@ pub fn get_random_move(game_state: &mut GameState, rng: &mut SmallRng) -> Option
             let row_score = new_complete_rows - current_complete_rows;
             let new_complete_columns = wall::count_complete_columns(wall_with_tile);
             let col_score = new_complete_columns - current_complete_columns;
-            let new_full_colors = wall::count_full_colors(wall_with_tile);
-            let color_score = new_full_colors - current_full_colors;
+            let color_score = (walls_after_round[color].count_ones() == 4) as u32;
 
             let final_score = score + row_score * 2 + col_score * 7 + color_score * 10;
             wall_field_score[row][color] = final_score as u8;
@@ -108,6 +142,8 @@ pub fn get_random_move(game_state: &mut GameState, rng: &mut SmallRng) -> Option
     let floor_line_progress = game_state.get_floor_line_progress()[current_player]
         .min(FLOOR_LINE_PENALTY.len() as u8 - 1) as usize;
     let floor_line_penalty = FLOOR_LINE_PENALTY[floor_line_progress];
+    let single_pattern_line =
+        game_state.get_ruleset().pattern_line_placement == PatternLinePlacement::Single;
 
     for (factory_index, factory_content) in factories.iter().enumerate() {
         for (tile_color, number_of_tiles) in factory_content.iter().enumerate() {
@@ -117,6 +153,7 @@ pub fn get_random_move(game_state: &mut GameState, rng: &mut SmallRng) -> Option
             }
 
             let color = TileColor::from(tile_color);
+            let drafted_tiles = number_of_tiles;
 
             let mut tiles_to_discard = 0;
             let mut score: f32 = 0.0;
@@ -128,7 +165,35 @@ pub fn get_random_move(game_state: &mut GameState, rng: &mut SmallRng) -> Option
             }
 
             let mut pattern: [u8; 6] = [0; 6];
-            if maximum_tiles == 0 {
+            if single_pattern_line {
+                // All tiles go into one pattern line, prefer lines that are completed and discard as few tiles as possible
+                let mut best_line: Option<(usize, f32)> = None;
+                let order = PERMUTATIONS[rng.gen_range(0..PERMUTATIONS.len())];
+                for pattern_line_index in order.iter() {
+                    let pattern_line_index = *pattern_line_index as usize;
+                    let missing_tiles = missing_tiles[tile_color][pattern_line_index];
+                    if missing_tiles == 0 {
+                        continue;
+                    }
+                    let placed_tiles = u8::min(drafted_tiles, missing_tiles);
+                    let mut line_score = -((drafted_tiles - placed_tiles) as f32);
+                    if placed_tiles == missing_tiles {
+                        line_score += wall_field_score[pattern_line_index][tile_color] as f32;
+                    }
+                    if best_line.is_none_or(|(_, best_line_score)| line_score > best_line_score) {
+                        best_line = Some((pattern_line_index, line_score));
+                    }
+                }
+                tiles_to_discard = drafted_tiles;
+                if let Some((pattern_line_index, _)) = best_line {
+                    let missing_tiles = missing_tiles[tile_color][pattern_line_index];
+                    pattern[pattern_line_index] = u8::min(drafted_tiles, missing_tiles);
+                    tiles_to_discard -= pattern[pattern_line_index];
+                    if pattern[pattern_line_index] == missing_tiles {
+                        score += wall_field_score[pattern_line_index][tile_color] as f32;
+                    }
+                }
+            } else if maximum_tiles == 0 {
                 tiles_to_discard += number_of_tiles;
             } else if number_of_tiles == maximum_tiles {
                 pattern = missing_tiles[tile_color];
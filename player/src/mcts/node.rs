@@ -6,28 +6,8 @@ use rand::Rng as _;
 
 const EXPANSION_PROBABILITY: f64 = 0.05;
 
-// 2 players
-#[cfg(not(any(feature = "three_players", feature = "four_players")))]
 const C: f32 = 0.1;
-#[cfg(not(any(feature = "three_players", feature = "four_players")))]
 const C_BASE: f32 = 30_000.0;
-#[cfg(not(any(feature = "three_players", feature = "four_players")))]
-const C_FACTOR: f32 = std::f32::consts::SQRT_2;
-
-// 3 players
-#[cfg(feature = "three_players")]
-const C: f32 = 0.1;
-#[cfg(feature = "three_players")]
-const C_BASE: f32 = 30_000.0;
-#[cfg(feature = "three_players")]
-const C_FACTOR: f32 = std::f32::consts::SQRT_2;
-
-// 4 players
-#[cfg(feature = "four_players")]
-const C: f32 = 0.1;
-#[cfg(feature = "four_players")]
-const C_BASE: f32 = 30_000.0;
-#[cfg(feature = "four_players")]
 const C_FACTOR: f32 = std::f32::consts::SQRT_2;
 
 pub struct Node {
@@ -102,27 +82,52 @@ impl Node {
         }
     }
 
-    pub fn take_child_with_move(self, move_: Move) -> Option<Node> {
+    // The tree only holds the canonical moves, a move from a factory with the same tiles as an earlier one is found
+    // through its canonical move. The subtree of that move is then re-keyed to the factories of the real position.
+    pub fn take_child_with_move(self, move_: Move, game_state: &GameState) -> Option<Node> {
+        let canonical_move = game_state.canonical_move(move_);
         let mut children = self.children;
-        let mut index = None;
-        for (i, child) in children.iter().enumerate() {
-            if let Some(child_move) = child.get_move() {
-                if child_move == move_ {
-                    index = Some(i);
-                    break;
-                }
+        let index = children
+            .iter()
+            .position(|child| child.get_move() == Some(canonical_move))?;
+        let mut child = children.remove(index);
+        if canonical_move != move_ {
+            let permutation = factory_permutation(
+                game_state.get_factories(),
+                canonical_move.take_from_factory_index as usize,
+                move_.take_from_factory_index as usize,
+            );
+            child.previous_event = Event::Deterministic(move_);
+            for grandchild in child.children.iter_mut() {
+                grandchild.permute_factories(&permutation);
             }
         }
+        Some(child)
+    }
 
-        index.map(|index| children.remove(index))
+    // Moves the moves of this subtree to other factories until the round ends, the refills bring new factories
+    fn permute_factories(&mut self, permutation: &[u8; MAX_FACTORIES]) {
+        match &mut self.previous_event {
+            Event::Deterministic(move_) => {
+                if !move_.is_wall_tiling() {
+                    move_.take_from_factory_index =
+                        permutation[move_.take_from_factory_index as usize];
+                }
+            }
+            Event::Probabilistic(_) => return,
+        }
+        for child in self.children.iter_mut() {
+            child.permute_factories(permutation);
+        }
     }
 
     #[inline]
-    pub fn get_value(&self) -> Value {
+    pub fn get_value(&self, num_players: usize) -> Value {
         if self.n > 0. {
             self.q / self.n
         } else {
-            Value::from([std::f32::NEG_INFINITY; NUM_PLAYERS])
+            // An unvisited node doesn't know the number of players yet, its q is still empty
+            Value::filled(f32::NEG_INFINITY, num_players)
         }
     }
 
@@ -131,7 +136,7 @@ impl Node {
             let mean_value = self.q[player_index] / self.n;
             mean_value + c * (parent_n.ln() / self.n).sqrt()
         } else {
-            std::f32::INFINITY
+            f32::INFINITY
         }
     }
 
@@ -139,7 +144,7 @@ impl Node {
         let c_adjusted = C + C_FACTOR * ((1. + self.n + C_BASE) / C_BASE).ln();
 
         let mut best_child_index = 0;
-        let mut best_chuld_uct_value = std::f32::NEG_INFINITY;
+        let mut best_chuld_uct_value = f32::NEG_INFINITY;
 
         for (i, child) in self.children.iter().enumerate() {
             let value = child.get_uct_value(player_index, self.n, c_adjusted);
@@ -152,9 +157,31 @@ impl Node {
         &mut self.children[best_child_index]
     }
 
+    // Sum of the probabilities of the refills that were expanded as children of a chance node
+    fn expanded_probability(&self) -> f64 {
+        self.children
+            .iter()
+            .map(|child| match &child.previous_event {
+                Event::Probabilistic(outcome) => outcome.probability,
+                Event::Deterministic(_) => 0.,
+            })
+            .sum()
+    }
+
     fn select_child(&mut self, player_index: usize, rng: &mut SmallRng) -> &mut Node {
         if self.has_probabilistic_children {
-            let index = rng.gen_range(0..self.children.len());
+            // The expanded refills are visited according to their probabilities
+            let mut remaining = rng.gen::<f64>() * self.expanded_probability();
+            let mut index = self.children.len() - 1;
+            for (i, child) in self.children.iter().enumerate() {
+                if let Event::Probabilistic(outcome) = &child.previous_event {
+                    remaining -= outcome.probability;
+                    if remaining < 0. {
+                        index = i;
+                        break;
+                    }
+                }
+            }
             &mut self.children[index]
         } else {
             self.child_with_max_uct_value(player_index)
@@ -167,15 +194,26 @@ impl Node {
     }
 
     fn expand(&mut self, game_state: &mut GameState, move_list: &mut MoveList, rng: &mut SmallRng) {
-        let result = game_state.get_possible_moves(move_list, rng);
-        let is_game_over = matches!(result, MoveGenerationResult::GameOver);
-        let probabilistic_event = matches!(result, MoveGenerationResult::RoundOver);
+        // At the end of a round the refill is drawn here instead of during the move generation, so its probability is known
+        let refill = if game_state.get_factories().is_empty()
+            && game_state.get_pending_wall_tiling().is_none()
+        {
+            if game_state.evaluate_round() {
+                // If the game is over, we don't need to expand any children
+                self.is_game_over = true;
+                return;
+            }
+            let outcome = ProbabilisticOutcome::from(game_state.sample_refill(rng));
+            game_state.set_factories(outcome.factories.clone());
+            game_state.set_bag(outcome.bag);
+            game_state.set_out_of_bag(outcome.out_of_bag);
+            Some(outcome)
+        } else {
+            None
+        };
 
-        self.is_game_over = is_game_over;
-        if is_game_over {
-            // If the game is over, we don't need to expand any children
-            return;
-        }
+        // Moves from factories with the same tiles lead to equivalent positions, only one of them is expanded
+        game_state.get_canonical_moves(move_list, rng);
 
         // Create children nodes for each possible move
         let mut children = Vec::with_capacity(move_list.len());
@@ -183,23 +221,17 @@ impl Node {
             children.push(Node::new_deterministic(move_list[i]))
         }
 
-        if probabilistic_event {
-            // Create a probabilistic child for the probabilistic event that just happend during the move generation
+        match refill {
+            // Create a probabilistic child for the refill that was just drawn
             // Since it is not possible to expand all outcomes of a probabilistic event, we will only expand one of them
             // and dynamically expand the other outcomes later
-            self.expand_probabilistic_child(game_state, children);
-        } else {
+            Some(outcome) => self.expand_probabilistic_child(outcome, children),
             // Expand the current node with the children we just created
-            self.children = children;
+            None => self.children = children,
         }
     }
 
-    fn expand_probabilistic_child(&mut self, game_state: &mut GameState, children: Vec<Node>) {
-        let outcome = ProbabilisticOutcome {
-            factories: game_state.get_factories().clone(),
-            out_of_bag: game_state.get_out_of_bag(),
-            bag: game_state.get_bag(),
-        };
+    fn expand_probabilistic_child(&mut self, outcome: ProbabilisticOutcome, children: Vec<Node>) {
         let mut child = Node::new_probabilistic(outcome);
         child.children = children;
         self.children.push(child);
@@ -225,42 +257,58 @@ impl Node {
             // If we expand a new child every time we iterate this node, we would never visit the same child twice. This would cause our estimations of the value of the child to be very inaccurate.
 
             // Let's just try this:
+            // Once the expanded refills cover almost all outcomes (e.g. with few tiles left in the bag), no more are needed
             let desired_number_of_children = self.n.sqrt().ceil() as usize / 2;
-            if desired_number_of_children > self.children.len() {
-                // We will expand a new child
-                let mut game_state_clone = game_state.clone(); // Clone here because we don't want to modify the game state
-                game_state_clone.evaluate_round();
-                game_state_clone.fill_factories(rng);
-
-                let outcome = ProbabilisticOutcome {
-                    factories: game_state_clone.get_factories().clone(),
-                    out_of_bag: game_state_clone.get_out_of_bag(),
-                    bag: game_state_clone.get_bag(),
-                };
-                let child = Node::new_probabilistic(outcome);
-                self.children.push(child);
+            if desired_number_of_children > self.children.len()
+                && self.expanded_probability() < 0.99
+            {
+                // We will expand a new child, the game state is restored afterwards because the child is applied like any other
+                let (_, round_undo) = game_state.evaluate_round_with_undo();
+                let outcome = ProbabilisticOutcome::from(game_state.sample_refill(rng));
+                game_state.undo_evaluate_round(round_undo);
+
+                // Refills that were drawn before already have a child, it is weighted by its probability
+                let is_new_outcome =
+                    self.children
+                        .iter()
+                        .all(|child| match &child.previous_event {
+                            Event::Probabilistic(existing) => {
+                                existing.factories != outcome.factories
+                            }
+                            Event::Deterministic(_) => true,
+                        });
+                if is_new_outcome {
+                    self.children.push(Node::new_probabilistic(outcome));
+                }
             }
         }
 
         let delta: Value = if self.children.is_empty() {
+            // The expansion and the playout may advance the game past the end of the round, so the leaf works on a copy
+            let mut game_state = game_state.clone();
             if rng.gen_bool(EXPANSION_PROBABILITY) {
-                self.expand(game_state, move_list, rng);
+                self.expand(&mut game_state, move_list, rng);
                 if !self.is_game_over {
-                    super::heuristic_move_generation::playout(game_state.clone(), rng)
+                    super::heuristic_move_generation::playout(game_state, rng)
                 } else if self.n == 0. {
-                    self.q = Value::from_game_scores(game_state.get_scores());
+                    self.q = Value::from_game_scores(
+                        game_state.get_scores(),
+                        &game_state.final_ranking(),
+                    );
                     self.n = 1.;
                     self.q
                 } else {
                     self.q / self.n
                 }
             } else {
-                super::heuristic_move_generation::playout(game_state.clone(), rng)
+                super::heuristic_move_generation::playout(game_state, rng)
             }
         } else {
             let next_child = self.select_child(current_player as usize, rng);
-            next_child.previous_event.apply_to_game_state(game_state);
-            next_child.iteration(game_state, move_list, rng)
+            let undo = next_child.previous_event.apply_to_game_state(game_state);
+            let delta = next_child.iteration(game_state, move_list, rng);
+            undo.undo(game_state);
+            delta
         };
 
         self.backpropagate(delta);
@@ -274,7 +322,7 @@ impl Node {
         }
 
         let player_index = usize::from(game_state.get_current_player());
-        let child = self.best_child(player_index);
+        let child = self.best_child(player_index, game_state.get_num_players());
 
         child.previous_event.apply_to_game_state(game_state);
         pv.push(child.previous_event.clone());
@@ -282,12 +330,12 @@ impl Node {
         child.build_pv(game_state, pv);
     }
 
-    pub fn best_child(&mut self, player_index: usize) -> &mut Node {
+    pub fn best_child(&mut self, player_index: usize, num_players: usize) -> &mut Node {
         let mut best_child_index = 0;
-        let mut best_child_value = std::f32::NEG_INFINITY;
+        let mut best_child_value = f32::NEG_INFINITY;
 
         for (i, child) in self.children.iter().enumerate() {
-            let value: Value = child.get_value();
+            let value: Value = child.get_value(num_players);
             if value[player_index] > best_child_value {
                 best_child_index = i;
                 best_child_value = value[player_index];
@@ -297,12 +345,12 @@ impl Node {
         &mut self.children[best_child_index]
     }
 
-    pub fn best_move(&mut self, player_index: usize) -> Option<Move> {
+    pub fn best_move(&mut self, player_index: usize, num_players: usize) -> Option<Move> {
         if self.children.is_empty() {
             return None;
         }
 
-        let child = self.best_child(player_index);
+        let child = self.best_child(player_index, num_players);
         match child.previous_event {
             Event::Deterministic(move_) => Some(move_),
             Event::Probabilistic(_) => None,
@@ -329,6 +377,31 @@ impl Node {
     }
 }
 
+/*
+    Maps the factories after taking from the canonical factory to the factories after taking the same tiles from the
+    factory that was actually chosen. Both factories belong to the group of factories with the same tiles. The emptied
+    canonical factory becomes the emptied chosen factory and the remaining factories of the group keep their order,
+    so the canonical moves in the subtree stay canonical.
+*/
+fn factory_permutation(
+    factories: &Factories,
+    canonical_factory: usize,
+    chosen_factory: usize,
+) -> [u8; MAX_FACTORIES] {
+    let mut permutation = [0; MAX_FACTORIES];
+    for (index, target) in permutation.iter_mut().enumerate() {
+        *target = index as u8;
+    }
+    let group: Vec<usize> = (canonical_factory..=chosen_factory)
+        .filter(|&index| factories[index] == factories[chosen_factory])
+        .collect();
+    for pair in group.windows(2) {
+        permutation[pair[1]] = pair[0] as u8;
+    }
+    permutation[canonical_factory] = chosen_factory as u8;
+    permutation
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct ChildCount {
     pub deterministic: usize,
@@ -341,3 +414,59 @@ impl std::ops::AddAssign for ChildCount {
         self.probabilistic += other.probabilistic;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_take_child_with_equivalent_move() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        let game_state = GameStateBuilder::new(2)
+            .factory(0, "BBYR")
+            .factory(1, "BBYR")
+            .factory(2, "RGWW")
+            .factory(3, "BBYR")
+            .center("Y")
+            .build()
+            .unwrap();
+        let mut root_node = Node::new_deterministic(Move::DUMMY);
+        let mut move_list = MoveList::default();
+        for _ in 0..3000 {
+            root_node.iteration(&mut game_state.clone(), &mut move_list, &mut rng);
+        }
+
+        // Only the moves from factory 1 are in the tree, taking from factory 4 reuses them
+        let mov = Move {
+            take_from_factory_index: 3,
+            color: TileColor::Blue,
+            pattern: [0, 2, 0, 0, 0, 0],
+        };
+        let child = root_node
+            .take_child_with_move(mov, &game_state)
+            .expect("The equivalent move is in the tree");
+        assert_eq!(child.get_move(), Some(mov));
+        assert!(!child.children.is_empty());
+
+        // The moves of the re-keyed subtree are the canonical moves of the real position
+        let mut after_move = game_state.clone();
+        after_move.do_move(mov);
+        after_move.get_canonical_moves(&mut move_list, &mut rng);
+        for grandchild in &child.children {
+            assert!(move_list.contains(grandchild.get_move().unwrap()));
+        }
+        let canonical_factories: Vec<u8> = child
+            .children
+            .iter()
+            .map(|grandchild| grandchild.get_move().unwrap().take_from_factory_index)
+            .collect();
+        assert!(canonical_factories.contains(&0));
+        assert!(!canonical_factories.contains(&1) && !canonical_factories.contains(&3));
+
+        let mut child = child;
+        for _ in 0..1000 {
+            child.iteration(&mut after_move.clone(), &mut move_list, &mut rng);
+        }
+    }
+}
@@ -1,14 +1,19 @@
 use super::node::Node;
 use crate::mcts::event::Event;
+use crate::solver::EndgameSolver;
 use game::*;
 use rand::{rngs::SmallRng, SeedableRng};
-use std::time::Instant;
+use std::time::{Duration, Instant};
+
+// Endgame positions are solved exactly if the solver finishes within this many nodes and a third of the time limit
+const SOLVER_NODE_LIMIT: u64 = 2_000_000;
 
 pub struct MonteCarloTreeSearch {
     name: String,
     root_node: Option<Node>,
     root_game_state: GameState,
     time_limit: u64,
+    solver: EndgameSolver,
 }
 
 fn do_iterations(
@@ -18,8 +23,10 @@ fn do_iterations(
     rng: &mut SmallRng,
 ) {
     let mut move_list = MoveList::new();
+    // Every iteration restores the game state on its way back up the tree
+    let mut game_state = root_game_state.clone();
     for _ in 0..iterations {
-        root_node.iteration(&mut root_game_state.clone(), &mut move_list, rng);
+        root_node.iteration(&mut game_state, &mut move_list, rng);
     }
 }
 
@@ -29,9 +36,7 @@ impl MonteCarloTreeSearch {
             .check_integrity()
             .expect("Trying to set root with invalid game state.");
 
-        if self.root_game_state.serialize_string() == game_state.serialize_string()
-            && self.root_node.is_some()
-        {
+        if self.root_game_state == *game_state && self.root_node.is_some() {
             println!("Keeping parts of the tree from previous search.");
         } else {
             self.root_game_state = game_state.clone();
@@ -41,8 +46,8 @@ impl MonteCarloTreeSearch {
 
     async fn search(&mut self, game_state: &GameState) -> Move {
         println!(
-            "Searching move using MCTS. Fen: {}",
-            game_state.serialize_string()
+            "Searching move using MCTS. Position: {}",
+            game_state.to_position_string()
         );
         let start_time = Instant::now();
         self.set_root(game_state);
@@ -52,17 +57,20 @@ impl MonteCarloTreeSearch {
         let mut completed_iterations: usize = 0;
         let search_start_time = Instant::now();
 
-        let factories: &[[u8; 5]; NUM_FACTORIES] = game_state.get_factories();
-        let all_empty = factories
-            .iter()
-            .all(|factory| factory.iter().all(|&tile| tile == 0));
-        if all_empty {
+        if game_state.get_factories().is_empty() && game_state.get_pending_wall_tiling().is_none() {
             panic!("Monte Carlo Tree search was started in a position where it is not possible to make a move.");
         }
 
+        if let Some(best_move) = self.solve_endgame(game_state, start_time) {
+            let mut game_state = game_state.clone();
+            game_state.do_move(best_move);
+            self.notify_move(&game_state, best_move).await;
+            return best_move;
+        }
+
         println!(
             "    Left Depth Iterations Value{} Principal variation",
-            " ".repeat(NUM_PLAYERS * 5 - "Value".len())
+            " ".repeat(game_state.get_num_players() * 5 - "Value".len())
         );
 
         let root_node = self.root_node.as_mut().unwrap();
@@ -78,7 +86,7 @@ impl MonteCarloTreeSearch {
                 time_left,
                 pv.len(),
                 completed_iterations,
-                root_node.get_value(),
+                root_node.get_value(game_state.get_num_players()),
                 pv.iter()
                     .map(|event| event.to_string())
                     .collect::<Vec<_>>()
@@ -103,7 +111,7 @@ impl MonteCarloTreeSearch {
         println!(
             "Search finished after {}ms. Value: {:7} PV-Depth: {} Iterations: {} Iterations/s: {:.2} PV: {}",
             start_time.elapsed().as_millis(),
-            root_node.get_value(),
+            root_node.get_value(game_state.get_num_players()),
             pv.len(),
             completed_iterations,
             iterations_per_ms * 1000.,
@@ -115,7 +123,9 @@ impl MonteCarloTreeSearch {
 
         let player_index = usize::from(game_state.get_current_player());
         println!("{:?}", root_node.count_nodes());
-        let best_move = root_node.best_move(player_index).unwrap();
+        let best_move = root_node
+            .best_move(player_index, game_state.get_num_players())
+            .unwrap();
         {
             let mut game_state = game_state.clone();
             game_state.do_move(best_move);
@@ -124,6 +134,31 @@ impl MonteCarloTreeSearch {
         best_move
     }
 
+    fn solve_endgame(&mut self, game_state: &GameState, start_time: Instant) -> Option<Move> {
+        if !EndgameSolver::is_final_round(game_state) {
+            return None;
+        }
+        self.solver.set_deadline(Some(
+            start_time + Duration::from_millis(self.time_limit / 3),
+        ));
+        match self.solver.solve(game_state) {
+            Some(solution) => {
+                println!(
+                    "Solved the endgame after {}ms. Margin: {} Nodes: {} Best move: {}",
+                    start_time.elapsed().as_millis(),
+                    solution.margin,
+                    solution.nodes,
+                    solution.best_move
+                );
+                Some(solution.best_move)
+            }
+            None => {
+                println!("The endgame could not be solved, falling back to the tree search.");
+                None
+            }
+        }
+    }
+
     pub fn store_tree(&self, min_visits: f32) {
         let mut current_id = 0;
         let mut data = String::from("digraph G {\n"); // Start of the DOT graph
@@ -156,8 +191,9 @@ impl Default for MonteCarloTreeSearch {
         Self {
             name: "Monte Carlo Tree Search".to_string(),
             root_node: None,
-            root_game_state: GameState::new(&mut rng),
+            root_game_state: GameState::new(MIN_PLAYERS, &mut rng), // Placeholder until the first search sets the root
             time_limit: 6000,
+            solver: EndgameSolver::new(SOLVER_NODE_LIMIT),
         }
     }
 }
@@ -180,21 +216,26 @@ impl Player for MonteCarloTreeSearch {
         self.time_limit = time;
     }
 
+    async fn reset(&mut self) {
+        self.root_node = None;
+        self.solver.clear();
+    }
+
     async fn notify_move(&mut self, new_game_state: &GameState, last_move: Move) {
         new_game_state
             .check_integrity()
             .expect("Trying to set root with invalid game state.");
-        if new_game_state.serialize_string() == self.root_game_state.serialize_string() {
+        if *new_game_state == self.root_game_state {
             return;
         }
 
         if let Some(new_root_node) = self
             .root_node
             .take()
-            .and_then(|root_node| root_node.take_child_with_move(last_move))
+            .and_then(|root_node| root_node.take_child_with_move(last_move, &self.root_game_state))
         {
             self.root_game_state.do_move(last_move);
-            if new_game_state.serialize_string() == self.root_game_state.serialize_string() {
+            if *new_game_state == self.root_game_state {
                 self.root_game_state = new_game_state.clone();
                 self.root_node = Some(new_root_node);
                 println!("Successfully applied the move {} to the tree.", last_move);
@@ -1,24 +1,39 @@
 use game::*;
 
+// The values are stored for the maximum number of players so Value stays Copy, only the first num_players entries are used
 #[derive(Debug, Clone, Copy, Default)]
-pub struct Value([f32; NUM_PLAYERS]);
+pub struct Value {
+    values: [f32; MAX_PLAYERS],
+    num_players: u8,
+}
 
 impl Value {
-    pub fn from_game_scores(game_scores: [i16; NUM_PLAYERS]) -> Self {
-        let max_score = game_scores.iter().cloned().fold(i16::MIN, i16::max);
-        let min_score = game_scores.iter().cloned().fold(i16::MAX, i16::min);
+    // The scores are normalized between the lowest and the highest score. Players with the same score are ordered by
+    // their placement (see GameState::final_ranking), each player they beat on the tie-break counts as half a point.
+    pub fn from_game_scores(game_scores: &[i16], placements: &[usize]) -> Self {
+        let num_players = game_scores.len();
+        let scores: Vec<f32> = (0..num_players)
+            .map(|i| {
+                let won_tie_breaks = (0..num_players)
+                    .filter(|&j| game_scores[j] == game_scores[i] && placements[j] > placements[i])
+                    .count();
+                game_scores[i] as f32 + won_tie_breaks as f32 * 0.5
+            })
+            .collect();
+        let max_score = scores.iter().cloned().fold(f32::MIN, f32::max);
+        let min_score = scores.iter().cloned().fold(f32::MAX, f32::min);
 
+        let mut value = [0.0; MAX_PLAYERS];
         let score_range = max_score - min_score;
-        if score_range == 0 {
-            // If all scores are the same, return 1 / NUM_PLAYERS for each player
+        if score_range == 0. {
+            // If all scores are the same, return 1 / num_players for each player
             // e.g. if there are 2 players, return [0.5, 0.5] for each player
-            return Self([1.0 / NUM_PLAYERS as f32; NUM_PLAYERS]);
+            value[..num_players].fill(1.0 / num_players as f32);
+            return Self::new(value, num_players);
         }
 
-        let mut value = [0.0; NUM_PLAYERS];
-        let score_range = score_range as f32;
-        for (i, &score) in game_scores.iter().enumerate() {
-            let normalized_score = (score - min_score) as f32 / score_range;
+        for (i, &score) in scores.iter().enumerate() {
+            let normalized_score = (score - min_score) / score_range;
             value[i] = normalized_score;
         }
 
@@ -28,14 +43,31 @@ impl Value {
             *value /= sum;
         }
 
-        Self(value)
+        Self::new(value, num_players)
+    }
+
+    pub fn filled(value: f32, num_players: usize) -> Self {
+        let mut values = [0.0; MAX_PLAYERS];
+        values[..num_players].fill(value);
+        Self::new(values, num_players)
+    }
+
+    fn new(values: [f32; MAX_PLAYERS], num_players: usize) -> Self {
+        Self {
+            values,
+            num_players: num_players as u8,
+        }
+    }
+
+    pub fn num_players(&self) -> usize {
+        self.num_players as usize
     }
 }
 
 impl std::fmt::Display for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let mut string = String::new();
-        for (i, &value) in self.0.iter().enumerate() {
+        for (i, &value) in self.values.iter().take(self.num_players()).enumerate() {
             if i > 0 {
                 string.push(' ');
             }
@@ -47,7 +79,9 @@ impl std::fmt::Display for Value {
 
 impl std::ops::AddAssign for Value {
     fn add_assign(&mut self, rhs: Self) {
-        for (lhs, rhs) in self.0.iter_mut().zip(rhs.0.iter()) {
+        // A default value has no players yet, it takes over the player count of the first value added to it
+        self.num_players = self.num_players.max(rhs.num_players);
+        for (lhs, rhs) in self.values.iter_mut().zip(rhs.values.iter()) {
             *lhs += *rhs;
         }
     }
@@ -55,7 +89,7 @@ impl std::ops::AddAssign for Value {
 
 impl std::ops::DivAssign<f32> for Value {
     fn div_assign(&mut self, rhs: f32) {
-        for value in self.0.iter_mut() {
+        for value in self.values.iter_mut() {
             *value /= rhs;
         }
     }
@@ -74,18 +108,20 @@ impl std::ops::Index<usize> for Value {
     type Output = f32;
 
     fn index(&self, index: usize) -> &Self::Output {
-        &self.0[index]
+        &self.values[index]
     }
 }
 
-impl std::convert::From<Value> for [f32; NUM_PLAYERS] {
+impl std::convert::From<Value> for Vec<f32> {
     fn from(value: Value) -> Self {
-        value.0
+        value.values[..value.num_players()].to_vec()
     }
 }
 
-impl std::convert::From<[f32; NUM_PLAYERS]> for Value {
-    fn from(value: [f32; NUM_PLAYERS]) -> Self {
-        Self(value)
+impl std::convert::From<&[f32]> for Value {
+    fn from(value: &[f32]) -> Self {
+        let mut values = [0.0; MAX_PLAYERS];
+        values[..value.len()].copy_from_slice(value);
+        Self::new(values, value.len())
     }
 }
@@ -0,0 +1,500 @@
+use game::{wall, GameState, Move, MoveList, RefillUndo, RoundUndo, MAX_PLAYERS, NUM_TILE_COLORS};
+use rand::{rngs::SmallRng, SeedableRng};
+use std::collections::HashMap;
+use std::time::Instant;
+
+/*
+    Exact solver for the end of the game. Once the current round is certain to end the game, there are no chance events left
+    and the rest of the game can be searched completely. Refills are followed only if their outcome is certain (e.g. with a single color left),
+    the search gives up at any other refill, after the node limit or after the deadline.
+
+    Two players: Alpha-beta on the final score difference.
+    More players: Max^n, every player maximizes the difference between their final score and the best final score of the others.
+    Positions with moves are stored in a transposition table keyed on the Zobrist hash of the game state.
+*/
+
+const MAX_TABLE_SIZE: usize = 1 << 19; // The table is cleared when it grows beyond this, about 20 MB
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Bound {
+    Exact,
+    Lower,
+    Upper,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TableEntry {
+    value: i16, // Alpha-beta: The value for the player to move
+    bound: Bound,
+    scores: [i16; MAX_PLAYERS], // Max^n: The final scores
+    best_move: Move,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Solution {
+    pub best_move: Move,
+    pub margin: i16, // Final score of the player to move minus the best final score of the others, with best play of all players
+    pub nodes: u64,
+}
+
+// Difference between the score of the player and the best score of the others
+fn margin(scores: &[i16], player: usize) -> i16 {
+    let best_other = scores
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| *i != player)
+        .map(|(_, score)| *score)
+        .max()
+        .unwrap_or(0);
+    scores[player] - best_other
+}
+
+pub struct EndgameSolver {
+    table: HashMap<u64, TableEntry>,
+    nodes: u64,
+    node_limit: u64,
+    deadline: Option<Instant>,
+    rng: SmallRng,
+}
+
+impl EndgameSolver {
+    pub fn new(node_limit: u64) -> Self {
+        Self {
+            table: HashMap::new(),
+            nodes: 0,
+            node_limit,
+            deadline: None,
+            rng: SmallRng::seed_from_u64(0),
+        }
+    }
+
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    // Forgets the positions of earlier searches, e.g. when a new game starts
+    pub fn clear(&mut self) {
+        self.table = HashMap::new();
+    }
+
+    // The current round ends the game: A full pattern line completes a row of the wall. On the free wall the row has to be
+    // completable by the pending tiling. The bag and the lid being empty also leaves no chance events for the solver.
+    pub fn is_final_round(game_state: &GameState) -> bool {
+        let bag_exhausted = game_state.get_bag().iter().sum::<u8>() == 0
+            && game_state.get_out_of_bag().iter().sum::<u8>() == 0;
+        if bag_exhausted {
+            return true;
+        }
+        let is_free_wall = game_state.get_ruleset().is_free_wall();
+        (0..game_state.get_num_players()).any(|player| {
+            let occupancy = game_state.get_wall_ocupancy()[player];
+            let pattern_lines = game_state.get_pattern_lines_occupancy()[player];
+            let colors = game_state.get_pattern_lines_colors()[player];
+            (0..5).any(|row| {
+                let row_mask = wall::get_row_mask(row);
+                let Some(color) = colors[row] else {
+                    return false;
+                };
+                if pattern_lines[row] as usize != row + 1 {
+                    return false;
+                }
+                if is_free_wall {
+                    (occupancy & row_mask).count_ones() == 4
+                        && game_state.get_wall_tiling_candidates(player, row) > 0
+                } else {
+                    let new_tile = row_mask & wall::WALL_COLOR_MASKS[color as usize];
+                    (occupancy | new_tile) & row_mask == row_mask
+                }
+            })
+        })
+    }
+
+    // Solves the position, None if the search hits a refill with more than one outcome, the node limit or the deadline
+    pub fn solve(&mut self, game_state: &GameState) -> Option<Solution> {
+        self.nodes = 0;
+        let mut game_state = game_state.clone();
+        let player = usize::from(game_state.get_current_player());
+        let margin = if game_state.get_num_players() == 2 {
+            self.alpha_beta(&mut game_state, -i16::MAX, i16::MAX)?
+        } else {
+            let scores = self.max_n(&mut game_state)?;
+            margin(&scores[..game_state.get_num_players()], player)
+        };
+        // The root has moves, otherwise the game would already be over
+        let best_move = self.table.get(&game_state.get_hash())?.best_move;
+        Some(Solution {
+            best_move,
+            margin,
+            nodes: self.nodes,
+        })
+    }
+
+    fn count_node(&mut self) -> Option<()> {
+        self.nodes += 1;
+        if self.nodes > self.node_limit {
+            return None;
+        }
+        if self.nodes.is_multiple_of(4096) {
+            if let Some(deadline) = self.deadline {
+                if Instant::now() >= deadline {
+                    return None;
+                }
+            }
+        }
+        if self.table.len() > MAX_TABLE_SIZE {
+            self.table.clear();
+        }
+        Some(())
+    }
+
+    // Evaluates the round and draws the refill if the factories are empty.
+    // Some(Ok(scores)) if the game is over, Some(Err(undo)) if the round was continued, None if the refill is uncertain.
+    #[allow(clippy::type_complexity)]
+    fn end_round(
+        &mut self,
+        game_state: &mut GameState,
+    ) -> Option<Result<[i16; MAX_PLAYERS], (RoundUndo, RefillUndo)>> {
+        let (is_game_over, round_undo) = game_state.evaluate_round_with_undo();
+        if is_game_over {
+            let mut scores = [0; MAX_PLAYERS];
+            scores[..game_state.get_num_players()].copy_from_slice(game_state.get_scores());
+            game_state.undo_evaluate_round(round_undo);
+            return Some(Ok(scores));
+        }
+        let outcome = game_state.sample_refill(&mut self.rng);
+        let refill_is_certain = outcome.probability > 1. - 1e-9
+            && outcome
+                .factories
+                .iter()
+                .any(|factory| factory.iter().take(NUM_TILE_COLORS).any(|&x| x > 0));
+        if !refill_is_certain {
+            game_state.undo_evaluate_round(round_undo);
+            return None;
+        }
+        let refill_undo = game_state
+            .fill_factories_with(&outcome.factories)
+            .expect("A sampled refill is possible");
+        Some(Err((round_undo, refill_undo)))
+    }
+
+    fn is_round_over(game_state: &GameState) -> bool {
+        game_state.get_factories().is_empty() && game_state.get_pending_wall_tiling().is_none()
+    }
+
+    // Negamax with alpha-beta pruning, the value is the final score of the player to move minus the score of the other player
+    fn alpha_beta(&mut self, game_state: &mut GameState, mut alpha: i16, beta: i16) -> Option<i16> {
+        self.count_node()?;
+        let player = usize::from(game_state.get_current_player());
+
+        if Self::is_round_over(game_state) {
+            return match self.end_round(game_state)? {
+                Ok(scores) => Some(scores[player] - scores[1 - player]),
+                Err((round_undo, refill_undo)) => {
+                    let next_player = usize::from(game_state.get_current_player());
+                    let value = if next_player == player {
+                        self.alpha_beta(game_state, alpha, beta)
+                    } else {
+                        self.alpha_beta(game_state, -beta, -alpha)
+                            .map(|value| -value)
+                    };
+                    game_state.undo_fill_factories(refill_undo);
+                    game_state.undo_evaluate_round(round_undo);
+                    value
+                }
+            };
+        }
+
+        let original_alpha = alpha;
+        let mut table_move = None;
+        if let Some(entry) = self.table.get(&game_state.get_hash()) {
+            let is_cutoff = match entry.bound {
+                Bound::Exact => true,
+                Bound::Lower => entry.value >= beta,
+                Bound::Upper => entry.value <= alpha,
+            };
+            if is_cutoff {
+                return Some(entry.value);
+            }
+            table_move = Some(entry.best_move);
+        }
+
+        let mut move_list = MoveList::default();
+        game_state.get_canonical_moves(&mut move_list, &mut self.rng);
+        // The best move of an earlier search is tried first
+        let moves = table_move.into_iter().chain(
+            move_list
+                .into_iter()
+                .copied()
+                .filter(|m| Some(*m) != table_move),
+        );
+
+        let mut best_value = -i16::MAX;
+        let mut best_move = move_list[0];
+        for mov in moves {
+            let undo = game_state.do_move(mov);
+            let next_player = usize::from(game_state.get_current_player());
+            let value = if next_player == player {
+                self.alpha_beta(game_state, alpha, beta)
+            } else {
+                self.alpha_beta(game_state, -beta, -alpha)
+                    .map(|value| -value)
+            };
+            game_state.undo_move(undo);
+            let value = value?;
+            if value > best_value {
+                best_value = value;
+                best_move = mov;
+            }
+            alpha = alpha.max(value);
+            if alpha >= beta {
+                break;
+            }
+        }
+
+        let bound = if best_value <= original_alpha {
+            Bound::Upper
+        } else if best_value >= beta {
+            Bound::Lower
+        } else {
+            Bound::Exact
+        };
+        self.table.insert(
+            game_state.get_hash(),
+            TableEntry {
+                value: best_value,
+                bound,
+                scores: [0; MAX_PLAYERS],
+                best_move,
+            },
+        );
+        Some(best_value)
+    }
+
+    // Max^n: Returns the final scores if every player maximizes their margin
+    fn max_n(&mut self, game_state: &mut GameState) -> Option<[i16; MAX_PLAYERS]> {
+        self.count_node()?;
+
+        if Self::is_round_over(game_state) {
+            return match self.end_round(game_state)? {
+                Ok(scores) => Some(scores),
+                Err((round_undo, refill_undo)) => {
+                    let scores = self.max_n(game_state);
+                    game_state.undo_fill_factories(refill_undo);
+                    game_state.undo_evaluate_round(round_undo);
+                    scores
+                }
+            };
+        }
+
+        if let Some(entry) = self.table.get(&game_state.get_hash()) {
+            return Some(entry.scores);
+        }
+
+        let player = usize::from(game_state.get_current_player());
+        let num_players = game_state.get_num_players();
+        let mut move_list = MoveList::default();
+        game_state.get_canonical_moves(&mut move_list, &mut self.rng);
+
+        let mut best: Option<([i16; MAX_PLAYERS], Move)> = None;
+        for mov in &move_list {
+            let undo = game_state.do_move(*mov);
+            let scores = self.max_n(game_state);
+            game_state.undo_move(undo);
+            let scores = scores?;
+            let is_better = match best {
+                None => true,
+                Some((best_scores, _)) => {
+                    margin(&scores[..num_players], player)
+                        > margin(&best_scores[..num_players], player)
+                }
+            };
+            if is_better {
+                best = Some((scores, *mov));
+            }
+        }
+
+        let (scores, best_move) = best?;
+        self.table.insert(
+            game_state.get_hash(),
+            TableEntry {
+                value: margin(&scores[..num_players], player),
+                bound: Bound::Exact,
+                scores,
+                best_move,
+            },
+        );
+        Some(scores)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use game::{GameStateBuilder, MoveGenerationResult, Ruleset, TileColor};
+    use rand::Rng;
+
+    // Max^n over all canonical moves in the same order as the solver, without pruning or a table
+    fn brute_force(game_state: &mut GameState, rng: &mut SmallRng) -> [i16; MAX_PLAYERS] {
+        let num_players = game_state.get_num_players();
+        if EndgameSolver::is_round_over(game_state) {
+            let (is_game_over, undo) = game_state.evaluate_round_with_undo();
+            assert!(is_game_over, "The test positions end with the round");
+            let mut scores = [0; MAX_PLAYERS];
+            scores[..num_players].copy_from_slice(game_state.get_scores());
+            game_state.undo_evaluate_round(undo);
+            return scores;
+        }
+        let player = usize::from(game_state.get_current_player());
+        let mut move_list = MoveList::default();
+        game_state.get_canonical_moves(&mut move_list, rng);
+        let mut best: Option<[i16; MAX_PLAYERS]> = None;
+        for mov in &move_list {
+            let undo = game_state.do_move(*mov);
+            let scores = brute_force(game_state, rng);
+            game_state.undo_move(undo);
+            let is_better = match best {
+                None => true,
+                Some(best) => {
+                    margin(&scores[..num_players], player) > margin(&best[..num_players], player)
+                }
+            };
+            if is_better {
+                best = Some(scores);
+            }
+        }
+        best.unwrap()
+    }
+
+    // Player 1 completes the first row of the wall at the end of the round
+    fn final_round(num_players: usize, ruleset: Ruleset) -> GameStateBuilder {
+        GameStateBuilder::new_with_ruleset(num_players, ruleset)
+            .wall_row(0, 0, "BYRG.")
+            .pattern_line(0, 0, TileColor::White, 1)
+            .wall_row(1, 1, "W.Y..")
+            .pattern_line(1, 3, TileColor::Red, 2)
+            .score(0, 12)
+            .score(1, 9)
+    }
+
+    fn test_positions() -> Vec<GameState> {
+        let mut positions = vec![
+            final_round(2, Ruleset::OFFICIAL)
+                .factory(0, "BBYR")
+                .center("W")
+                .build()
+                .unwrap(),
+            final_round(2, Ruleset::default())
+                .center("RRYW")
+                .build()
+                .unwrap(),
+            final_round(3, Ruleset::OFFICIAL)
+                .factory(0, "BRRW")
+                .center("G")
+                .build()
+                .unwrap(),
+        ];
+
+        // Random games until the last round is certain and only a few tiles are left
+        let mut rng = SmallRng::seed_from_u64(0);
+        let mut move_list = MoveList::default();
+        while positions.len() < 12 {
+            let num_players = 2 + positions.len() % 2;
+            let mut game_state =
+                GameState::new_with_ruleset(num_players, Ruleset::OFFICIAL, &mut rng);
+            while game_state.get_possible_moves(&mut move_list, &mut rng)
+                != MoveGenerationResult::GameOver
+            {
+                let tiles_left: u32 = game_state
+                    .get_factories()
+                    .iter()
+                    .flatten()
+                    .map(|&tiles| tiles as u32)
+                    .sum();
+                if EndgameSolver::is_final_round(&game_state) && tiles_left <= 5 {
+                    positions.push(game_state.clone());
+                    break;
+                }
+                game_state.do_move(move_list[rng.gen_range(0..move_list.len())]);
+            }
+        }
+        positions
+    }
+
+    #[test]
+    fn test_solve_matches_brute_force() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        for game_state in test_positions() {
+            assert!(EndgameSolver::is_final_round(&game_state));
+            let num_players = game_state.get_num_players();
+            let player = usize::from(game_state.get_current_player());
+            let scores = brute_force(&mut game_state.clone(), &mut rng);
+
+            let mut solver = EndgameSolver::new(u64::MAX);
+            let solution = solver.solve(&game_state).unwrap();
+            assert_eq!(solution.margin, margin(&scores[..num_players], player));
+
+            // The best move reaches the same margin
+            let mut after_move = game_state.clone();
+            after_move.do_move(solution.best_move);
+            let scores_after_move = brute_force(&mut after_move, &mut rng);
+            assert_eq!(
+                margin(&scores_after_move[..num_players], player),
+                solution.margin
+            );
+        }
+    }
+
+    #[test]
+    fn test_alpha_beta_matches_max_n() {
+        for game_state in test_positions() {
+            if game_state.get_num_players() != 2 {
+                continue;
+            }
+            let player = usize::from(game_state.get_current_player());
+            let mut solver = EndgameSolver::new(u64::MAX);
+            let alpha_beta = solver
+                .alpha_beta(&mut game_state.clone(), -i16::MAX, i16::MAX)
+                .unwrap();
+            solver.clear();
+            let scores = solver.max_n(&mut game_state.clone()).unwrap();
+            assert_eq!(alpha_beta, margin(&scores[..2], player));
+        }
+    }
+
+    #[test]
+    fn test_is_final_round() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        assert!(!EndgameSolver::is_final_round(&GameState::new(2, &mut rng)));
+
+        // The row is only complete once the pattern line is full
+        let almost = GameStateBuilder::new(2)
+            .wall_row(0, 0, "BYRG.")
+            .pattern_line(0, 1, TileColor::White, 1)
+            .factory(0, "BBYR")
+            .build()
+            .unwrap();
+        assert!(!EndgameSolver::is_final_round(&almost));
+        let complete = final_round(2, Ruleset::OFFICIAL)
+            .factory(0, "BBYR")
+            .build()
+            .unwrap();
+        assert!(EndgameSolver::is_final_round(&complete));
+
+        // On the free wall the tile has to fit into the missing column
+        let free_wall = GameStateBuilder::new_with_ruleset(2, Ruleset::OFFICIAL_FREE_WALL)
+            .wall_row(0, 0, "BYRG.")
+            .factory(0, "BBYR");
+        let fits = free_wall
+            .clone()
+            .pattern_line(0, 0, TileColor::White, 1)
+            .build()
+            .unwrap();
+        assert!(EndgameSolver::is_final_round(&fits));
+        let blocked = free_wall
+            .wall_row(0, 1, "....W")
+            .pattern_line(0, 0, TileColor::White, 1)
+            .build()
+            .unwrap();
+        assert!(!EndgameSolver::is_final_round(&blocked));
+    }
+}
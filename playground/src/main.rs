@@ -11,19 +11,19 @@ use rand::{rngs::SmallRng, SeedableRng};
 #[tokio::main]
 async fn main() {
     let mut rng = SmallRng::from_entropy();
-    let game_state = GameState::new(&mut rng);
+    let game_state = GameState::new(2, &mut rng);
 
     // loop {
     //     let mut players: Vec<Box<dyn Player>> = Vec::new();
-    //     for _ in 0..NUM_PLAYERS {
+    //     for _ in 0..2 {
     //         let mut player = MonteCarloTreeSearch::default();
     //         player.set_time(250).await;
     //         players.push(Box::new(player));
     //     }
     //     // players.push(Box::<RandomPlayer>::default());
 
-    //     let game_state = GameState::new(&mut rng);
-    //     let stats = run_match(game_state, &mut players, true).await.unwrap();
+    //     let game_state = GameState::new_without_factories(2, Ruleset::default());
+    //     let stats = run_match(game_state, &mut players, rng.gen(), true).await.unwrap();
     //     let scores = stats
     //         .player_statistics
     //         .iter()
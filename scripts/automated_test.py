@@ -12,15 +12,8 @@ class TestStatus(enum.Enum):
     COMPILE_ERROR = 1
     SERVER_ERROR = 2
 
-def feature(num_players: int):
-    if num_players == 3:
-        return ["--features", "three_players"]
-    elif num_players == 4:
-        return ["--features", "four_players"]
-    return []
-
-def build_executable(binary: str, num_players: int) -> TestStatus:
-    command = ["cargo", "build", "--bin", binary, "--release"] + feature(num_players)
+def build_executable(binary: str) -> TestStatus:
+    command = ["cargo", "build", "--bin", binary, "--release"]
     print(f"Building {binary}... ({' '.join(command)})")
     result = subprocess.run(command, capture_output=True, text=True)
 
@@ -35,10 +28,10 @@ def build_executable(binary: str, num_players: int) -> TestStatus:
     print("Build successful")
     return TestStatus.OK
 
-def run_test_server(num_players):
-    build_executable("test_server", num_players)
+def run_test_server():
+    build_executable("test_server")
 
-    command = ["cargo", "run", "--bin", "test_server", "--release"] + feature(num_players)
+    command = ["cargo", "run", "--bin", "test_server", "--release"]
     print(f"Starting test server... ({' '.join(command)})")
     # Start the test server and yield each line of output
     with subprocess.Popen(command, stdout=subprocess.PIPE, stderr=subprocess.STDOUT, text=True, bufsize=1, universal_newlines=True) as proc:
@@ -205,7 +198,7 @@ class Test:
 
     def run(self):
         self.game_config.activate()
-        build_executable("test_client", len(self.game_config.players))
+        build_executable("test_client")
         game_result_stream = self.run_games()
         if self.game_config.stop_on_significant_difference:
             run_hypothesis_tests_for_players(game_result_stream, len(self.game_config.players), self.game_config.num_simulations_games * 2)
@@ -216,7 +209,7 @@ class Test:
     def run_games(self):
         num_players = len(game_config.players)
         log_lines = []
-        for line in run_test_server(num_players):
+        for line in run_test_server():
             log_lines.append(line)
             results = parse_game_results(log_lines[-4:])
             if len(results.scores) == num_players:
@@ -23,9 +23,14 @@ async fn main() {
                 println!("move_response {}", move_.serialize_string());
             }
             "notify_move" => {
-                let game_state = GameState::deserialize_string(entries.get(1).unwrap()).unwrap();
-                let move_ = Move::deserialize_string(entries.get(2).unwrap());
-                player.notify_move(&game_state, move_).await;
+                let game_state = GameState::deserialize_string(entries.get(1).unwrap());
+                let move_ = Move::deserialize_string(entries.get(2).unwrap_or(&""));
+                match (game_state, move_) {
+                    (Ok(game_state), Ok(move_)) => player.notify_move(&game_state, move_).await,
+                    // The notification only helps to keep the search tree, it can be skipped
+                    (Err(e), _) => println!("Ignoring notify_move with invalid game state: {}", e),
+                    (_, Err(e)) => println!("Ignoring notify_move with invalid move: {}", e),
+                }
             }
             "reset" => {
                 player = Player::default();
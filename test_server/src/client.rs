@@ -84,7 +84,11 @@ impl Player for Client {
             line.truncate(0);
         }
         line.pop();
-        Move::deserialize_string(&line)
+        // A malformed answer is treated like an illegal move, run_match ends the game with an error
+        Move::deserialize_string(&line).unwrap_or_else(|e| {
+            log::error!("{}: Could not parse move '{}': {}", self.path, line, e);
+            Move::DUMMY
+        })
     }
 
     async fn notify_move(&mut self, new_game_state: &GameState, move_: Move) {
@@ -2,7 +2,6 @@ use std::vec;
 
 use clap::Parser;
 use config::{Config, File, FileFormat};
-use rand::{rngs::SmallRng, SeedableRng};
 use serde::Deserialize;
 
 mod client;
@@ -10,8 +9,9 @@ use async_mutex::MutexGuard;
 use client::Client;
 use game::{
     init_logging,
-    match_::{self, MatchStatistcs},
-    GameError, GameState, Player, SharedState, NUM_PLAYERS,
+    match_::{self, ConsoleObserver, MatchDriver, MatchObserver, MatchOutcome, MatchStatistcs},
+    GameError, GameState, Player, Ruleset, SharedState, TimeControl, WallVariant, MAX_PLAYERS,
+    MIN_PLAYERS,
 };
 
 #[derive(Parser, Debug)]
@@ -28,6 +28,12 @@ struct GameConfig {
     pub num_simultaneous_games: u64,
     pub verbose: bool,
     pub constant_ordering: bool,
+    #[serde(default)]
+    pub official_rules: bool,
+    #[serde(default)]
+    pub free_wall: bool,
+    #[serde(default)]
+    pub time_control: Option<String>, // e.g. "1000/move" or "60000+500", see TimeControl
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -46,15 +52,32 @@ struct AppConfig {
 }
 
 async fn run_match(
+    driver: &MatchDriver,
     players: &mut [Box<dyn Player>],
+    ruleset: Ruleset,
     verbose: bool,
 ) -> Result<MatchStatistcs, GameError> {
-    match_::run_match(
-        GameState::new(&mut SmallRng::from_entropy()),
-        players,
-        verbose,
-    )
-    .await
+    // The game only depends on the seed and the moves of the clients, it is logged to reproduce failed games
+    let seed = rand::random::<u64>();
+    log::debug!("Starting game with seed {}", seed);
+    let mut console_observer = ConsoleObserver::default();
+    let observers: &mut [&mut dyn MatchObserver] = if verbose {
+        &mut [&mut console_observer]
+    } else {
+        &mut []
+    };
+    let result = driver
+        .run(
+            GameState::new_without_factories(players.len(), ruleset),
+            players,
+            seed,
+            observers,
+        )
+        .await;
+    if result.is_err() {
+        log::error!("The failed game was played with seed {}", seed);
+    }
+    result
 }
 
 #[tokio::main]
@@ -86,18 +109,18 @@ async fn main() {
         players.push(player);
     }
 
-    if players.len() != NUM_PLAYERS {
+    let num_players = players.len();
+    if !(MIN_PLAYERS..=MAX_PLAYERS).contains(&num_players) {
         panic!(
-            "Invalid number of players. Expected {}, got {}",
-            NUM_PLAYERS,
-            players.len()
+            "Invalid number of players. Expected between {} and {}, got {}",
+            MIN_PLAYERS, MAX_PLAYERS, num_players
         );
     }
 
     let player_combinations = if app_config.game.constant_ordering {
-        constant_player_ordering(NUM_PLAYERS)
+        constant_player_ordering(num_players)
     } else {
-        rotating_player_ordering(NUM_PLAYERS)
+        rotating_player_ordering(num_players)
     };
     // Set num_games to a multiple of the number of player combinations
     let remainder = app_config.game.num_games % player_combinations.len() as u64;
@@ -114,12 +137,39 @@ async fn main() {
     let game_results: SharedState<Vec<MatchStatistcs>> = SharedState::new(Vec::new());
 
     let verbose = app_config.game.verbose;
+    let mut ruleset = if app_config.game.official_rules {
+        Ruleset::OFFICIAL
+    } else {
+        Ruleset::default()
+    };
+    if app_config.game.free_wall {
+        ruleset.wall_variant = WallVariant::Free;
+    }
+    log::info!("Ruleset: {}", ruleset);
+    let time_control: TimeControl = match &app_config.game.time_control {
+        Some(time_control) => time_control
+            .parse()
+            .expect("Configuration file format error"),
+        None => TimeControl::Unlimited,
+    };
+    log::info!("Time control: {}", time_control);
+    let driver = MatchDriver::new(time_control);
+
+    // Ctrl-C stops the running games, the statistics of the finished games have already been printed
+    let cancel_token = driver.cancel_token();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            log::info!("Stopping the running games");
+            cancel_token.cancel();
+        }
+    });
 
     let mut handles = Vec::new();
     for _ in 0..app_config.game.num_simultaneous_games {
         let game_queue_clone = game_queue.clone();
         let game_results_clone = game_results.clone();
         let players_clone = players.clone();
+        let driver = driver.clone();
 
         let handle = tokio::spawn(async move {
             loop {
@@ -140,9 +190,10 @@ async fn main() {
                     ordered_clients.push(Box::new(client));
                 }
 
-                let stats = run_match(&mut ordered_clients, verbose).await;
+                let stats = run_match(&driver, &mut ordered_clients, ruleset, verbose).await;
                 let mut stats = match stats {
                     Ok(stats) => stats,
+                    Err(GameError::Cancelled) => break,
                     Err(e) => {
                         log::error!("Game ended with an error: {:?}", e);
                         continue;
@@ -151,11 +202,22 @@ async fn main() {
 
                 // Reordering player_statistics to match the original order
                 let mut reordered_stats: Vec<match_::PlayerStatistics> =
-                    vec![match_::PlayerStatistics::default(); NUM_PLAYERS];
+                    vec![match_::PlayerStatistics::default(); next_order.len()];
+                let mut order = vec![0; next_order.len()];
                 for (index, &original_index) in next_order.iter().enumerate() {
                     reordered_stats[original_index - 1] = stats.player_statistics[index].clone();
+                    order[original_index - 1] = index;
+                }
+                stats.player_statistics = reordered_stats;
+                stats.result = stats.result.reordered(&order);
+                if let Some(forfeit) = &mut stats.forfeit {
+                    forfeit.player = next_order[forfeit.player] - 1;
+                    log::warn!(
+                        "Player {} forfeited: {}",
+                        forfeit.player + 1,
+                        forfeit.reason
+                    );
                 }
-                stats.player_statistics = reordered_stats.try_into().expect("Incorrect length");
                 let mut game_results_lock = game_results_clone.lock().await;
                 game_results_lock.push(stats);
 
@@ -187,45 +249,23 @@ fn print_stats(game_results_lock: MutexGuard<Vec<MatchStatistcs>>) {
         .sum::<u32>() as f32
         / total_games as f32;
 
-    let mut avg_scores = vec![0f32; NUM_PLAYERS];
-    let mut wins = [0; NUM_PLAYERS];
-    let mut draws = [0; NUM_PLAYERS];
-    let mut losses = [0; NUM_PLAYERS];
+    let num_players = game_results_lock
+        .first()
+        .map_or(0, |stats| stats.player_statistics.len());
+    let mut avg_scores = vec![0f32; num_players];
+    let mut wins = vec![0; num_players];
+    let mut draws = vec![0; num_players];
+    let mut losses = vec![0; num_players];
 
     for stats in game_results_lock.iter() {
         for (i, player_stats) in stats.player_statistics.iter().enumerate() {
             avg_scores[i] += player_stats.final_score as f32;
 
-            // determine wins, draws, losses
-            // Update wins[i], draws[i], losses[i] accordingly
-            let mut max_score = 0;
-            let mut max_score_count = 0;
-            for (j, other_player_stats) in stats.player_statistics.iter().enumerate() {
-                if i == j {
-                    continue;
-                }
-                match other_player_stats.final_score.cmp(&max_score) {
-                    std::cmp::Ordering::Greater => {
-                        max_score = other_player_stats.final_score;
-                        max_score_count = 1;
-                    }
-                    std::cmp::Ordering::Equal => {
-                        max_score_count += 1;
-                    }
-                    _ => {}
-                }
-            }
-
-            match player_stats.final_score.cmp(&max_score) {
-                std::cmp::Ordering::Greater => wins[i] += 1,
-                std::cmp::Ordering::Equal => {
-                    if max_score_count == 1 {
-                        draws[i] += 1;
-                    } else {
-                        losses[i] += 1;
-                    }
-                }
-                std::cmp::Ordering::Less => losses[i] += 1,
+            // The official tie-break decides between players with the same score
+            match stats.result.outcome(i) {
+                MatchOutcome::Win => wins[i] += 1,
+                MatchOutcome::Draw => draws[i] += 1,
+                MatchOutcome::Loss => losses[i] += 1,
             }
         }
     }
@@ -263,7 +303,7 @@ fn print_stats(game_results_lock: MutexGuard<Vec<MatchStatistcs>>) {
     log::info!("Total games: {}", total_games);
     log::debug!("Average executed moves per game: {}", avg_moves);
     log::debug!("Average factory refills per game: {}", avg_refills);
-    for i in 0..NUM_PLAYERS {
+    for i in 0..num_players {
         log::debug!(
             "Player {} - Average score: {}, Wins: {}, Draws: {}, Losses: {}",
             i + 1,